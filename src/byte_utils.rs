@@ -4,6 +4,185 @@ use anyhow::Context;
 
 use crate::common::{R, err};
 
+/// A little-endian value with a fixed on-disk layout.
+/// Structs implement it through [bin_layout], which declares the fields once
+/// for both [BinRead] and [BinWrite].
+pub trait BinRead: Sized {
+    /// Size in bytes of the on-disk representation.
+    const SIZE: usize;
+
+    fn read_from(cursor: &mut ByteCursor) -> R<Self>;
+
+    fn from_bytes(bytes: &[u8]) -> R<Self> {
+        ByteCursor::new(bytes).read()
+    }
+
+    /// Reads `bytes` as a tightly packed array of records.
+    fn read_all(bytes: &[u8]) -> R<Vec<Self>> {
+        if !bytes.len().is_multiple_of(Self::SIZE) {
+            return err(format!(
+                "ByteUtils: {} bytes can't be split into records of {} bytes",
+                bytes.len(),
+                Self::SIZE
+            ));
+        }
+        let mut cursor = ByteCursor::new(bytes);
+        (0..bytes.len() / Self::SIZE)
+            .map(|_| cursor.read())
+            .collect()
+    }
+}
+
+pub trait BinWrite {
+    fn write_to(&self, out: &mut Vec<u8>);
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.write_to(&mut out);
+        out
+    }
+}
+
+impl<T: BinWrite> BinWrite for [T] {
+    fn write_to(&self, out: &mut Vec<u8>) {
+        for item in self {
+            item.write_to(out);
+        }
+    }
+}
+
+/// Reads [BinRead] values one after another, keeping track of the offset.
+pub struct ByteCursor<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> ByteCursor<'a> {
+    pub fn new(bytes: &'a [u8]) -> ByteCursor<'a> {
+        ByteCursor { bytes, offset: 0 }
+    }
+
+    pub fn at(bytes: &'a [u8], offset: usize) -> ByteCursor<'a> {
+        ByteCursor { bytes, offset }
+    }
+
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.bytes.len().saturating_sub(self.offset)
+    }
+
+    pub fn read<T: BinRead>(&mut self) -> R<T> {
+        T::read_from(self)
+    }
+
+    pub fn read_bytes(&mut self, length: usize) -> R<&'a [u8]> {
+        let end = self.offset + length;
+        if end > self.bytes.len() {
+            let msg = format!("ByteUtils: Slice out of range! [{}..{}] but slice length is {}",
+                              self.offset,
+                              end,
+                              self.bytes.len()
+            );
+            return err(msg);
+        }
+        let slice = &self.bytes[self.offset..end];
+        self.offset = end;
+        Ok(slice)
+    }
+
+    pub fn skip(&mut self, length: usize) -> R<()> {
+        self.read_bytes(length).map(|_| ())
+    }
+}
+
+macro_rules! impl_bin_primitive {
+    ($($primitive:ty),*) => {
+        $(
+            impl BinRead for $primitive {
+                const SIZE: usize = std::mem::size_of::<$primitive>();
+
+                fn read_from(cursor: &mut ByteCursor) -> R<Self> {
+                    let bytes = cursor.read_bytes(Self::SIZE)?;
+                    Ok(<$primitive>::from_le_bytes(bytes.try_into()?))
+                }
+            }
+
+            impl BinWrite for $primitive {
+                fn write_to(&self, out: &mut Vec<u8>) {
+                    out.extend_from_slice(&self.to_le_bytes());
+                }
+            }
+        )*
+    };
+}
+
+impl_bin_primitive!(u8, i8, u16, i16, u32, i32, u64, i64, f32);
+
+impl<T: BinRead, const N: usize> BinRead for [T; N] {
+    const SIZE: usize = T::SIZE * N;
+
+    fn read_from(cursor: &mut ByteCursor) -> R<Self> {
+        let items = (0..N)
+            .map(|_| cursor.read())
+            .collect::<R<Vec<T>>>()?;
+        items.try_into()
+            .map_err(|_| anyhow::anyhow!("ByteUtils: Failed to read an array of {} elements", N))
+    }
+}
+
+impl<T: BinWrite, const N: usize> BinWrite for [T; N] {
+    fn write_to(&self, out: &mut Vec<u8>) {
+        for item in self {
+            item.write_to(out);
+        }
+    }
+}
+
+/// Declares a struct together with its on-disk layout, fields are read and written in declaration order.
+///
+/// ```ignore
+/// bin_layout! {
+///     #[derive(Debug)]
+///     pub struct ChunkMcin {
+///         pub offs_mcnk: u32,
+///         pub size: u32,
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! bin_layout {
+    (
+        $(#[$struct_meta:meta])*
+        $vis:vis struct $name:ident {
+            $($(#[$field_meta:meta])* $field_vis:vis $field:ident : $field_type:ty),* $(,)?
+        }
+    ) => {
+        $(#[$struct_meta])*
+        $vis struct $name {
+            $($(#[$field_meta])* $field_vis $field: $field_type),*
+        }
+
+        impl $crate::byte_utils::BinRead for $name {
+            const SIZE: usize = 0 $(+ <$field_type as $crate::byte_utils::BinRead>::SIZE)*;
+
+            fn read_from(cursor: &mut $crate::byte_utils::ByteCursor) -> $crate::common::R<Self> {
+                Ok($name {
+                    $($field: cursor.read()?),*
+                })
+            }
+        }
+
+        impl $crate::byte_utils::BinWrite for $name {
+            fn write_to(&self, out: &mut Vec<u8>) {
+                $($crate::byte_utils::BinWrite::write_to(&self.$field, out);)*
+            }
+        }
+    };
+}
+
 pub trait VecUtils {
     fn get_reversed_string(&self, from: usize, to: usize) -> R<String>;
     fn get_string(&self, from: usize, to: usize) -> R<String>;
@@ -18,9 +197,11 @@ pub trait VecUtils {
     fn get_null_terminated_strings(&self) -> R<Vec<String>>;
 }
 
-impl VecUtils for Vec<u8> {
+impl VecUtils for [u8] {
     fn get_reversed_string(&self, from: usize, to: usize) -> R<String> {
-        let bytes = &self[from..from + to];
+        let bytes = from.checked_add(to)
+            .and_then(|end| self.get(from..end))
+            .with_context(|| format!("String at [{}..+{}] is out of range, slice length is {}", from, to, self.len()))?;
         let parsed: String = std::str::from_utf8(bytes)?
             .chars()
             .rev()
@@ -29,7 +210,9 @@ impl VecUtils for Vec<u8> {
     }
 
     fn get_string(&self, offset: usize, length: usize) -> R<String> {
-        let bytes = &self[offset..offset + length];
+        let bytes = offset.checked_add(length)
+            .and_then(|end| self.get(offset..end))
+            .with_context(|| format!("String at [{}..+{}] is out of range, slice length is {}", offset, length, self.len()))?;
         let parsed: String = std::str::from_utf8(bytes)?
             .chars()
             .collect::<String>();
@@ -110,4 +293,56 @@ impl VecUtils for Vec<u8> {
         }
         Ok(acc)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+#[test]
+fn bin_layout_round_trip() {
+    bin_layout! {
+        #[derive(Debug, PartialEq)]
+        struct TestInner {
+            flags: u16,
+            position: [f32; 3],
+        }
+    }
+
+    bin_layout! {
+        #[derive(Debug, PartialEq)]
+        struct TestOuter {
+            id: u32,
+            inner: [TestInner; 2],
+            signed: i8,
+        }
+    }
+
+    assert_eq!(TestInner::SIZE, 14);
+    assert_eq!(TestOuter::SIZE, 33);
+
+    let mut bytes = 7u32.to_le_bytes().to_vec();
+    for flags in &[1u16, 2u16] {
+        bytes.extend_from_slice(&flags.to_le_bytes());
+        for coord in &[1.0f32, -2.5, 3.25] {
+            bytes.extend_from_slice(&coord.to_le_bytes());
+        }
+    }
+    bytes.push(0xFF);
+
+    let outer = TestOuter::from_bytes(&bytes).unwrap();
+    assert_eq!(outer.id, 7);
+    assert_eq!(outer.inner[1].flags, 2);
+    assert_eq!(outer.inner[0].position, [1.0, -2.5, 3.25]);
+    assert_eq!(outer.signed, -1);
+    assert_eq!(outer.to_bytes(), bytes);
+
+    assert!(TestOuter::from_bytes(&bytes[..32]).is_err());
+}
+
+#[cfg(test)]
+#[test]
+fn out_of_range_strings_are_errors() {
+    let bytes = b"abcd";
+    assert_eq!(bytes.get_string(1, 2).unwrap(), "bc");
+    assert_eq!(bytes.get_reversed_string(0, 4).unwrap(), "dcba");
+    assert!(bytes.get_string(2, 3).is_err());
+    assert!(bytes.get_reversed_string(usize::MAX, 2).is_err());
+}
@@ -0,0 +1,120 @@
+use crate::command_handler::alpha_map::{export_alpha, import_alpha};
+use crate::command_handler::area::set_area;
+use crate::command_handler::convert_split::convert_split_adts;
+use crate::command_handler::heightmap::{export_heightmap, import_heightmap};
+use crate::command_handler::merge::merge_adts;
+use crate::command_handler::placements::{apply_placements, list_placements};
+use crate::command_handler::relocate::relocate_adt;
+use crate::command_handler::terrain_mesh::export_terrain_mesh;
+use crate::command_handler::validate::validate_adts;
+use crate::command_handler::water::set_water;
+use crate::formats::adt::{tile_coords_from_path, TileCoords};
+use crate::formats::wdt::WdtFile;
+use crate::{common::{err, R}, AdtToolCmd};
+use std::path::{Path, PathBuf};
+
+pub fn handle_adt_command(cmd: &AdtToolCmd) -> R<Box<dyn erased_serde::Serialize>> {
+    let res: Box<dyn erased_serde::Serialize> = match cmd {
+        AdtToolCmd::ExportHeightmap(cmd) => Box::new(export_heightmap(&cmd.file, &cmd.output)?),
+        AdtToolCmd::ImportHeightmap(cmd) => Box::new(import_heightmap(
+            &cmd.input,
+            &cmd.files,
+            cmd.base_height,
+            cmd.height_scale,
+            cmd.output_dir.as_deref(),
+        )?),
+        AdtToolCmd::ExportAlpha(cmd) => Box::new(export_alpha(&cmd.file, &cmd.output_dir)?),
+        AdtToolCmd::ImportAlpha(cmd) => Box::new(import_alpha(
+            &cmd.file,
+            &cmd.masks,
+            cmd.alpha_format,
+            cmd.wdt.as_deref(),
+            cmd.output.as_deref(),
+        )?),
+        AdtToolCmd::Placements(cmd) => Box::new(list_placements(&cmd.file, cmd.output.as_deref())?),
+        AdtToolCmd::ApplyPlacements(cmd) => Box::new(apply_placements(
+            &cmd.file,
+            &cmd.input,
+            cmd.workspace.as_deref(),
+            cmd.first_unique_id,
+            cmd.output.as_deref(),
+        )?),
+        AdtToolCmd::ExportMesh(cmd) => Box::new(export_terrain_mesh(
+            &cmd.file,
+            &cmd.output,
+            cmd.min_tile,
+            cmd.max_tile,
+            cmd.water,
+        )?),
+        AdtToolCmd::SetWater(cmd) => Box::new(set_water(
+            &cmd.file,
+            cmd.chunks,
+            cmd.mask.as_deref(),
+            cmd.liquid_type,
+            cmd.height,
+            cmd.clear,
+            cmd.output.as_deref(),
+        )?),
+        AdtToolCmd::SetArea(cmd) => Box::new(set_area(
+            &cmd.file,
+            &cmd.area_table,
+            cmd.area_id,
+            cmd.chunks,
+            cmd.mask.as_deref(),
+            &cmd.colors,
+            cmd.min_tile,
+            cmd.max_tile,
+            cmd.output.as_deref(),
+        )?),
+        AdtToolCmd::Merge(cmd) => Box::new(merge_adts(
+            &cmd.terrain,
+            &cmd.objects,
+            cmd.first_unique_id,
+            &cmd.output,
+        )?),
+        AdtToolCmd::Validate(cmd) => Box::new(validate_adts(&cmd.file)?),
+        AdtToolCmd::ConvertSplit(cmd) => Box::new(convert_split_adts(&cmd.file, &cmd.output)?),
+        AdtToolCmd::Relocate(cmd) => Box::new(relocate_adt(&cmd.file, cmd.from, cmd.to, cmd.move_tile)?),
+    };
+    Ok(res)
+}
+
+/// The tiles to work on as `(x, y, path)`, a single ADT whose name carries no
+/// coordinates is placed at `(0, 0)`.
+pub(crate) fn get_adt_tiles(file: &Path) -> R<Vec<(u32, u32, PathBuf)>> {
+    if file_has_extension(file, "wdt") {
+        let tiles = WdtFile::from_path(file)?.adt_paths(file)?;
+        if tiles.is_empty() {
+            return err(format!("WDT {:?} has no ADT tiles", file.display()));
+        }
+        Ok(tiles)
+    } else {
+        let (x, y) = tile_coords_from_path(file).unwrap_or((0, 0));
+        Ok(vec![(x, y, file.to_path_buf())])
+    }
+}
+
+/// Like [get_adt_tiles], limited to the tiles between `min_tile` and `max_tile`.
+pub(crate) fn get_adt_tiles_in_range(
+    file: &Path,
+    min_tile: Option<TileCoords>,
+    max_tile: Option<TileCoords>,
+) -> R<Vec<(u32, u32, PathBuf)>> {
+    let min = min_tile.unwrap_or(TileCoords { x: 0, y: 0 });
+    let max = max_tile.unwrap_or(TileCoords { x: 63, y: 63 });
+    let tiles: Vec<(u32, u32, PathBuf)> = get_adt_tiles(file)?
+        .into_iter()
+        .filter(|(x, y, _)| (min.x..=max.x).contains(x) && (min.y..=max.y).contains(y))
+        .collect();
+    if tiles.is_empty() {
+        return err("No ADT tile in the given tile range".to_string());
+    }
+    Ok(tiles)
+}
+
+pub(crate) fn file_has_extension(file: &Path, extension: &str) -> bool {
+    file.extension()
+        .and_then(|it| it.to_str())
+        .map(|it| it.eq_ignore_ascii_case(extension))
+        == Some(true)
+}
@@ -0,0 +1,286 @@
+use crate::command_handler::adt::{file_has_extension, get_adt_tiles};
+use crate::common::{err, R};
+use crate::formats::adt::AdtFile;
+use crate::formats::alpha_map::{AlphaMapFormat, ALPHA_MAP_SIZE};
+use crate::formats::chunk::ChunkMclyItem;
+use crate::formats::wdt::WdtFile;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+/// Side of the alpha image of a whole tile.
+pub const TILE_ALPHA_SIZE: usize = 16 * ALPHA_MAP_SIZE;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportAlphaCmdResult {
+    pub tiles: Vec<ExportAlphaTile>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportAlphaTile {
+    pub adt: PathBuf,
+    pub layers: Vec<ExportAlphaLayer>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportAlphaLayer {
+    /// Index into MTEX.
+    pub texture_id: u32,
+    pub texture: String,
+    pub image: PathBuf,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ImportAlphaCmdResult {
+    pub written: PathBuf,
+    pub alpha_format: AlphaMapFormat,
+    pub added_textures: Vec<String>,
+    /// MCNKs painted with more than 4 textures, only the 4 most used ones were kept.
+    pub truncated_chunks: Vec<(u32, u32)>,
+    pub warnings: Vec<String>,
+}
+
+/// Writes one grayscale image per texture per tile, holding the weight of that texture everywhere
+/// on the tile. The base layer weighs whatever the alpha mapped layers above it leave.
+pub fn export_alpha(file: &str, output_dir: &str) -> R<ExportAlphaCmdResult> {
+    let file = Path::new(file);
+    let big_alpha = if file_has_extension(file, "wdt") {
+        Some(WdtFile::from_path(file)?.mphd.has_big_alpha())
+    } else {
+        None
+    };
+    let output_dir = Path::new(output_dir);
+    std::fs::create_dir_all(output_dir)
+        .with_context(|| format!("Failed to create {:?}", output_dir.display()))?;
+
+    let mut tiles = vec![];
+    for (_, _, path) in get_adt_tiles(file)? {
+        let adt = AdtFile::from_path(&path)?;
+        let weights = get_texture_weights(&adt, big_alpha)
+            .with_context(|| format!("Failed to decode the alpha maps of {:?}", path.display()))?;
+        let tile_name = path.file_stem().and_then(|it| it.to_str()).unwrap_or("tile");
+
+        let mut layers = vec![];
+        for (texture_id, weights) in weights {
+            let texture = adt.mtex.0.get(texture_id as usize).cloned().unwrap_or_default();
+            let texture_name = Path::new(&texture.replace('\\', "/"))
+                .file_stem()
+                .and_then(|it| it.to_str())
+                .unwrap_or("missing")
+                .to_string();
+            let image = output_dir.join(format!("{}_{}_{}.png", tile_name, texture_id, texture_name));
+            write_grayscale_png(&image, TILE_ALPHA_SIZE, &weights)?;
+            layers.push(ExportAlphaLayer {
+                texture_id,
+                texture,
+                image,
+            });
+        }
+        tiles.push(ExportAlphaTile {
+            adt: path,
+            layers,
+        });
+    }
+
+    Ok(ExportAlphaCmdResult { tiles })
+}
+
+/// Repaints the tile from `<texture>=<png>` masks, each one tile sized like the ones [export_alpha]
+/// writes. Textures without a mask keep their current weights, every MCNK then keeps its 4 most
+/// used textures, the most used one becoming the base layer. The alpha format has to match the
+/// big alpha flag of the `wdt` MPHD, it is only warned about when no WDT is given.
+pub fn import_alpha(
+    file: &str,
+    masks: &[String],
+    alpha_format: Option<AlphaMapFormat>,
+    wdt: Option<&str>,
+    output: Option<&str>,
+) -> R<ImportAlphaCmdResult> {
+    let path = Path::new(file);
+    let mut adt = AdtFile::from_path(path)?;
+    let big_alpha = wdt.map(|it| WdtFile::from_path(it).map(|it| it.mphd.has_big_alpha())).transpose()?;
+    let mut warnings = vec![];
+    let alpha_format = match alpha_format {
+        Some(alpha_format) => alpha_format,
+        None => match (detect_alpha_format(&adt, big_alpha)?, big_alpha) {
+            (detected, Some(big_alpha)) if detected.needs_big_alpha() != big_alpha => {
+                if big_alpha { AlphaMapFormat::Uncompressed8Bit } else { AlphaMapFormat::Uncompressed4Bit }
+            }
+            (detected, _) => detected,
+        },
+    };
+    match big_alpha {
+        Some(big_alpha) if alpha_format.needs_big_alpha() != big_alpha => {
+            return err(format!(
+                "Alpha format {:?} doesn't match the WDT, which {} big alpha",
+                alpha_format,
+                if big_alpha { "has" } else { "doesn't have" }
+            ));
+        }
+        Some(_) => {}
+        None => warnings.push(format!(
+            "Alpha format {:?} was not checked against the WDT MPHD flags, give --wdt to check it",
+            alpha_format
+        )),
+    }
+    let mut weights = get_texture_weights(&adt, big_alpha)
+        .with_context(|| format!("Failed to decode the alpha maps of {:?}", path.display()))?;
+
+    let mut added_textures = vec![];
+    for mask in masks {
+        let (texture, image) = match mask.find('=') {
+            Some(i) => (mask[..i].replace('/', "\\"), Path::new(&mask[i + 1..])),
+            None => return err(format!("Invalid mask {:?}, expected <texture>=<png>", mask)),
+        };
+        let pixels = read_grayscale_png(image, TILE_ALPHA_SIZE)?;
+        let texture_id = match adt.mtex.0.iter().position(|it| it.eq_ignore_ascii_case(&texture)) {
+            Some(texture_id) => texture_id,
+            None => {
+                adt.mtex.0.push(texture.clone());
+                added_textures.push(texture);
+                adt.mtex.0.len() - 1
+            }
+        };
+        weights.insert(texture_id as u32, pixels);
+    }
+
+    let mut truncated_chunks = vec![];
+    for mcnk in &mut adt.mcnk.items {
+        let chunk_x = mcnk.header.index_x as usize % 16 * ALPHA_MAP_SIZE;
+        let chunk_y = mcnk.header.index_y as usize % 16 * ALPHA_MAP_SIZE;
+        let chunk_pixels = |i: usize| (chunk_y + i / ALPHA_MAP_SIZE) * TILE_ALPHA_SIZE + chunk_x + i % ALPHA_MAP_SIZE;
+
+        let mut used: Vec<(u32, u32)> = weights.iter()
+            .map(|(texture_id, image)| {
+                let total = (0..ALPHA_MAP_SIZE * ALPHA_MAP_SIZE).map(|i| image[chunk_pixels(i)] as u32).sum();
+                (*texture_id, total)
+            })
+            .filter(|(_, total)| *total > 0)
+            .collect();
+        if used.is_empty() {
+            continue;
+        }
+        used.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        if used.len() > 4 {
+            truncated_chunks.push((mcnk.header.index_x, mcnk.header.index_y));
+            used.truncate(4);
+        }
+
+        let chosen: Vec<&Vec<u8>> = used.iter().map(|(texture_id, _)| &weights[texture_id]).collect();
+        let layers = used.iter()
+            .enumerate()
+            .map(|(layer, (texture_id, _))| {
+                let item = mcnk.mcly.0.iter()
+                    .find(|it| it.texture_id == *texture_id)
+                    .cloned()
+                    .unwrap_or(ChunkMclyItem {
+                        texture_id: *texture_id,
+                        flags: 0,
+                        offset_in_mcal: 0,
+                        effect_id: 0,
+                    });
+                if layer == 0 {
+                    return (item, None);
+                }
+                // weights are normalized so the chosen layers add up to a full weight.
+                let alpha_map = (0..ALPHA_MAP_SIZE * ALPHA_MAP_SIZE)
+                    .map(|i| {
+                        let pixel = chunk_pixels(i);
+                        let total: u32 = chosen.iter().map(|it| it[pixel] as u32).sum();
+                        (chosen[layer][pixel] as u32 * 255 + total / 2).checked_div(total).unwrap_or(0) as u8
+                    })
+                    .collect();
+                (item, Some(alpha_map))
+            })
+            .collect();
+        mcnk.set_alpha_maps(layers, alpha_format);
+    }
+
+    let written = output.map_or_else(|| path.to_path_buf(), PathBuf::from);
+    adt.write(&written)?;
+    Ok(ImportAlphaCmdResult {
+        written,
+        alpha_format,
+        added_textures,
+        truncated_chunks,
+        warnings,
+    })
+}
+
+/// The format of the first alpha map found in the tile, 4 bit if there is none.
+fn detect_alpha_format(adt: &AdtFile, big_alpha: Option<bool>) -> R<AlphaMapFormat> {
+    for mcnk in &adt.mcnk.items {
+        for layer in 1..mcnk.mcly.0.len() {
+            if let Some(format) = mcnk.alpha_map_format(layer, big_alpha)? {
+                return Ok(format);
+            }
+        }
+    }
+    Ok(AlphaMapFormat::Uncompressed4Bit)
+}
+
+/// The weight of every texture used on the tile, keyed by MTEX index, as one tile sized image each.
+fn get_texture_weights(adt: &AdtFile, big_alpha: Option<bool>) -> R<BTreeMap<u32, Vec<u8>>> {
+    let mut weights: BTreeMap<u32, Vec<u8>> = BTreeMap::new();
+    for mcnk in &adt.mcnk.items {
+        let alpha_maps = mcnk.alpha_maps(big_alpha)
+            .with_context(|| format!("MCNK ({}, {})", mcnk.header.index_x, mcnk.header.index_y))?;
+        let chunk_x = mcnk.header.index_x as usize % 16 * ALPHA_MAP_SIZE;
+        let chunk_y = mcnk.header.index_y as usize % 16 * ALPHA_MAP_SIZE;
+
+        for y in 0..ALPHA_MAP_SIZE {
+            for x in 0..ALPHA_MAP_SIZE {
+                let i = y * ALPHA_MAP_SIZE + x;
+                let pixel = (chunk_y + y) * TILE_ALPHA_SIZE + chunk_x + x;
+                let mut base_weight = u8::MAX;
+                for (layer, alpha_map) in mcnk.mcly.0.iter().zip(&alpha_maps).skip(1) {
+                    let alpha = alpha_map.as_ref().map_or(0, |it| it[i]);
+                    base_weight = base_weight.saturating_sub(alpha);
+                    add_weight(&mut weights, layer.texture_id, pixel, alpha);
+                }
+                if let Some(base) = mcnk.mcly.0.first() {
+                    add_weight(&mut weights, base.texture_id, pixel, base_weight);
+                }
+            }
+        }
+    }
+    Ok(weights)
+}
+
+fn add_weight(weights: &mut BTreeMap<u32, Vec<u8>>, texture_id: u32, pixel: usize, weight: u8) {
+    let image = weights.entry(texture_id)
+        .or_insert_with(|| vec![0; TILE_ALPHA_SIZE * TILE_ALPHA_SIZE]);
+    image[pixel] = image[pixel].saturating_add(weight);
+}
+
+/// Reads a square grayscale png of the given size, 16 bit samples are reduced to 8 bit.
+pub(crate) fn read_grayscale_png(path: &Path, size: usize) -> R<Vec<u8>> {
+    let file = File::open(path).with_context(|| format!("Failed to open {:?}", path.display()))?;
+    let mut decoder = png::Decoder::new(file);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let (info, mut reader) = decoder.read_info()?;
+    if info.color_type != png::ColorType::Grayscale {
+        return err(format!("{:?} is not a grayscale png but {:?}", path.display(), info.color_type));
+    }
+    if info.width as usize != size || info.height as usize != size {
+        return err(format!(
+            "{:?} is {}x{}, expected {}x{}",
+            path.display(), info.width, info.height, size, size
+        ));
+    }
+    let mut pixels = vec![0; info.buffer_size()];
+    reader.next_frame(&mut pixels)?;
+    Ok(pixels)
+}
+
+pub(crate) fn write_grayscale_png(path: &Path, size: usize, pixels: &[u8]) -> R<()> {
+    let file = File::create(path).with_context(|| format!("Failed to create {:?}", path.display()))?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), size as u32, size as u32);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(pixels)?;
+    Ok(())
+}
@@ -0,0 +1,153 @@
+use crate::command_handler::adt::{file_has_extension, get_adt_tiles_in_range};
+use crate::common::{err, R};
+use crate::formats::adt::{AdtFile, ChunkRange, TileCoords};
+use crate::formats::dbc::dbc::load_area_table_from_path;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+/// MCNKs per tile side, one mask pixel each.
+const TILE_AREA_SIZE: usize = 16;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SetAreaCmdResult {
+    pub written: Vec<PathBuf>,
+    pub areas: Vec<SetAreaCount>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SetAreaCount {
+    pub area_id: u32,
+    pub area_name: String,
+    pub chunk_count: usize,
+}
+
+/// Rewrites the MCNK area ids of an ADT, or of every tile of a WDT between `--min-tile` and
+/// `--max-tile`. Either every MCNK in `--chunks` (all of them by default) gets `--area-id`, or a png
+/// mask with one pixel per MCNK is mapped to area ids through `--color <rrggbb>=<id>`, MCNKs under
+/// other colors are left as is. The mask covers a single tile (16x16) or the whole map (1024x1024)
+/// when given a WDT. Every id must be in AreaTable.dbc. Tiles are only rewritten when an area id
+/// changed, unless an `output` is given.
+#[allow(clippy::too_many_arguments)]
+pub fn set_area(
+    file: &str,
+    area_table: &str,
+    area_id: Option<u32>,
+    chunks: Option<ChunkRange>,
+    mask: Option<&str>,
+    colors: &[String],
+    min_tile: Option<TileCoords>,
+    max_tile: Option<TileCoords>,
+    output: Option<&str>,
+) -> R<SetAreaCmdResult> {
+    let path = Path::new(file);
+    let is_map = file_has_extension(path, "wdt");
+    if is_map && output.is_some() {
+        return err("--output can only be used with a single ADT".to_string());
+    }
+
+    let colors = colors.iter().map(|it| parse_color_mapping(it)).collect::<R<HashMap<[u8; 3], u32>>>()?;
+    let mask = match (area_id, mask) {
+        (Some(_), None) => None,
+        (None, Some(mask)) => {
+            if colors.is_empty() {
+                return err("--mask needs at least one --color".to_string());
+            }
+            let size = if is_map { 64 * TILE_AREA_SIZE } else { TILE_AREA_SIZE };
+            Some((read_rgb_png(Path::new(mask), size)?, size))
+        }
+        _ => return err("Exactly one of --area-id and --mask is needed".to_string()),
+    };
+    if mask.is_some() && chunks.is_some() {
+        return err("--chunks can't be used together with --mask".to_string());
+    }
+    let chunks = chunks.unwrap_or(ChunkRange { min: (0, 0), max: (15, 15) });
+
+    let area_table = load_area_table_from_path(area_table)?;
+    let area_names: HashMap<u32, &str> = area_table.rows.iter().map(|it| (it.id, it.area_name.as_str())).collect();
+    for id in area_id.iter().chain(colors.values()) {
+        if !area_names.contains_key(id) {
+            return err(format!("Area id {} is not in AreaTable.dbc", id));
+        }
+    }
+
+    let mut written = vec![];
+    let mut chunk_counts: BTreeMap<u32, usize> = BTreeMap::new();
+    for (tile_x, tile_y, adt_path) in get_adt_tiles_in_range(path, min_tile, max_tile)? {
+        let mut adt = AdtFile::from_path(&adt_path)?;
+        let mut changed = false;
+        for chunk in &mut adt.mcnk.items {
+            let (chunk_x, chunk_y) = (chunk.header.index_x as usize, chunk.header.index_y as usize);
+            let new_area_id = match &mask {
+                Some((pixels, size)) => {
+                    let (x, y) = if is_map {
+                        (tile_x as usize * TILE_AREA_SIZE + chunk_x, tile_y as usize * TILE_AREA_SIZE + chunk_y)
+                    } else {
+                        (chunk_x, chunk_y)
+                    };
+                    pixels.get(y * size + x).and_then(|it| colors.get(it)).copied()
+                }
+                None => area_id.filter(|_| chunks.contains(chunk_x as u32, chunk_y as u32)),
+            };
+            if let Some(new_area_id) = new_area_id {
+                *chunk_counts.entry(new_area_id).or_default() += 1;
+                changed |= chunk.header.area_id != new_area_id;
+                chunk.header.area_id = new_area_id;
+            }
+        }
+        if changed || output.is_some() {
+            let output_path = output.map_or(adt_path, PathBuf::from);
+            adt.write(&output_path)?;
+            written.push(output_path);
+        }
+    }
+
+    Ok(SetAreaCmdResult {
+        written,
+        areas: chunk_counts.into_iter()
+            .map(|(area_id, chunk_count)| SetAreaCount {
+                area_id,
+                area_name: area_names[&area_id].to_string(),
+                chunk_count,
+            })
+            .collect(),
+    })
+}
+
+/// `<rrggbb>=<area id>`, the color may start with a `#`.
+fn parse_color_mapping(mapping: &str) -> R<([u8; 3], u32)> {
+    let (color, id) = mapping.split_at(mapping.find('=').with_context(|| format!("Expected <rrggbb>=<area id> but got {:?}", mapping))?);
+    let color = hex::decode(color.trim_start_matches('#'))
+        .ok()
+        .filter(|it| it.len() == 3)
+        .with_context(|| format!("Invalid color {:?}, expected rrggbb", color))?;
+    let id = id[1..].parse().with_context(|| format!("Invalid area id in {:?}", mapping))?;
+    Ok(([color[0], color[1], color[2]], id))
+}
+
+fn read_rgb_png(path: &Path, size: usize) -> R<Vec<[u8; 3]>> {
+    let file = File::open(path).with_context(|| format!("Failed to open {:?}", path.display()))?;
+    let mut decoder = png::Decoder::new(file);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let (info, mut reader) = decoder.read_info()?;
+    if info.width as usize != size || info.height as usize != size {
+        return err(format!(
+            "{:?} is {}x{}, expected {}x{}",
+            path.display(), info.width, info.height, size, size
+        ));
+    }
+    let mut pixels = vec![0; info.buffer_size()];
+    reader.next_frame(&mut pixels)?;
+    let channels = match info.color_type {
+        png::ColorType::Grayscale => 1,
+        png::ColorType::GrayscaleAlpha => 2,
+        png::ColorType::RGB => 3,
+        png::ColorType::RGBA => 4,
+        color_type => return err(format!("{:?} has an unsupported color type {:?}", path.display(), color_type)),
+    };
+    Ok(pixels.chunks(channels)
+        .map(|it| if channels < 3 { [it[0]; 3] } else { [it[0], it[1], it[2]] })
+        .collect())
+}
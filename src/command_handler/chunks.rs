@@ -0,0 +1,145 @@
+use crate::common::{map_file, R};
+use crate::formats::chunk::{ChunkMcnkHeader, MCNK_VERTEX_COUNT, PARSED_CHUNK_TYPES};
+use crate::formats::wmo::ChunkMogpHeader;
+use crate::byte_utils::{BinRead, VecUtils};
+use crate::ChunksCmd;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChunksCmdResult {
+    pub file_size: usize,
+    pub chunks: Vec<ChunkInfo>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChunkInfo {
+    pub id: String,
+    pub offset: usize,
+    pub declared_size: u32,
+    pub actual_size: usize,
+    pub has_parser: bool,
+    pub hex_preview: Option<String>,
+    /// Why the listing stopped at this chunk.
+    pub error: Option<String>,
+    /// The sub-chunks of MCNK and MOGP, after their fixed headers.
+    pub children: Vec<ChunkInfo>,
+}
+
+pub fn handle_chunks_command(cmd: &ChunksCmd) -> R<Box<dyn erased_serde::Serialize>> {
+    let result = list_chunks(Path::new(cmd.file.as_str()), cmd.hex_preview)?;
+    Ok(Box::new(result))
+}
+
+/// Walks the chunks leniently: a header running past the end of the file ends the listing with an
+/// entry for that chunk, its `actual_size` being the bytes left, instead of failing like [crate::formats::chunk::ChunkRef::from_bytes].
+/// MCNK and MOGP are walked the same way, a broken sub-chunk only ends the listing of its parent.
+fn list_chunks(path: &Path, hex_preview: Option<usize>) -> R<ChunksCmdResult> {
+    let mapped_file = map_file(path)?;
+    Ok(ChunksCmdResult {
+        file_size: mapped_file.len(),
+        chunks: walk_chunks(&mapped_file, hex_preview),
+    })
+}
+
+fn walk_chunks(bytes: &[u8], hex_preview: Option<usize>) -> Vec<ChunkInfo> {
+    walk_chunks_at(bytes, 0, None, hex_preview)
+}
+
+/// `base_offset` is the file offset of `bytes`, `mcnk` the header of the MCNK they are the sub-chunks of.
+fn walk_chunks_at(bytes: &[u8], base_offset: usize, mcnk: Option<&ChunkMcnkHeader>, hex_preview: Option<usize>) -> Vec<ChunkInfo> {
+    let mut chunks = vec![];
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let id: String = String::from_utf8_lossy(&bytes[offset..bytes.len().min(offset + 4)]).chars().rev().collect();
+        let declared_size = bytes.get_u32(offset + 4).ok();
+        let data = &bytes[bytes.len().min(offset + 8)..];
+        let (data, error) = match declared_size {
+            Some(size) if size as usize <= data.len() => {
+                // the sizes the MCNK parser reads past, see [crate::formats::chunk::ChunkMcnkItem::from_chunk].
+                let size = match (mcnk, id.as_str()) {
+                    (Some(_), "MCNR") => (size as usize).max(MCNK_VERTEX_COUNT * 3 + 13),
+                    (Some(header), "MCLQ") => (size as usize).max((header.size_mclq as usize).saturating_sub(8)),
+                    _ => size as usize,
+                };
+                (&data[..size.min(data.len())], None)
+            }
+            Some(size) => (data, Some(format!("Declared size {} but only {} bytes are left", size, data.len()))),
+            None => (data, Some(format!("Incomplete chunk header, only {} bytes are left", bytes.len() - offset))),
+        };
+        let header_size = match id.as_str() {
+            "MCNK" => ChunkMcnkHeader::SIZE,
+            "MOGP" => ChunkMogpHeader::SIZE,
+            _ => 0,
+        };
+        let children = match data.get(header_size..) {
+            Some(sub_chunks) if header_size > 0 => {
+                let header = ChunkMcnkHeader::from_bytes(data).ok().filter(|_| id == "MCNK");
+                walk_chunks_at(sub_chunks, base_offset + offset + 8 + header_size, header.as_ref(), hex_preview)
+            }
+            _ => vec![],
+        };
+        let stop = error.is_some();
+        chunks.push(ChunkInfo {
+            has_parser: PARSED_CHUNK_TYPES.contains(&id.as_str()),
+            id,
+            offset: base_offset + offset,
+            declared_size: declared_size.unwrap_or(0),
+            actual_size: data.len(),
+            hex_preview: hex_preview.map(|len| hex::encode(&data[..len.min(data.len())])),
+            error,
+            children,
+        });
+        if stop {
+            break;
+        }
+        offset += 8 + data.len();
+    }
+    chunks
+}
+
+#[cfg(test)]
+#[test]
+fn walk_chunks_reports_truncated_chunk() {
+    let mut bytes = b"REVM".to_vec();
+    bytes.extend_from_slice(&4u32.to_le_bytes());
+    bytes.extend_from_slice(&18u32.to_le_bytes());
+    bytes.extend_from_slice(b"RDHM");
+    bytes.extend_from_slice(&64u32.to_le_bytes());
+    bytes.extend_from_slice(&[0; 10]);
+
+    let chunks = walk_chunks(&bytes, None);
+    assert_eq!(chunks.len(), 2);
+    assert!(chunks[0].error.is_none());
+    assert_eq!((chunks[1].id.as_str(), chunks[1].offset), ("MHDR", 12));
+    assert_eq!((chunks[1].declared_size, chunks[1].actual_size), (64, 10));
+    assert!(chunks[1].error.is_some());
+}
+
+#[cfg(test)]
+#[test]
+fn walk_chunks_lists_mcnk_and_mogp_sub_chunks() {
+    use crate::formats::adt::test_adt;
+    use crate::formats::chunk::{chunks_to_bytes, Chunk, ChunkRef, ChunkVecUtils};
+
+    let bytes = chunks_to_bytes(&test_adt().to_chunks());
+    let mcnk = ChunkRef::from_bytes(&bytes).unwrap().get_all_chunks_of_type("MCNK")[3].offset;
+    let chunks = walk_chunks(&bytes, None);
+    let listed = chunks.iter().find(|it| it.offset == mcnk).unwrap();
+    let children: Vec<&str> = listed.children.iter().map(|it| it.id.as_str()).collect();
+    // MCNR is followed by 13 bytes of padding its size leaves out.
+    assert_eq!(children, vec!["MCVT", "MCNR", "MCLY", "MCRF", "MCSE"]);
+    assert_eq!(listed.children[0].offset, mcnk + 8 + ChunkMcnkHeader::SIZE);
+    assert_eq!(listed.children[1].actual_size, MCNK_VERTEX_COUNT * 3 + 13);
+    assert!(listed.children.iter().all(|it| it.error.is_none() && it.children.is_empty()));
+
+    let mut mogp_data = vec![0u8; ChunkMogpHeader::SIZE];
+    mogp_data.append(&mut Chunk::new("MOPY", vec![1, 2]).to_bytes());
+    mogp_data.append(&mut Chunk::new("MOVI", vec![0; 64]).to_bytes());
+    let bytes = chunks_to_bytes(&[Chunk::new("MVER", 17u32.to_le_bytes().to_vec()), Chunk::new("MOGP", mogp_data)]);
+    let chunks = walk_chunks(&bytes[..bytes.len() - 10], None);
+    let mogp = &chunks[1].children;
+    assert_eq!((mogp[0].id.as_str(), mogp[0].offset), ("MOPY", 12 + 8 + ChunkMogpHeader::SIZE));
+    assert_eq!((mogp[1].id.as_str(), mogp[1].actual_size), ("MOVI", 54));
+    assert!(mogp[1].error.is_some());
+}
@@ -0,0 +1,56 @@
+use crate::command_handler::adt::{file_has_extension, get_adt_tiles};
+use crate::common::R;
+use crate::formats::adt::AdtFile;
+use crate::formats::alpha_map::AlphaMapFormat;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ConvertSplitAdtCmdResult {
+    pub written: Vec<PathBuf>,
+    /// Formats of the alpha maps, copied as they are from the `_tex0` files.
+    pub alpha_formats: Vec<AlphaMapFormat>,
+    /// Whether the WDT has to flag big alpha in MPHD for the alpha maps to be read right.
+    pub needs_big_alpha: bool,
+}
+
+/// Converts a split root ADT (with its `_tex0` and `_obj0` files), or every tile of a WDT, to
+/// 3.3.5 ADTs. `output` is the ADT to write, or the folder to write the tiles to for a WDT.
+/// Alpha maps are not re-encoded, the formats found are reported so the WDT flags can follow.
+pub fn convert_split_adts(file: &str, output: &str) -> R<ConvertSplitAdtCmdResult> {
+    let path = Path::new(file);
+    let output = Path::new(output);
+    let is_map = file_has_extension(path, "wdt");
+    if is_map {
+        std::fs::create_dir_all(output)
+            .with_context(|| format!("Could not create folder {:?}", output.display()))?;
+    }
+
+    let mut written = vec![];
+    let mut alpha_formats = vec![];
+    for (_, _, root_path) in get_adt_tiles(path)? {
+        let adt = AdtFile::from_split_path(&root_path)?;
+        for mcnk in &adt.mcnk.items {
+            for layer in 1..mcnk.mcly.0.len() {
+                match mcnk.alpha_map_format(layer, None)? {
+                    Some(format) if !alpha_formats.contains(&format) => alpha_formats.push(format),
+                    _ => {}
+                }
+            }
+        }
+        let output_path = if is_map {
+            output.join(root_path.file_name().unwrap_or_default())
+        } else {
+            output.to_path_buf()
+        };
+        adt.write(&output_path)?;
+        written.push(output_path);
+    }
+    let needs_big_alpha = alpha_formats.iter().any(|it| it.needs_big_alpha());
+    Ok(ConvertSplitAdtCmdResult {
+        written,
+        alpha_formats,
+        needs_big_alpha,
+    })
+}
@@ -0,0 +1,102 @@
+use crate::command_handler::adt::file_has_extension;
+use crate::command_handler::resolve_map_assets::{find_map_folder, join_path_ignoring_casing};
+use crate::common::R;
+use crate::formats::adt::{tile_coords_from_path, TileCoords};
+use crate::formats::chunk::{MAIN_FLAG_HAS_ADT, MPHD_FLAG_ADT_HAS_BIG_ALPHA, MPHD_FLAG_ADT_HAS_MCCV};
+use crate::formats::wdt::WdtFile;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateWdtCmdResult {
+    pub written: PathBuf,
+    /// Whether MPHD, MWMO and MODF were taken from the WDT already there.
+    pub kept_existing: bool,
+    pub mphd_flags: u32,
+    pub tiles: Vec<TileCoords>,
+    /// `.adt` files of the map folder not named `<Map>_<x>_<y>.adt`, split `_tex0`/`_obj0` files for instance.
+    pub ignored: Vec<PathBuf>,
+}
+
+/// Writes the WDT of a map from the `<Map>_<x>_<y>.adt` files in its World/Maps folder, exactly
+/// those tiles are flagged in MAIN. A WDT already there only gets its MAIN flags rebuilt, its MPHD
+/// flags (plus the requested ones), MWMO, MODF and MAIN area ids are kept. `force` writes a new
+/// WDT from scratch instead, which is also the only way to replace a WDT that doesn't parse.
+pub fn create_wdt(
+    workspace: &str,
+    map_id: u32,
+    big_alpha: bool,
+    vertex_shading: bool,
+    mphd_flags: Option<u32>,
+    force: bool,
+    output: Option<&str>,
+) -> R<CreateWdtCmdResult> {
+    let (map_name, maps_folder) = find_map_folder(Path::new(workspace), map_id)?;
+
+    let mut adt_paths: Vec<PathBuf> = std::fs::read_dir(&maps_folder)
+        .with_context(|| format!("Could not read folder {:?}", maps_folder.display()))?
+        .map(|entry| entry.map(|it| it.path()))
+        .collect::<Result<_, _>>()?;
+    adt_paths.retain(|it| file_has_extension(it, "adt"));
+    adt_paths.sort();
+
+    let output = match output {
+        Some(output) => PathBuf::from(output),
+        None => {
+            let file_name = format!("{}.wdt", map_name);
+            join_path_ignoring_casing(&maps_folder, &file_name).unwrap_or_else(|| maps_folder.join(file_name))
+        }
+    };
+    let existing = if output.exists() && !force {
+        let existing = WdtFile::from_path(&output)
+            .context("The existing WDT can't be read, use --force to replace it")?;
+        Some(existing)
+    } else {
+        None
+    };
+    let kept_existing = existing.is_some();
+    let mut wdt = existing.unwrap_or_else(|| WdtFile::empty(0));
+
+    wdt.mphd.flags |= mphd_flags.unwrap_or(0);
+    if big_alpha {
+        wdt.mphd.flags |= MPHD_FLAG_ADT_HAS_BIG_ALPHA;
+    }
+    if vertex_shading {
+        wdt.mphd.flags |= MPHD_FLAG_ADT_HAS_MCCV;
+    }
+    for item in &mut wdt.main.0 {
+        item.flags &= !MAIN_FLAG_HAS_ADT;
+    }
+    let mut tiles = vec![];
+    let mut ignored = vec![];
+    for adt_path in adt_paths {
+        match tile_coords(&adt_path, &map_name) {
+            Some(tile) => {
+                wdt.main.tile_mut(tile.x, tile.y).flags |= MAIN_FLAG_HAS_ADT;
+                tiles.push(tile);
+            }
+            None => ignored.push(adt_path),
+        }
+    }
+
+    wdt.write(&output)?;
+    Ok(CreateWdtCmdResult {
+        written: output,
+        kept_existing,
+        mphd_flags: wdt.mphd.flags,
+        tiles,
+        ignored,
+    })
+}
+
+/// The tile of a `<Map>_<x>_<y>.adt` file of map `map_name`, the map name is matched ignoring case.
+fn tile_coords(adt_path: &Path, map_name: &str) -> Option<TileCoords> {
+    let (x, y) = tile_coords_from_path(adt_path)?;
+    let stem = adt_path.file_stem()?.to_str()?;
+    if x < 64 && y < 64 && stem.eq_ignore_ascii_case(&format!("{}_{}_{}", map_name, x, y)) {
+        Some(TileCoords { x, y })
+    } else {
+        None
+    }
+}
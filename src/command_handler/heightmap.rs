@@ -0,0 +1,125 @@
+use crate::command_handler::adt::{file_has_extension, get_adt_tiles};
+use crate::common::{err, R};
+use crate::formats::adt::{tile_coords_from_path, AdtFile};
+use crate::formats::heightmap::{Heightmap, HeightmapFormat, HeightmapMetadata, TILE_GRID_SIZE};
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ImportHeightmapCmdResult {
+    pub base_height: f32,
+    pub height_scale: f32,
+    pub written: Vec<PathBuf>,
+}
+
+/// Exports a single ADT, or every tile of the map when given its WDT.
+pub fn export_heightmap(file: &str, output: &str) -> R<HeightmapMetadata> {
+    let file = Path::new(file);
+    let tiles = get_adt_tiles(file)?;
+
+    let min_x = tiles.iter().map(|(x, _, _)| *x).min().unwrap();
+    let min_y = tiles.iter().map(|(_, y, _)| *y).min().unwrap();
+    let max_x = tiles.iter().map(|(x, _, _)| *x).max().unwrap();
+    let max_y = tiles.iter().map(|(_, y, _)| *y).max().unwrap();
+    let tile_step = TILE_GRID_SIZE - 1;
+    let mut heightmap = Heightmap::new(
+        (max_x - min_x + 1) as usize * tile_step + 1,
+        (max_y - min_y + 1) as usize * tile_step + 1,
+    );
+
+    for (x, y, path) in &tiles {
+        let adt = AdtFile::from_path(path)?;
+        heightmap.blit_adt(
+            &adt,
+            (x - min_x) as usize * tile_step,
+            (y - min_y) as usize * tile_step,
+        )?;
+    }
+    heightmap.fill_gaps();
+
+    let origin_tile = if file_has_extension(file, "wdt") || tile_coords_from_path(file).is_some() {
+        Some((min_x, min_y))
+    } else {
+        None
+    };
+    heightmap.write(output, origin_tile)
+}
+
+/// Writes the heights of `input` back into every given ADT. The tiles are placed in the grid
+/// by their file name coordinates, relative to the origin tile of the heightmap metadata.
+pub fn import_heightmap(
+    input: &str,
+    files: &[String],
+    base_height: Option<f32>,
+    height_scale: Option<f32>,
+    output_dir: Option<&str>,
+) -> R<ImportHeightmapCmdResult> {
+    let input = Path::new(input);
+    let metadata = HeightmapMetadata::read_for(input)?;
+    let format = HeightmapFormat::from_path(input)?;
+
+    let (base_height, height_scale) = match (format, &metadata) {
+        (HeightmapFormat::Raw, _) => (base_height.unwrap_or(0.0), height_scale.unwrap_or(1.0)),
+        (_, Some(metadata)) => (
+            base_height.unwrap_or(metadata.min_height),
+            height_scale.unwrap_or(metadata.max_height - metadata.min_height),
+        ),
+        (_, None) => (
+            base_height.unwrap_or(0.0),
+            height_scale.with_context(|| format!(
+                "No metadata found for {:?}, the height scale must be given", input.display()
+            ))?,
+        ),
+    };
+    let raw_size = metadata.as_ref().map(|it| (it.width, it.height));
+    let heightmap = Heightmap::read(input, base_height, height_scale, raw_size)?;
+
+    let mut tiles = Vec::with_capacity(files.len());
+    for file in files {
+        let path = Path::new(file);
+        let coords = match tile_coords_from_path(path) {
+            Some(coords) => coords,
+            None if files.len() == 1 => (0, 0),
+            None => return err(format!("Can't tell the tile coordinates of {:?} from its name", path.display())),
+        };
+        tiles.push((coords, path));
+    }
+    let origin_tile = match metadata.and_then(|it| it.origin_tile) {
+        Some(origin_tile) => origin_tile,
+        None => (
+            tiles.iter().map(|((x, _), _)| *x).min().unwrap_or(0),
+            tiles.iter().map(|((_, y), _)| *y).min().unwrap_or(0),
+        ),
+    };
+
+    let tile_step = TILE_GRID_SIZE - 1;
+    let mut written = Vec::with_capacity(tiles.len());
+    for ((x, y), path) in tiles {
+        if x < origin_tile.0 || y < origin_tile.1 {
+            return err(format!(
+                "Tile {:?} lies outside of the heightmap starting at tile {:?}",
+                path.display(), origin_tile
+            ));
+        }
+        let mut adt = AdtFile::from_path(path)?;
+        heightmap.apply_to_adt(
+            &mut adt,
+            (x - origin_tile.0) as usize * tile_step,
+            (y - origin_tile.1) as usize * tile_step,
+        ).with_context(|| format!("Failed to import heights into {:?}", path.display()))?;
+
+        let target = match output_dir {
+            Some(output_dir) => Path::new(output_dir).join(path.file_name().context("Invalid ADT path")?),
+            None => path.to_path_buf(),
+        };
+        adt.write(&target)?;
+        written.push(target);
+    }
+
+    Ok(ImportHeightmapCmdResult {
+        base_height,
+        height_scale,
+        written,
+    })
+}
@@ -0,0 +1,21 @@
+use crate::command_handler::create_wdt::create_wdt;
+use crate::command_handler::unique_ids::check_unique_ids;
+use crate::{common::R, MapToolCmd};
+
+pub fn handle_map_command(cmd: &MapToolCmd) -> R<Box<dyn erased_serde::Serialize>> {
+    let res: Box<dyn erased_serde::Serialize> = match cmd {
+        MapToolCmd::CheckUniqueIds(cmd) => {
+            Box::new(check_unique_ids(&cmd.workspace, cmd.map_id, cmd.renumber)?)
+        }
+        MapToolCmd::CreateWdt(cmd) => Box::new(create_wdt(
+            &cmd.workspace,
+            cmd.map_id,
+            cmd.big_alpha,
+            cmd.vertex_shading,
+            cmd.mphd_flags,
+            cmd.force,
+            cmd.output.as_deref(),
+        )?),
+    };
+    Ok(res)
+}
@@ -0,0 +1,101 @@
+use crate::common::{err, R};
+use crate::formats::adt::{tile_coords_from_path, AdtFile};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MergeAdtCmdResult {
+    pub written: PathBuf,
+    pub doodad_count: usize,
+    pub wmo_count: usize,
+    pub renumbered: Vec<RenumberedUniqueId>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RenumberedUniqueId {
+    pub old: u32,
+    pub new: u32,
+}
+
+/// Writes the terrain of `terrain` (MCNKs with their heights, textures and sounds, MH2O and the
+/// chunks without a typed field) along with the MMDX/MMID/MWMO/MWID/MDDF/MODF of `objects` to
+/// `output`. Both have to be copies of the same tile. MCRF is rebuilt for the new objects, and
+/// ids that are 0 or used twice in the tile are given fresh ones above the highest id in use, and
+/// from `first_unique_id` on when given, to keep them unique across the map.
+pub fn merge_adts(terrain: &str, objects: &str, first_unique_id: Option<u32>, output: &str) -> R<MergeAdtCmdResult> {
+    let tiles = (tile_coords_from_path(terrain), tile_coords_from_path(objects));
+    let mut adt = AdtFile::from_path(terrain)?;
+    let objects = AdtFile::from_path(objects)?;
+    let same_tile = match tiles {
+        (Some(terrain_tile), Some(objects_tile)) => terrain_tile == objects_tile,
+        _ => mcnk_corners(&adt) == mcnk_corners(&objects),
+    };
+    if !same_tile {
+        return err("--terrain and --objects are not the same tile".to_string());
+    }
+
+    adt.mmdx = objects.mmdx;
+    adt.mmid = objects.mmid;
+    adt.mwmo = objects.mwmo;
+    adt.mwid = objects.mwid;
+    adt.mddf = objects.mddf;
+    adt.modf = objects.modf;
+    let renumbered = fix_unique_ids(&mut adt, first_unique_id);
+    adt.rebuild_mcrf();
+    adt.write(output)?;
+
+    Ok(MergeAdtCmdResult {
+        written: PathBuf::from(output),
+        doodad_count: adt.mddf.0.len(),
+        wmo_count: adt.modf.0.len(),
+        renumbered,
+    })
+}
+
+/// The north west corner of every MCNK, for tiles whose file names don't tell their coordinates.
+fn mcnk_corners(adt: &AdtFile) -> Vec<[u32; 2]> {
+    adt.mcnk.items.iter()
+        .map(|it| [it.header.position[0].to_bits(), it.header.position[1].to_bits()])
+        .collect()
+}
+
+/// Doodads and WMOs share one id space, an object is only placed once per ADT.
+fn fix_unique_ids(adt: &mut AdtFile, first_unique_id: Option<u32>) -> Vec<RenumberedUniqueId> {
+    let ids = adt.mddf.0.iter().map(|it| it.unique_id).chain(adt.modf.0.iter().map(|it| it.unique_id));
+    let mut next_id = ids.max().unwrap_or(0) + 1;
+    if let Some(first_unique_id) = first_unique_id {
+        next_id = next_id.max(first_unique_id);
+    }
+    let mut seen = HashSet::new();
+    let mut renumbered = vec![];
+    let doodad_ids = adt.mddf.0.iter_mut().map(|it| &mut it.unique_id);
+    let wmo_ids = adt.modf.0.iter_mut().map(|it| &mut it.unique_id);
+    for unique_id in doodad_ids.chain(wmo_ids) {
+        if *unique_id == 0 || !seen.insert(*unique_id) {
+            renumbered.push(RenumberedUniqueId { old: *unique_id, new: next_id });
+            *unique_id = next_id;
+            next_id += 1;
+        }
+    }
+    renumbered
+}
+
+#[cfg(test)]
+#[test]
+fn fix_unique_ids_renumbers_duplicates() {
+    use crate::formats::adt::{test_adt, test_doodad};
+
+    let mut adt = test_adt();
+    for unique_id in [5, 0, 5, 7].iter() {
+        adt.mddf.0.push(test_doodad(0, *unique_id, [0.0; 3]));
+    }
+    let renumbered = fix_unique_ids(&mut adt, None);
+    let ids: Vec<u32> = adt.mddf.0.iter().map(|it| it.unique_id).collect();
+    assert_eq!(ids, vec![5, 8, 9, 7]);
+    assert_eq!((renumbered[1].old, renumbered[1].new), (5, 9));
+
+    adt.mddf.0[1].unique_id = 0;
+    fix_unique_ids(&mut adt, Some(1000));
+    assert_eq!(adt.mddf.0[1].unique_id, 1000);
+}
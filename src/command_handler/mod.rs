@@ -1,4 +1,20 @@
 pub mod view;
 pub mod resolve_map_assets;
 pub mod mpq;
-pub mod dbc_join;
\ No newline at end of file
+pub mod dbc_join;
+pub mod chunks;
+pub mod adt;
+pub mod heightmap;
+pub mod alpha_map;
+pub mod placements;
+pub mod terrain_mesh;
+pub mod water;
+pub mod area;
+pub mod relocate;
+pub mod merge;
+pub mod validate;
+pub mod convert_split;
+pub mod map;
+pub mod unique_ids;
+pub mod create_wdt;
+pub mod scene;
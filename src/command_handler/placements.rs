@@ -0,0 +1,180 @@
+use crate::command_handler::adt::file_has_extension;
+use crate::command_handler::resolve_map_assets::join_path_ignoring_casing;
+use crate::common::{err, R};
+use crate::formats::adt::AdtFile;
+use crate::formats::placement::{adt_to_server_position, server_to_adt_position, wmo_extents, Placement, PlacementKind};
+use crate::formats::wmo::WmoRootFile;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ApplyPlacementsCmdResult {
+    pub written: PathBuf,
+    pub m2_count: usize,
+    pub wmo_count: usize,
+    /// Entries that came without a unique id, or with one already used in the list.
+    pub assigned_unique_ids: Vec<AssignedUniqueId>,
+    pub warnings: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AssignedUniqueId {
+    pub model: String,
+    pub unique_id: u32,
+}
+
+/// Lists the placements of an ADT, written to `output` as .json or .csv when given.
+pub fn list_placements(file: &str, output: Option<&str>) -> R<Vec<Placement>> {
+    let placements = AdtFile::from_path(file)?.placements();
+    if let Some(output) = output {
+        write_placements(Path::new(output), &placements)?;
+    }
+    Ok(placements)
+}
+
+/// Replaces every placement of the ADT with the ones in `input`, a .json or .csv file shaped like
+/// the `adt placements` output. Entries with a unique id of 0 or a duplicated one get a new id.
+/// WMO extents are computed from the WMO root file when it is found in `workspace`. Entries left
+/// where they were, matched by unique id, keep their exact ADT position and extents.
+pub fn apply_placements(
+    file: &str,
+    input: &str,
+    workspace: Option<&str>,
+    first_unique_id: Option<u32>,
+    output: Option<&str>,
+) -> R<ApplyPlacementsCmdResult> {
+    let path = Path::new(file);
+    let mut adt = AdtFile::from_path(path)?;
+    let mut placements = read_placements(Path::new(input))?;
+    let mut warnings = vec![];
+
+    let existing_ids = adt.mddf.0.iter().map(|it| it.unique_id)
+        .chain(adt.modf.0.iter().map(|it| it.unique_id));
+    let mut next_unique_id = existing_ids
+        .chain(placements.iter().map(|it| it.unique_id))
+        .max()
+        .unwrap_or(0) + 1;
+    if let Some(first_unique_id) = first_unique_id {
+        next_unique_id = next_unique_id.max(first_unique_id);
+    }
+
+    let original_wmos: HashMap<u32, _> = adt.modf.0.iter()
+        .map(|it| (it.unique_id, (it.clone(), adt.get_wmo_path(it.name_id).unwrap_or_default().to_string())))
+        .collect();
+    let mut used_ids = HashSet::new();
+    let mut assigned_unique_ids = vec![];
+    let mut extents = vec![];
+
+    for placement in &mut placements {
+        if placement.model.is_empty() {
+            return err(format!("Placement with unique id {} has no model", placement.unique_id));
+        }
+        let original_wmo = original_wmos.get(&placement.unique_id)
+            .filter(|(_, model)| placement.kind == PlacementKind::Wmo && model.eq_ignore_ascii_case(&placement.model));
+        let original_wmo = if placement.unique_id == 0 || !used_ids.insert(placement.unique_id) {
+            placement.unique_id = next_unique_id;
+            next_unique_id += 1;
+            used_ids.insert(placement.unique_id);
+            assigned_unique_ids.push(AssignedUniqueId {
+                model: placement.model.clone(),
+                unique_id: placement.unique_id,
+            });
+            None
+        } else {
+            original_wmo
+        };
+        if placement.kind != PlacementKind::Wmo {
+            continue;
+        }
+
+        let position = server_to_adt_position([placement.x, placement.y, placement.z]);
+        let rotation = [placement.rotation_x, placement.rotation_y, placement.rotation_z];
+        // compared in server space, the space of the listing, as the ADT position doesn't survive
+        // the round trip through it exactly.
+        let unchanged = original_wmo
+            .map(|(it, _)| {
+                adt_to_server_position(it.position) == [placement.x, placement.y, placement.z]
+                    && it.rotation == rotation
+            })
+            .unwrap_or(false);
+        let root = match workspace {
+            Some(workspace) if !unchanged => join_path_ignoring_casing(Path::new(workspace), &placement.model)
+                .map(WmoRootFile::from_path)
+                .transpose()?,
+            _ => None,
+        };
+        extents.push(match (original_wmo, root) {
+            (Some((original, _)), _) if unchanged => original.extents,
+            (_, Some(root)) => wmo_extents(
+                position,
+                (root.mohd.bounding_box_corner_1, root.mohd.bounding_box_corner_2),
+                placement.scale,
+            ),
+            (Some((original, _)), None) => {
+                if original.rotation != rotation {
+                    warnings.push(format!(
+                        "WMO {} ({}) was rotated, its extents were only moved. Give a workspace to recompute them",
+                        placement.model, placement.unique_id
+                    ));
+                }
+                let mut extents = original.extents;
+                for (i, extent) in extents.iter_mut().enumerate() {
+                    *extent += position[i % 3] - original.position[i % 3];
+                }
+                extents
+            }
+            (None, None) => {
+                warnings.push(format!(
+                    "WMO {} ({}) not found, its extents are empty. Give a workspace to compute them",
+                    placement.model, placement.unique_id
+                ));
+                [position[0], position[1], position[2], position[0], position[1], position[2]]
+            }
+        });
+    }
+
+    adt.set_placements(&placements, &extents);
+    let written = output.map_or_else(|| path.to_path_buf(), PathBuf::from);
+    adt.write(&written)?;
+
+    Ok(ApplyPlacementsCmdResult {
+        written,
+        m2_count: adt.mddf.0.len(),
+        wmo_count: adt.modf.0.len(),
+        assigned_unique_ids,
+        warnings,
+    })
+}
+
+pub(crate) fn read_placements(path: &Path) -> R<Vec<Placement>> {
+    let file = File::open(path).with_context(|| format!("Failed to open {:?}", path.display()))?;
+    if file_has_extension(path, "csv") {
+        csv::Reader::from_reader(file)
+            .deserialize()
+            .collect::<Result<Vec<Placement>, _>>()
+            .with_context(|| format!("Invalid placements in {:?}", path.display()))
+    } else if file_has_extension(path, "json") {
+        serde_json::from_reader(file).with_context(|| format!("Invalid placements in {:?}", path.display()))
+    } else {
+        err(format!("Unsupported placements file {:?}, expected a .json or .csv extension", path.display()))
+    }
+}
+
+pub(crate) fn write_placements(path: &Path, placements: &[Placement]) -> R<()> {
+    let file = File::create(path).with_context(|| format!("Failed to create {:?}", path.display()))?;
+    if file_has_extension(path, "csv") {
+        let mut writer = csv::Writer::from_writer(file);
+        for placement in placements {
+            writer.serialize(placement)?;
+        }
+        writer.flush()?;
+    } else if file_has_extension(path, "json") {
+        serde_json::to_writer_pretty(file, placements)?;
+    } else {
+        return err(format!("Unsupported placements file {:?}, expected a .json or .csv extension", path.display()));
+    }
+    Ok(())
+}
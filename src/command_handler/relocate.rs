@@ -0,0 +1,83 @@
+use crate::common::{err, R};
+use crate::formats::adt::{AdtFile, TileCoords};
+use crate::formats::chunk::MAIN_FLAG_HAS_ADT;
+use crate::formats::wdt::WdtFile;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RelocateAdtCmdResult {
+    pub written: PathBuf,
+    pub wdt: PathBuf,
+    pub removed: Option<PathBuf>,
+    pub warnings: Vec<String>,
+}
+
+/// Copies the ADT at grid slot `from` of the map of `wdt` to slot `to`, shifting every absolute
+/// coordinate inside by the distance between both slots, and flags the new tile in the WDT MAIN.
+/// With `move_tile` the source ADT is deleted and its MAIN flag cleared.
+pub fn relocate_adt(wdt: &str, from: TileCoords, to: TileCoords, move_tile: bool) -> R<RelocateAdtCmdResult> {
+    let wdt_path = Path::new(wdt);
+    if from == to {
+        return err("--from and --to are the same tile".to_string());
+    }
+    let mut wdt_file = WdtFile::from_path(wdt_path)?;
+    if wdt_file.main.tile(from.x, from.y).flags & MAIN_FLAG_HAS_ADT == 0 {
+        return err(format!("Tile {}_{} is not flagged in the WDT MAIN", from.x, from.y));
+    }
+    if wdt_file.main.tile(to.x, to.y).flags & MAIN_FLAG_HAS_ADT != 0 {
+        return err(format!("Tile {}_{} is already flagged in the WDT MAIN", to.x, to.y));
+    }
+
+    let map_name = wdt_path.file_stem()
+        .and_then(|it| it.to_str())
+        .with_context(|| format!("Invalid WDT file name {:?}", wdt_path.display()))?;
+    let folder = wdt_path.parent().unwrap_or_else(|| Path::new(""));
+    let source = folder.join(format!("{}_{}_{}.adt", map_name, from.x, from.y));
+    let destination = folder.join(format!("{}_{}_{}.adt", map_name, to.x, to.y));
+    if destination.exists() {
+        return err(format!("{:?} already exists", destination.display()));
+    }
+
+    let mut adt = AdtFile::from_path(&source)?;
+    adt.relocate(from, to);
+    adt.write(&destination)?;
+
+    let source_entry = wdt_file.main.tile(from.x, from.y);
+    let (flags, area) = (source_entry.flags, source_entry.area);
+    let destination_entry = wdt_file.main.tile_mut(to.x, to.y);
+    destination_entry.flags = flags;
+    destination_entry.area = area;
+    if move_tile {
+        let source_entry = wdt_file.main.tile_mut(from.x, from.y);
+        source_entry.flags = 0;
+        source_entry.area = 0;
+    }
+    wdt_file.write(wdt_path)?;
+
+    let mut warnings = vec![];
+    let removed = if move_tile {
+        std::fs::remove_file(&source)
+            .with_context(|| format!("Could not remove {:?}", source.display()))?;
+        Some(source)
+    } else {
+        if !adt.mddf.0.is_empty() || !adt.modf.0.is_empty() {
+            warnings.push(
+                "The copied placements keep their unique ids, run map check-unique-ids --renumber".to_string()
+            );
+        }
+        None
+    };
+    let wdl = wdt_path.with_extension("wdl");
+    if wdl.exists() {
+        warnings.push(format!("{:?} was not updated and has no low resolution terrain for the new tile", wdl.display()));
+    }
+
+    Ok(RelocateAdtCmdResult {
+        written: destination,
+        wdt: wdt_path.to_path_buf(),
+        removed,
+        warnings,
+    })
+}
@@ -31,7 +31,7 @@ pub enum ResolveMapAssetsCmdWarn {
     Missing(String),
     FileParseFail(String),
     FailedToRemoveFile(String),
-    AdtParseErr(PathBuf),
+    AdtParseErr(PathBuf, String),
     MissingDbcEntry(String),
     MissingMiniMapFolder,
 }
@@ -87,11 +87,7 @@ fn resolve_map_assets(
             .find(|map| map.id == *map_id)
             .context(format!("Map with id {} not found", map_id))?;
 
-        let maps_folder = join_path_ignoring_casing(
-            workspace_path,
-            format!("World/Maps/{}", map_row.internal_name).as_str(),
-        )
-        .context("Missing World/Maps folder in workspace")?;
+        let maps_folder = get_maps_folder(workspace_path, map_row)?;
 
         // to(maybe)do: these could be a warning.
         let wdt_file_path = get_wdt_path(&maps_folder, map_row).context("Missing Map WDT file")?;
@@ -108,18 +104,17 @@ fn resolve_map_assets(
             let adt_path = adt_entry.into_path();
             results_builder.push(adt_path.clone());
 
-            let adt = AdtFile::from_path(adt_path.clone());
-
-            if adt.is_err() {
-                warns.push(ResolveMapAssetsCmdWarn::AdtParseErr(adt_path.clone()));
-                break;
-            }
-
-            let adt = adt.unwrap();
+            let adt = match AdtFile::from_path(adt_path.clone()) {
+                Ok(adt) => adt,
+                Err(e) => {
+                    warns.push(ResolveMapAssetsCmdWarn::AdtParseErr(adt_path.clone(), format!("{:#}", e)));
+                    continue;
+                }
+            };
 
             for mcnk in &adt.mcnk.items {
                 mcnk_area_id_entries.insert(ResolveMapAssetsAreaIdEntry {
-                    area_id: mcnk.area_id,
+                    area_id: mcnk.header.area_id,
                     map_id: map_id.clone(),
                     map_name: map_row.internal_name.clone(),
                 });
@@ -468,7 +463,7 @@ fn find_and_add_loading_screen_blp(
     }
 }
 
-fn join_path_ignoring_casing(base: &Path, join: &str) -> Option<PathBuf> {
+pub(crate) fn join_path_ignoring_casing(base: &Path, join: &str) -> Option<PathBuf> {
     let parts: Vec<&str> = join.split(&['/', '\\'][..]).collect();
     let mut buf = PathBuf::new();
     buf.push(base.clone());
@@ -552,6 +547,38 @@ fn find_file_by_predicate<P: AsRef<Path>, F : Fn(&DirEntry) -> bool>(
     res
 }
 
+fn get_maps_folder(workspace_path: &Path, map_row: &MapDbcRow) -> R<PathBuf> {
+    join_path_ignoring_casing(
+        workspace_path,
+        format!("World/Maps/{}", map_row.internal_name).as_str(),
+    )
+    .context("Missing World/Maps folder in workspace")
+}
+
+/// The internal name of the map from Map.dbc along with its World/Maps folder.
+pub(crate) fn find_map_folder(workspace_path: &Path, map_id: u32) -> R<(String, PathBuf)> {
+    let map_dbc_loc = join_path_ignoring_casing(workspace_path, "DBFilesClient/Map.dbc")
+        .context("Missing Map.dbc file")?;
+    let map_dbc = load_map_dbc_from_path(map_dbc_loc.str())?;
+    let map_row = map_dbc
+        .rows
+        .iter()
+        .find(|map| map.id == map_id)
+        .context(format!("Map with id {} not found", map_id))?;
+    Ok((map_row.internal_name.clone(), get_maps_folder(workspace_path, map_row)?))
+}
+
+/// Walks the map like [resolve_map_assets] does, returns every ADT of the map sorted by path.
+pub(crate) fn find_map_adts(workspace_path: &Path, map_id: u32) -> R<Vec<PathBuf>> {
+    let (_, maps_folder) = find_map_folder(workspace_path, map_id)?;
+    let mut adt_paths: Vec<PathBuf> = find_files_by_extension(maps_folder, 2, ".adt")
+        .into_iter()
+        .map(DirEntry::into_path)
+        .collect();
+    adt_paths.sort();
+    Ok(adt_paths)
+}
+
 fn get_wdt_path(maps_folder: &PathBuf, map_row: &MapDbcRow) -> Option<PathBuf> {
     join_path_ignoring_casing(
         maps_folder,
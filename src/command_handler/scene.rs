@@ -0,0 +1,99 @@
+use crate::command_handler::resolve_map_assets::join_path_ignoring_casing;
+use crate::common::R;
+use crate::formats::adt::AdtFile;
+use crate::formats::m2::M2File;
+use crate::formats::mesh::{GltfBuilder, GltfNode, Mesh};
+use crate::formats::placement::PlacementKind;
+use crate::formats::wmo::WmoFile;
+use crate::SceneToolCmd;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportSceneCmdResult {
+    pub written: PathBuf,
+    pub m2_count: usize,
+    pub wmo_count: usize,
+    /// Models that could not be found in the workspace or parsed, placed as empty nodes.
+    pub warnings: Vec<String>,
+}
+
+pub fn handle_scene_command(cmd: &SceneToolCmd) -> R<Box<dyn erased_serde::Serialize>> {
+    let res: Box<dyn erased_serde::Serialize> = match cmd {
+        SceneToolCmd::Export(cmd) => Box::new(export_scene(&cmd.file, &cmd.workspace, &cmd.output)?),
+    };
+    Ok(res)
+}
+
+/// Writes the terrain of an ADT along with every MDDF and MODF model it places to a .glb file.
+/// Models are looked up in `workspace` and stored once, each placement being a node using them.
+pub fn export_scene(file: &str, workspace: &str, output: &str) -> R<ExportSceneCmdResult> {
+    let adt_path = Path::new(file);
+    let adt = AdtFile::from_path(adt_path)?;
+    let name = adt_path.file_stem().and_then(|it| it.to_str()).unwrap_or("scene").to_string();
+
+    let mut gltf = GltfBuilder::new();
+    let terrain_mesh = gltf.add_mesh(&format!("{} terrain", name), &adt.terrain_mesh());
+    let mut children = vec![gltf.add_node(GltfNode::new(format!("{} terrain", name), terrain_mesh))];
+
+    let mut meshes: HashMap<String, Option<usize>> = HashMap::new();
+    let mut warnings = vec![];
+    let (mut m2_count, mut wmo_count) = (0, 0);
+    for placement in adt.placements() {
+        match placement.kind {
+            PlacementKind::M2 => m2_count += 1,
+            PlacementKind::Wmo => wmo_count += 1,
+        }
+        let mesh = *meshes.entry(placement.model.to_lowercase()).or_insert_with(|| {
+            match read_model_mesh(Path::new(workspace), &placement.model, placement.kind) {
+                Ok(mesh) => gltf.add_mesh(&placement.model, &mesh),
+                Err(e) => {
+                    warnings.push(format!("{}: {:#}", placement.model, e));
+                    None
+                }
+            }
+        });
+
+        let model_name = placement.model.rsplit(&['\\', '/'][..]).next().unwrap_or_default();
+        let mut node = GltfNode::new(format!("{} ({})", model_name, placement.unique_id), mesh);
+        let (translation, rotation, scale) = placement.mesh_transform();
+        node.translation = translation;
+        node.rotation = rotation;
+        node.scale = [scale; 3];
+        children.push(gltf.add_node(node));
+    }
+
+    let mut root = GltfNode::new(name, None);
+    root.children = children;
+    gltf.add_node(root);
+    gltf.write_glb(output)?;
+
+    Ok(ExportSceneCmdResult {
+        written: PathBuf::from(output),
+        m2_count,
+        wmo_count,
+        warnings,
+    })
+}
+
+fn read_model_mesh(workspace: &Path, model: &str, kind: PlacementKind) -> R<Mesh> {
+    let path = find_model(workspace, model, kind).context("Missing in workspace")?;
+    match kind {
+        PlacementKind::M2 => M2File::read_mesh(path),
+        PlacementKind::Wmo => WmoFile::from_path(path)?.mesh(),
+    }
+}
+
+/// Resolves a model path like `resolve_map_assets` does, MDDF entries often still name the `.mdx`
+/// the `.m2` was converted from.
+fn find_model(workspace: &Path, model: &str, kind: PlacementKind) -> Option<PathBuf> {
+    join_path_ignoring_casing(workspace, model).or_else(|| {
+        if kind == PlacementKind::M2 && model.to_lowercase().ends_with(".mdx") {
+            join_path_ignoring_casing(workspace, &format!("{}.m2", &model[..model.len() - 4]))
+        } else {
+            None
+        }
+    })
+}
@@ -0,0 +1,61 @@
+use crate::command_handler::adt::{file_has_extension, get_adt_tiles_in_range};
+use crate::common::{err, R};
+use crate::formats::adt::{AdtFile, TileCoords};
+use crate::formats::mesh::{write_obj, GltfBuilder, GltfNode, Mesh};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportTerrainMeshCmdResult {
+    pub written: PathBuf,
+    pub tiles: Vec<PathBuf>,
+    pub vertex_count: usize,
+    pub triangle_count: usize,
+}
+
+/// Writes the terrain of an ADT, or of every tile of a WDT between `min_tile` and `max_tile`,
+/// as one object per tile to an .obj or .glb file. With `water`, the MH2O water surface of
+/// each tile follows as an object of its own.
+pub fn export_terrain_mesh(
+    file: &str,
+    output: &str,
+    min_tile: Option<TileCoords>,
+    max_tile: Option<TileCoords>,
+    water: bool,
+) -> R<ExportTerrainMeshCmdResult> {
+    let output_path = Path::new(output);
+    let is_glb = file_has_extension(output_path, "glb");
+    if !is_glb && !file_has_extension(output_path, "obj") {
+        return err(format!("Unsupported mesh format {:?}, expected .obj or .glb", output_path.display()));
+    }
+
+    let tiles = get_adt_tiles_in_range(Path::new(file), min_tile, max_tile)?;
+
+    let mut objects: Vec<(String, Mesh)> = Vec::with_capacity(tiles.len());
+    for (_, _, path) in &tiles {
+        let name = path.file_stem().and_then(|it| it.to_str()).unwrap_or("terrain").to_string();
+        let adt = AdtFile::from_path(path)?;
+        objects.push((name.clone(), adt.terrain_mesh()));
+        if water {
+            objects.push((format!("{}_water", name), adt.water_mesh()));
+        }
+    }
+
+    if is_glb {
+        let mut gltf = GltfBuilder::new();
+        for (name, mesh) in &objects {
+            let mesh_index = gltf.add_mesh(name, mesh);
+            gltf.add_node(GltfNode::new(name.clone(), mesh_index));
+        }
+        gltf.write_glb(output_path)?;
+    } else {
+        write_obj(output_path, &objects)?;
+    }
+
+    Ok(ExportTerrainMeshCmdResult {
+        written: output_path.to_path_buf(),
+        tiles: tiles.into_iter().map(|(_, _, path)| path).collect(),
+        vertex_count: objects.iter().map(|(_, it)| it.positions.len()).sum(),
+        triangle_count: objects.iter().map(|(_, it)| it.triangle_count()).sum(),
+    })
+}
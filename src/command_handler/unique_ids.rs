@@ -0,0 +1,215 @@
+use crate::command_handler::resolve_map_assets::find_map_adts;
+use crate::common::{err, R};
+use crate::formats::adt::{tile_coords_from_path, AdtFile};
+use crate::formats::placement::PlacementKind;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CheckUniqueIdsCmdResult {
+    pub adt_count: usize,
+    pub placement_count: usize,
+    pub collisions: Vec<UniqueIdCollision>,
+    /// ADTs rewritten by `--renumber`.
+    pub renumbered: Vec<PathBuf>,
+    /// ADTs that could not be read, left out of the check.
+    pub parse_errors: Vec<(PathBuf, String)>,
+}
+
+/// Different objects sharing a unique id.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UniqueIdCollision {
+    pub unique_id: u32,
+    pub entries: Vec<UniqueIdEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UniqueIdEntry {
+    pub adt: PathBuf,
+    pub kind: PlacementKind,
+    pub model: String,
+    /// Server coordinates.
+    pub position: [f32; 3],
+}
+
+/// An object spanning several tiles is placed in each of their ADTs under the same unique id,
+/// so entries are told apart by what they place and where.
+type ObjectKey = (PlacementKind, String, [u32; 3]);
+
+fn object_key(entry: &UniqueIdEntry) -> ObjectKey {
+    let position = [entry.position[0].to_bits(), entry.position[1].to_bits(), entry.position[2].to_bits()];
+    (entry.kind, entry.model.to_lowercase(), position)
+}
+
+/// Lists the unique ids shared by different objects of the map, `renumber` rewrites them all with
+/// [renumber_unique_ids]. Only `<Map>_<x>_<y>.adt` files are taken, ADTs that don't parse are
+/// reported and left alone, which rules out renumbering.
+pub fn check_unique_ids(workspace: &str, map_id: u32, renumber: bool) -> R<CheckUniqueIdsCmdResult> {
+    let mut adt_paths = find_map_adts(Path::new(workspace), map_id)?;
+    // split _tex0/_obj0 files don't follow the <Map>_<x>_<y>.adt naming.
+    adt_paths.retain(|it| tile_coords_from_path(it).is_some());
+
+    let mut adts = vec![];
+    let mut parse_errors = vec![];
+    for adt_path in adt_paths {
+        match AdtFile::from_path(&adt_path) {
+            Ok(adt) => adts.push((adt_path, adt)),
+            Err(e) => parse_errors.push((adt_path, format!("{:#}", e))),
+        }
+    }
+
+    let entries = unique_id_entries(&adts);
+    let placement_count = entries.len();
+    let collisions = find_collisions(entries);
+    let renumbered = if renumber {
+        let renumbered = renumber_unique_ids(&mut adts, &parse_errors)?;
+        for (adt_path, adt) in &adts {
+            if renumbered.contains(adt_path) {
+                adt.write(adt_path)?;
+            }
+        }
+        renumbered
+    } else {
+        vec![]
+    };
+
+    Ok(CheckUniqueIdsCmdResult {
+        adt_count: adts.len(),
+        placement_count,
+        collisions,
+        renumbered,
+        parse_errors,
+    })
+}
+
+fn unique_id_entries(adts: &[(PathBuf, AdtFile)]) -> Vec<(u32, UniqueIdEntry)> {
+    adts.iter()
+        .flat_map(|(adt_path, adt)| adt.placements().into_iter().map(move |placement| {
+            (placement.unique_id, UniqueIdEntry {
+                adt: adt_path.clone(),
+                kind: placement.kind,
+                model: placement.model,
+                position: [placement.x, placement.y, placement.z],
+            })
+        }))
+        .collect()
+}
+
+/// Groups the entries by unique id, keeping the ids placing more than one object.
+fn find_collisions(entries: Vec<(u32, UniqueIdEntry)>) -> Vec<UniqueIdCollision> {
+    let mut by_id: BTreeMap<u32, Vec<UniqueIdEntry>> = BTreeMap::new();
+    for (unique_id, entry) in entries {
+        by_id.entry(unique_id).or_default().push(entry);
+    }
+    by_id.into_iter()
+        .filter_map(|(unique_id, entries)| {
+            let mut objects: Vec<ObjectKey> = entries.iter().map(object_key).collect();
+            objects.sort();
+            objects.dedup();
+            if objects.len() > 1 {
+                Some(UniqueIdCollision { unique_id, entries })
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Gives every object of the map a new id counting up from 1, in path order then MDDF, MODF order,
+/// so running it twice yields the same ids. Objects spanning several tiles keep sharing theirs.
+/// Returns the paths of the ADTs whose ids changed. Refused when some ADTs of the map could not be
+/// read, their objects would keep ids the new ones may collide with.
+fn renumber_unique_ids(adts: &mut [(PathBuf, AdtFile)], parse_errors: &[(PathBuf, String)]) -> R<Vec<PathBuf>> {
+    if !parse_errors.is_empty() {
+        return err(format!(
+            "Can't renumber while {} ADTs of the map can't be read, see parse_errors without --renumber",
+            parse_errors.len()
+        ));
+    }
+    let mut ids: HashMap<ObjectKey, u32> = HashMap::new();
+    let mut renumbered = vec![];
+    for (adt_path, adt) in adts {
+        let new_ids: Vec<u32> = adt.placements()
+            .into_iter()
+            .map(|placement| {
+                let key = object_key(&UniqueIdEntry {
+                    adt: adt_path.clone(),
+                    kind: placement.kind,
+                    model: placement.model,
+                    position: [placement.x, placement.y, placement.z],
+                });
+                let next_id = ids.len() as u32 + 1;
+                *ids.entry(key).or_insert(next_id)
+            })
+            .collect();
+
+        // placements() lists MDDF entries first, then MODF entries.
+        let (m2_ids, wmo_ids) = new_ids.split_at(adt.mddf.0.len());
+        let mut changed = false;
+        for (item, id) in adt.mddf.0.iter_mut().zip(m2_ids) {
+            changed |= item.unique_id != *id;
+            item.unique_id = *id;
+        }
+        for (item, id) in adt.modf.0.iter_mut().zip(wmo_ids) {
+            changed |= item.unique_id != *id;
+            item.unique_id = *id;
+        }
+        if changed {
+            renumbered.push(adt_path.clone());
+        }
+    }
+    Ok(renumbered)
+}
+
+#[cfg(test)]
+fn unique_ids_test_map() -> Vec<(PathBuf, AdtFile)> {
+    use crate::formats::adt::{test_adt, test_doodad};
+
+    // (mmid entry, unique id, x) of every doodad, a.m2 then b.m2 in MMDX.
+    let tile = |name: &str, doodads: &[(u32, u32, f32)]| {
+        let mut adt = test_adt();
+        adt.mmdx.0 = vec!["a.m2".to_string(), "b.m2".to_string()];
+        adt.mmid.0 = vec![0, 5];
+        adt.mddf.0 = doodads.iter()
+            .map(|&(mmid_entry, unique_id, x)| test_doodad(mmid_entry, unique_id, [x, 0.0, 100.0]))
+            .collect();
+        (PathBuf::from(name), adt)
+    };
+    vec![
+        tile("Map_32_32.adt", &[(0, 5, 10.0), (0, 7, 20.0)]),
+        // the same a.m2 on the tile border under 5, another object reusing 7.
+        tile("Map_32_33.adt", &[(0, 5, 10.0), (1, 7, 30.0)]),
+    ]
+}
+
+#[cfg(test)]
+#[test]
+fn collisions_group_different_objects() {
+    let collisions = find_collisions(unique_id_entries(&unique_ids_test_map()));
+    assert_eq!(collisions.len(), 1);
+    assert_eq!(collisions[0].unique_id, 7);
+    let models: Vec<&str> = collisions[0].entries.iter().map(|it| it.model.as_str()).collect();
+    assert_eq!(models, vec!["a.m2", "b.m2"]);
+}
+
+#[cfg(test)]
+#[test]
+fn renumbering_is_stable() {
+    let mut adts = unique_ids_test_map();
+    assert_eq!(renumber_unique_ids(&mut adts, &[]).unwrap().len(), 2);
+    let ids: Vec<Vec<u32>> = adts.iter().map(|(_, adt)| adt.mddf.0.iter().map(|it| it.unique_id).collect()).collect();
+    assert_eq!(ids, vec![vec![1, 2], vec![1, 3]]);
+    assert!(find_collisions(unique_id_entries(&adts)).is_empty());
+    assert!(renumber_unique_ids(&mut adts, &[]).unwrap().is_empty());
+}
+
+#[cfg(test)]
+#[test]
+fn renumbering_refuses_unreadable_tiles() {
+    let mut adts = unique_ids_test_map();
+    let parse_errors = vec![(PathBuf::from("Map_33_32.adt"), "truncated".to_string())];
+    assert!(renumber_unique_ids(&mut adts, &parse_errors).is_err());
+    let ids: Vec<u32> = adts[0].1.mddf.0.iter().map(|it| it.unique_id).collect();
+    assert_eq!(ids, vec![5, 7]);
+}
@@ -0,0 +1,402 @@
+use crate::byte_utils::BinRead;
+use crate::command_handler::adt::{file_has_extension, get_adt_tiles};
+use crate::common::{map_file, R};
+use crate::formats::adt::{tile_coords_from_path, MAP_ORIGIN, TILE_SIZE};
+use crate::formats::chunk::{
+    string_at_offset, ChunkMcin, ChunkMcnkHeader, ChunkMcnkItem, ChunkMddf, ChunkMddfItem, ChunkMhdr, ChunkMmdx,
+    ChunkMmid, ChunkModf, ChunkModfItem, ChunkMtex, ChunkMver, ChunkMwid, ChunkMwmo, ChunkRef, ChunkVecUtils,
+};
+use crate::formats::liquid::ChunkMh2o;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Allowed drift of MCNK positions, they are stored as f32 far away from the origin.
+const POSITION_TOLERANCE: f32 = 0.1;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ValidateAdtCmdResult {
+    pub error_count: usize,
+    pub warning_count: usize,
+    pub files: Vec<AdtValidation>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AdtValidation {
+    pub file: PathBuf,
+    pub findings: Vec<ValidationFinding>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// Breaks the client or makes it read garbage.
+    Error,
+    /// Unusual, but loads.
+    Warning,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ValidationFinding {
+    pub severity: Severity,
+    pub chunk: String,
+    /// File offset of the chunk header, when the finding is about a specific chunk.
+    pub offset: Option<usize>,
+    pub message: String,
+}
+
+/// Checks the structure of an ADT, or of every tile of a WDT, instead of taking it on trust like
+/// [crate::formats::adt::AdtFile] does: MHDR and MCIN offsets, MMID/MWID string offsets, MCNK
+/// order and positions, and MDDF/MODF/MCRF indices. Chunks are parsed one by one, one that doesn't
+/// parse is reported without hiding the findings about the others. Nothing is written.
+pub fn validate_adts(file: &str) -> R<ValidateAdtCmdResult> {
+    let path = Path::new(file);
+    let is_map = file_has_extension(path, "wdt");
+    let mut files = vec![];
+    for (x, y, adt_path) in get_adt_tiles(path)? {
+        let tile = if is_map || tile_coords_from_path(&adt_path).is_some() {
+            Some((x, y))
+        } else {
+            None
+        };
+        let findings = match map_file(&adt_path) {
+            Ok(bytes) => validate_adt(&bytes, tile),
+            Err(e) => vec![finding(Severity::Error, "", None, format!("{:#}", e))],
+        };
+        files.push(AdtValidation { file: adt_path, findings });
+    }
+
+    let count = |severity| files.iter().flat_map(|it| &it.findings).filter(|it| it.severity == severity).count();
+    Ok(ValidateAdtCmdResult {
+        error_count: count(Severity::Error),
+        warning_count: count(Severity::Warning),
+        files,
+    })
+}
+
+fn finding(severity: Severity, chunk: &str, offset: Option<usize>, message: String) -> ValidationFinding {
+    ValidationFinding { severity, chunk: chunk.to_string(), offset, message }
+}
+
+/// Validates the bytes of a single ADT. `tile` is its `(x, y)` grid slot, when known.
+fn validate_adt(bytes: &[u8], tile: Option<(u32, u32)>) -> Vec<ValidationFinding> {
+    let chunks = match ChunkRef::from_bytes(bytes) {
+        Ok(chunks) => chunks,
+        Err(e) => return vec![finding(Severity::Error, "", None, format!("{:#}", e))],
+    };
+    let mut findings = vec![];
+    check_chunk_offsets(&chunks, &mut findings);
+
+    // every chunk is parsed on its own, one that doesn't parse only hides the checks needing it.
+    if let Some((offset, mver)) = parse_chunk(&chunks, "MVER", ChunkMver::from_chunk, &mut findings) {
+        if mver.map_version != 18 {
+            findings.push(finding(Severity::Warning, "MVER", Some(offset), format!("Version {} instead of 18", mver.map_version)));
+        }
+    }
+    parse_chunk(&chunks, "MTEX", ChunkMtex::from_chunk, &mut findings);
+    parse_chunk(&chunks, "MH2O", ChunkMh2o::from_chunk, &mut findings);
+    let mmdx = parse_chunk(&chunks, "MMDX", ChunkMmdx::from_chunk, &mut findings).map(|(_, it)| it.0);
+    let mmid = parse_chunk(&chunks, "MMID", ChunkMmid::from_chunk, &mut findings).map(|(offset, it)| (offset, it.0));
+    let mwmo = parse_chunk(&chunks, "MWMO", ChunkMwmo::from_chunk, &mut findings).map(|(_, it)| it.0);
+    let mwid = parse_chunk(&chunks, "MWID", ChunkMwid::from_chunk, &mut findings).map(|(offset, it)| (offset, it.0));
+    check_string_offsets("MMID", "MMDX", mmid.as_ref(), mmdx.as_deref(), &mut findings);
+    check_string_offsets("MWID", "MWMO", mwid.as_ref(), mwmo.as_deref(), &mut findings);
+
+    let mcnks = chunks.get_all_chunks_of_type("MCNK");
+    check_mcnks(&mcnks, tile, &mut findings);
+    let mddf = parse_chunk(&chunks, "MDDF", ChunkMddf::from_chunk, &mut findings);
+    let modf = parse_chunk(&chunks, "MODF", ChunkModf::from_chunk, &mut findings);
+    let refs = PlacementRefs {
+        mmid_count: mmid.map_or(0, |(_, it)| it.len()),
+        mwid_count: mwid.map_or(0, |(_, it)| it.len()),
+        mddf: mddf.map(|(offset, it)| (offset, it.0)),
+        modf: modf.map(|(offset, it)| (offset, it.0)),
+    };
+    check_placement_refs(&refs, &mcnks, &mut findings);
+    findings
+}
+
+/// Parses the first chunk of `chunk_type` along with its file offset, `None` when it is missing
+/// or doesn't parse, which is reported.
+fn parse_chunk<T>(
+    chunks: &[ChunkRef],
+    chunk_type: &str,
+    parse: fn(&ChunkRef) -> R<T>,
+    findings: &mut Vec<ValidationFinding>,
+) -> Option<(usize, T)> {
+    let chunk = chunks.get_chunk_of_type_optionally(chunk_type)?;
+    match parse(chunk) {
+        Ok(parsed) => Some((chunk.offset, parsed)),
+        Err(e) => {
+            findings.push(finding(Severity::Error, chunk_type, Some(chunk.offset), format!("{:#}", e)));
+            None
+        }
+    }
+}
+
+/// MHDR offsets and MCIN entries against where the chunks really are.
+fn check_chunk_offsets(chunks: &[ChunkRef], findings: &mut Vec<ValidationFinding>) {
+    if chunks.first().filter(|it| it.is_type("MVER")).is_none() {
+        findings.push(finding(Severity::Error, "MVER", None, "The file does not start with MVER".to_string()));
+    }
+    match chunks.get(1).filter(|it| it.is_type("MHDR")) {
+        Some(mhdr_chunk) => match ChunkMhdr::from_chunk(mhdr_chunk) {
+            Ok(mhdr) => {
+                // MHDR offsets are relative to the start of its data.
+                let base = mhdr_chunk.offset + 8;
+                let offsets = [
+                    ("MCIN", mhdr.offs_mcin, true),
+                    ("MTEX", mhdr.offs_mtex, true),
+                    ("MMDX", mhdr.offs_mmdx, true),
+                    ("MMID", mhdr.offs_mmid, true),
+                    ("MWMO", mhdr.offs_mwmo, true),
+                    ("MWID", mhdr.offs_mwid, true),
+                    ("MDDF", mhdr.offs_mddf, true),
+                    ("MODF", mhdr.offs_modf, true),
+                    ("MFBO", mhdr.offs_mfbo, false),
+                    ("MH2O", mhdr.offs_mh2o, false),
+                    ("MTXF", mhdr.offs_mtxf, false),
+                ];
+                for (chunk_type, offset, required) in offsets.iter() {
+                    let actual = chunks.get_chunk_of_type_optionally(chunk_type);
+                    match (*offset, actual) {
+                        (0, None) if *required => findings.push(finding(
+                            Severity::Error, chunk_type, None, "Missing, and MHDR has no offset for it".to_string(),
+                        )),
+                        (0, None) => {}
+                        (0, Some(actual)) => findings.push(finding(
+                            Severity::Error,
+                            chunk_type,
+                            Some(actual.offset),
+                            "MHDR has no offset for it, the client won't read it".to_string(),
+                        )),
+                        (offset, actual) => {
+                            let target = base + offset as usize;
+                            if actual.map(|it| it.offset) != Some(target) {
+                                let pointed_at = chunks.iter()
+                                    .find(|it| it.offset == target)
+                                    .map_or("no chunk start".to_string(), |it| it.get_id_as_string());
+                                findings.push(finding(
+                                    Severity::Error,
+                                    chunk_type,
+                                    actual.map(|it| it.offset),
+                                    format!("MHDR offset {:#x} points at {} (file offset {:#x})", offset, pointed_at, target),
+                                ));
+                            }
+                        }
+                    }
+                }
+            }
+            Err(e) => findings.push(finding(Severity::Error, "MHDR", Some(mhdr_chunk.offset), format!("{:#}", e))),
+        },
+        None => findings.push(finding(Severity::Error, "MHDR", None, "MHDR is not the second chunk".to_string())),
+    }
+
+    let mcnks = chunks.get_all_chunks_of_type("MCNK");
+    if mcnks.len() != 256 {
+        findings.push(finding(Severity::Error, "MCNK", None, format!("{} MCNKs instead of 256", mcnks.len())));
+    }
+    let mcin_chunk = chunks.get_chunk_of_type_optionally("MCIN");
+    let mcin = match mcin_chunk {
+        Some(mcin) if mcin.size as usize == 256 * ChunkMcin::SIZE => ChunkMcin::read_all(mcin.data).ok(),
+        Some(mcin) => {
+            findings.push(finding(
+                Severity::Error, "MCIN", Some(mcin.offset), format!("Size {} instead of {}", mcin.size, 256 * ChunkMcin::SIZE),
+            ));
+            None
+        }
+        None => None,
+    };
+    for (i, entry) in mcin.iter().flatten().enumerate() {
+        let expected = mcnks.get(i).map(|it| (it.offset as u32, it.size + 8));
+        if expected != Some((entry.offs_mcnk, entry.size)) {
+            let message = match expected {
+                Some((offset, size)) => format!(
+                    "Entry {} is offset {:#x} size {}, MCNK {} is at {:#x} with size {}",
+                    i, entry.offs_mcnk, entry.size, i, offset, size
+                ),
+                None => format!("Entry {} points at a missing MCNK", i),
+            };
+            findings.push(finding(Severity::Error, "MCIN", mcin_chunk.map(|it| it.offset), message));
+        }
+    }
+}
+
+/// MMID and MWID offsets have to land on the start of a MMDX/MWMO string.
+fn check_string_offsets(
+    offsets_type: &str,
+    strings_type: &str,
+    offsets: Option<&(usize, Vec<u32>)>,
+    strings: Option<&[String]>,
+    findings: &mut Vec<ValidationFinding>,
+) {
+    let (chunk_offset, offsets) = match offsets {
+        Some((chunk_offset, offsets)) => (*chunk_offset, offsets),
+        None => return,
+    };
+    let strings = strings.unwrap_or(&[]);
+    for (i, offset) in offsets.iter().enumerate() {
+        if string_at_offset(strings, *offset).is_none() {
+            findings.push(finding(
+                Severity::Error,
+                offsets_type,
+                Some(chunk_offset),
+                format!("Entry {} ({:#x}) is not the start of a {} string", i, offset, strings_type),
+            ));
+        }
+    }
+}
+
+/// MCNKs have to come row by row, each one a chunk further than the one before. Only their
+/// headers are read, so a broken sub-chunk doesn't get in the way.
+fn check_mcnks(mcnks: &[&ChunkRef], tile: Option<(u32, u32)>, findings: &mut Vec<ValidationFinding>) {
+    const CHUNK_SIZE: f32 = TILE_SIZE / 16.0;
+    let mut headers = vec![];
+    for (i, mcnk) in mcnks.iter().enumerate() {
+        match ChunkMcnkHeader::from_bytes(mcnk.data) {
+            Ok(header) => headers.push((i, mcnk.offset, header)),
+            Err(e) => findings.push(finding(
+                Severity::Error, "MCNK", Some(mcnk.offset), format!("MCNK {} header: {:#}", i, e),
+            )),
+        }
+    }
+    let first = match headers.first() {
+        Some((_, _, first)) => first.position,
+        None => return,
+    };
+    // the north west corner of the tile, taken from the grid slot or else the first MCNK.
+    let (corner_x, corner_y) = match tile {
+        Some((x, y)) => (MAP_ORIGIN - y as f32 * TILE_SIZE, MAP_ORIGIN - x as f32 * TILE_SIZE),
+        None => (first[0], first[1]),
+    };
+    for (i, offset, header) in &headers {
+        let (x, y) = ((i % 16) as u32, (i / 16) as u32);
+        if (header.index_x, header.index_y) != (x, y) {
+            findings.push(finding(
+                Severity::Error,
+                "MCNK",
+                Some(*offset),
+                format!("MCNK {} has index {}_{} instead of {}_{}", i, header.index_x, header.index_y, x, y),
+            ));
+        }
+        let expected = [corner_x - y as f32 * CHUNK_SIZE, corner_y - x as f32 * CHUNK_SIZE];
+        if (header.position[0] - expected[0]).abs() > POSITION_TOLERANCE
+            || (header.position[1] - expected[1]).abs() > POSITION_TOLERANCE
+        {
+            findings.push(finding(
+                Severity::Warning,
+                "MCNK",
+                Some(*offset),
+                format!(
+                    "MCNK {} is at {:?}, expected {:?} for chunk {}_{}",
+                    i, [header.position[0], header.position[1]], expected, x, y
+                ),
+            ));
+        }
+    }
+}
+
+/// What MDDF/MODF entries and MCRF references point into, along with the MDDF/MODF offsets.
+struct PlacementRefs {
+    mmid_count: usize,
+    mwid_count: usize,
+    mddf: Option<(usize, Vec<ChunkMddfItem>)>,
+    modf: Option<(usize, Vec<ChunkModfItem>)>,
+}
+
+/// MDDF/MODF entries have to point into MMID/MWID, and MCRF into MDDF/MODF. MCNKs are parsed one
+/// by one, those that don't parse are reported.
+fn check_placement_refs(refs: &PlacementRefs, mcnks: &[&ChunkRef], findings: &mut Vec<ValidationFinding>) {
+    if let Some((offset, mddf)) = &refs.mddf {
+        for (i, item) in mddf.iter().enumerate() {
+            if item.mmid_entry as usize >= refs.mmid_count {
+                findings.push(finding(
+                    Severity::Error,
+                    "MDDF",
+                    Some(*offset),
+                    format!("Entry {} (unique id {}) uses MMID entry {} of {}", i, item.unique_id, item.mmid_entry, refs.mmid_count),
+                ));
+            }
+        }
+    }
+    if let Some((offset, modf)) = &refs.modf {
+        for (i, item) in modf.iter().enumerate() {
+            if item.name_id as usize >= refs.mwid_count {
+                findings.push(finding(
+                    Severity::Error,
+                    "MODF",
+                    Some(*offset),
+                    format!("Entry {} (unique id {}) uses MWID entry {} of {}", i, item.unique_id, item.name_id, refs.mwid_count),
+                ));
+            }
+        }
+    }
+    let doodad_count = refs.mddf.as_ref().map_or(0, |(_, it)| it.len());
+    let wmo_count = refs.modf.as_ref().map_or(0, |(_, it)| it.len());
+    for (i, mcnk) in mcnks.iter().enumerate() {
+        let chunk = match ChunkMcnkItem::from_chunk(mcnk) {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                findings.push(finding(Severity::Error, "MCNK", Some(mcnk.offset), format!("MCNK {}: {:#}", i, e)));
+                continue;
+            }
+        };
+        let doodads = chunk.mcrf.doodad_refs.iter().filter(|it| **it as usize >= doodad_count).count();
+        let wmos = chunk.mcrf.map_obj_refs.iter().filter(|it| **it as usize >= wmo_count).count();
+        if doodads + wmos > 0 {
+            findings.push(finding(
+                Severity::Error,
+                "MCRF",
+                Some(mcnk.offset + chunk.header.ofs_mcrf as usize),
+                format!("MCNK {} has {} doodad and {} WMO references out of range", i, doodads, wmos),
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn validate_finds_broken_offsets() {
+    use crate::formats::adt::test_adt;
+    use crate::formats::chunk::chunks_to_bytes;
+
+    let mut adt = test_adt();
+    for chunk in &mut adt.mcnk.items {
+        let (x, y) = (chunk.header.index_x as f32, chunk.header.index_y as f32);
+        chunk.header.position = [MAP_ORIGIN - y * TILE_SIZE / 16.0, MAP_ORIGIN - x * TILE_SIZE / 16.0, 0.0];
+    }
+    let mut bytes = chunks_to_bytes(&adt.to_chunks());
+    assert!(validate_adt(&bytes, Some((0, 0))).is_empty());
+
+    // MHDR's MCIN offset, right after its flags.
+    let mhdr_data = 12 + 8;
+    bytes[mhdr_data + 4] += 4;
+    let findings = validate_adt(&bytes, Some((0, 1)));
+    assert_eq!(findings.len(), 1 + 256);
+    assert_eq!(findings[0].chunk, "MCIN");
+    assert!(findings[1..].iter().all(|it| it.severity == Severity::Warning));
+}
+
+#[cfg(test)]
+#[test]
+fn validate_reports_past_broken_mcnks() {
+    use crate::formats::adt::test_adt;
+    use crate::formats::chunk::chunks_to_bytes;
+
+    let mut adt = test_adt();
+    adt.mmdx.0 = vec!["a.m2".to_string()];
+    adt.mmid.0 = vec![0, 3];
+    let mut bytes = chunks_to_bytes(&adt.to_chunks());
+    let (mmid_offset, mcnk_offset) = {
+        let chunks = ChunkRef::from_bytes(&bytes).unwrap();
+        (chunks.get_chunk_of_type("MMID").unwrap().offset, chunks.get_all_chunks_of_type("MCNK")[5].offset)
+    };
+    // MCNK 5 loses its MCVT, right after the flags, indices, layer and doodad counts.
+    bytes[mcnk_offset + 8 + 20..mcnk_offset + 8 + 24].copy_from_slice(&[0; 4]);
+
+    let findings = validate_adt(&bytes, None);
+    let errors: Vec<(&str, Option<usize>)> = findings.iter()
+        .filter(|it| it.severity == Severity::Error)
+        .map(|it| (it.chunk.as_str(), it.offset))
+        .collect();
+    assert_eq!(errors, vec![("MMID", Some(mmid_offset)), ("MCNK", Some(mcnk_offset))]);
+}
@@ -0,0 +1,93 @@
+use crate::command_handler::alpha_map::read_grayscale_png;
+use crate::common::{err, R};
+use crate::formats::adt::{AdtFile, ChunkRange};
+use crate::formats::liquid::{ChunkMh2o, ChunkMh2oChunk, LIQUID_TILES_PER_CHUNK};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Liquid tiles per tile side, one mask pixel each.
+const TILE_LIQUID_SIZE: usize = 16 * LIQUID_TILES_PER_CHUNK;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SetWaterCmdResult {
+    pub written: PathBuf,
+    pub targeted_chunks: usize,
+    pub chunks_with_water: usize,
+}
+
+/// Sets or clears the MH2O water of the MCNKs in `chunks`, or of the liquid tiles that are white
+/// in `mask`, a 128x128 grayscale png with one pixel per liquid tile. MCNKs getting water end up
+/// with a single layer of `liquid_type` flat at `height`, with a mask that layer only covers the
+/// painted tiles and the liquid layers the MCNK already had stay on the others.
+pub fn set_water(
+    file: &str,
+    chunks: Option<ChunkRange>,
+    mask: Option<&str>,
+    liquid_type: u16,
+    height: Option<f32>,
+    clear: bool,
+    output: Option<&str>,
+) -> R<SetWaterCmdResult> {
+    let path = Path::new(file);
+    let mut adt = AdtFile::from_path(path)?;
+    let height = match (height, clear) {
+        (Some(height), false) => height,
+        (None, true) => 0.0,
+        (Some(_), true) => return err("--height can't be used together with --clear".to_string()),
+        (None, false) => return err("Either --height or --clear is needed".to_string()),
+    };
+
+    let mask_pixels = match (chunks, mask) {
+        (None, Some(mask)) => Some(read_grayscale_png(Path::new(mask), TILE_LIQUID_SIZE)?),
+        (Some(_), None) => None,
+        _ => return err("Exactly one of --chunks and --mask is needed".to_string()),
+    };
+
+    let mut mh2o = adt.mh2o.take().unwrap_or_else(|| ChunkMh2o { chunks: vec![] });
+    mh2o.chunks.resize(adt.mcnk.items.len(), ChunkMh2oChunk::default());
+    let mut targeted_chunks = 0;
+    for (chunk, liquids) in adt.mcnk.items.iter().zip(mh2o.chunks.iter_mut()) {
+        let (chunk_x, chunk_y) = (chunk.header.index_x as usize, chunk.header.index_y as usize);
+        let tiles = match (&chunks, &mask_pixels) {
+            (Some(chunks), _) if chunks.contains(chunk_x as u32, chunk_y as u32) => u64::MAX,
+            (Some(_), _) => 0,
+            (None, Some(pixels)) => {
+                let mut tiles = 0;
+                for y in 0..LIQUID_TILES_PER_CHUNK {
+                    for x in 0..LIQUID_TILES_PER_CHUNK {
+                        let pixel = (chunk_y * LIQUID_TILES_PER_CHUNK + y) * TILE_LIQUID_SIZE
+                            + chunk_x * LIQUID_TILES_PER_CHUNK + x;
+                        if pixels.get(pixel).copied().unwrap_or(0) >= 128 {
+                            tiles |= 1 << (y * LIQUID_TILES_PER_CHUNK + x);
+                        }
+                    }
+                }
+                tiles
+            }
+            (None, None) => 0,
+        };
+        if tiles == 0 {
+            continue;
+        }
+        targeted_chunks += 1;
+        if clear {
+            liquids.clear_liquid(tiles);
+        } else if mask_pixels.is_some() {
+            // a mask may only cover part of the MCNK, the liquids on its other tiles stay.
+            liquids.add_liquid(tiles, liquid_type, height, chunk);
+        } else {
+            liquids.set_liquid(tiles, liquid_type, height, chunk);
+        }
+    }
+
+    let chunks_with_water = mh2o.chunks.iter().filter(|it| !it.instances.is_empty()).count();
+    adt.mh2o = if mh2o.is_empty() { None } else { Some(mh2o) };
+    let written = output.map_or_else(|| path.to_path_buf(), PathBuf::from);
+    adt.write(&written)?;
+
+    Ok(SetWaterCmdResult {
+        written,
+        targeted_chunks,
+        chunks_with_water,
+    })
+}
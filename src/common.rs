@@ -1,5 +1,21 @@
+use anyhow::Context;
+use memmap2::Mmap;
+use std::fs::File;
+use std::path::Path;
+
 pub(crate) type R<T> = anyhow::Result<T>;
 
 pub fn err<T>(reason: String) -> R<T> {
     return Err(anyhow::anyhow!(reason));
 }
+
+/// Memory maps the file at `path`, the mapping derefs to the `&[u8]` expected by the parsers.
+pub fn map_file<P: AsRef<Path>>(path: P) -> R<Mmap> {
+    let path = path.as_ref();
+    let file = File::open(path)
+        .with_context(|| format!("Could not open file {:?}", path.display()))?;
+    // Safety: the mapping is read only, the game files are not expected to change while we parse them.
+    let mmap = unsafe { Mmap::map(&file) }
+        .with_context(|| format!("Could not map file {:?}", path.display()))?;
+    Ok(mmap)
+}
@@ -1,7 +1,15 @@
 use crate::formats::chunk::*;
+use crate::formats::liquid::ChunkMh2o;
 use serde::{Deserialize, Serialize};
-use crate::common::R;
+use crate::common::{R, map_file};
+use crate::byte_utils::BinWrite;
 use std::path::Path;
+use anyhow::Context;
+
+/// Side of a tile in world units.
+pub const TILE_SIZE: f32 = 1600.0 / 3.0;
+/// Tiles are laid out from the north west corner of the map, 32 tiles away from its center.
+pub const MAP_ORIGIN: f32 = 32.0 * TILE_SIZE;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AdtFile {
@@ -14,26 +22,46 @@ pub struct AdtFile {
     pub mwmo: ChunkMwmo,
     pub mwid: ChunkMwid,
     pub mddf: ChunkMddf,
+    pub modf: ChunkModf,
+    pub mh2o: Option<ChunkMh2o>,
     pub mcnk: ChunkMcnk,
+    /// Top level chunks without a typed field, written back as is.
+    #[serde(skip)]
+    pub unparsed_chunks: Vec<Chunk>,
 }
 
+const ADT_PARSED_CHUNKS: &[&str] = &[
+    "MVER", "MHDR", "MCIN", "MTEX", "MMDX", "MMID", "MWMO", "MWID", "MDDF", "MODF", "MH2O", "MCNK",
+];
+
 impl AdtFile {
     pub fn from_path<P: AsRef<Path>>(path: P) -> R<AdtFile> {
-        let chunks = Chunk::from_path(path)?;
-        AdtFile::new(chunks)
+        let path = path.as_ref();
+        let mapped_file = map_file(path)?;
+        ChunkRef::from_bytes(&mapped_file)
+            .and_then(|chunks| AdtFile::new(&chunks))
+            .with_context(|| format!("Failed to parse ADT file {:?}", path.display()))
     }
 
-    fn new(chunks: Vec<Chunk>) -> R<AdtFile> {
+    pub(crate) fn new(chunks: &[ChunkRef]) -> R<AdtFile> {
         let mver = chunks.get_mver_chunk()?;
-        let mhdr = chunks.get_mhdr();
-        let mcin = chunks.get_mcin();
-        let mtex = chunks.get_mtex();
-        let mmdx = chunks.get_mmdx();
-        let mmid = chunks.get_mmid();
-        let mwmo = chunks.get_mwmo();
-        let mwid = chunks.get_mwid();
-        let mddf = chunks.get_mddf();
-        let mcnk = chunks.get_mcnk();
+        let mhdr = chunks.get_mhdr()?;
+        let mcin = chunks.get_mcin()?;
+        let mtex = chunks.get_mtex()?;
+        let mmdx = chunks.get_mmdx()?;
+        let mmid = chunks.get_mmid()?;
+        let mwmo = chunks.get_mwmo()?;
+        let mwid = chunks.get_mwid()?;
+        let mddf = chunks.get_mddf()?;
+        let modf = chunks.get_modf()?.unwrap_or(ChunkModf(vec![]));
+        let mh2o = chunks.get_chunk_of_type_optionally("MH2O")
+            .map(ChunkMh2o::from_chunk)
+            .transpose()?;
+        let mcnk = chunks.get_mcnk()?;
+        let unparsed_chunks = chunks.iter()
+            .filter(|c| !ADT_PARSED_CHUNKS.iter().any(|it| c.is_type(it)))
+            .map(ChunkRef::to_owned_chunk)
+            .collect();
         Ok(AdtFile {
             mver,
             mhdr,
@@ -44,7 +72,255 @@ impl AdtFile {
             mwmo,
             mwid,
             mddf,
-            mcnk
+            modf,
+            mh2o,
+            mcnk,
+            unparsed_chunks,
         })
     }
-}
\ No newline at end of file
+
+    /// Lays the chunks out in the order the client expects, unparsed chunks go after the MCNKs.
+    /// MHDR and MCIN are recomputed to point at their new offsets.
+    pub fn to_chunks(&self) -> Vec<Chunk> {
+        let mut chunks = vec![
+            self.mver.to_chunk(),
+            self.mhdr.to_chunk(),
+            Chunk::new("MCIN", self.mcin.to_bytes()),
+            self.mtex.to_chunk(),
+            self.mmdx.to_chunk(),
+            self.mmid.to_chunk(),
+            self.mwmo.to_chunk(),
+            self.mwid.to_chunk(),
+            self.mddf.to_chunk(),
+            self.modf.to_chunk(),
+        ];
+        chunks.extend(self.mh2o.as_ref().map(ChunkMh2o::to_chunk));
+        let first_mcnk = chunks.len();
+        chunks.extend(self.mcnk.items.iter().map(ChunkMcnkItem::to_chunk));
+        chunks.extend(self.unparsed_chunks.iter().cloned());
+
+        let mut offsets = Vec::with_capacity(chunks.len());
+        let mut offset = 0;
+        for chunk in &chunks {
+            offsets.push(offset);
+            offset += 8 + chunk.data.len() as u32;
+        }
+        // MHDR offsets are relative to the start of its data, MVER always comes first.
+        let mhdr_data_offset = offsets[1] + 8;
+        let offset_of = |chunk_type: &str| {
+            chunks.iter()
+                .position(|c| c.get_id_as_string() == chunk_type)
+                .map_or(0, |i| offsets[i] - mhdr_data_offset)
+        };
+
+        let mut mhdr = self.mhdr.clone();
+        mhdr.offs_mcin = offset_of("MCIN");
+        mhdr.offs_mtex = offset_of("MTEX");
+        mhdr.offs_mmdx = offset_of("MMDX");
+        mhdr.offs_mmid = offset_of("MMID");
+        mhdr.offs_mwmo = offset_of("MWMO");
+        mhdr.offs_mwid = offset_of("MWID");
+        mhdr.offs_mddf = offset_of("MDDF");
+        mhdr.offs_modf = offset_of("MODF");
+        mhdr.offs_mfbo = offset_of("MFBO");
+        mhdr.offs_mh2o = offset_of("MH2O");
+        mhdr.offs_mtxf = offset_of("MTXF");
+
+        let mut mcin = self.mcin.clone();
+        for (i, item) in mcin.iter_mut().enumerate() {
+            match chunks.get(first_mcnk + i).filter(|c| c.get_id_as_string() == "MCNK") {
+                Some(mcnk) => {
+                    item.offs_mcnk = offsets[first_mcnk + i];
+                    item.size = 8 + mcnk.data.len() as u32;
+                }
+                None => {
+                    item.offs_mcnk = 0;
+                    item.size = 0;
+                }
+            }
+        }
+
+        chunks[1] = mhdr.to_chunk();
+        chunks[2] = Chunk::new("MCIN", mcin.to_bytes());
+        chunks
+    }
+
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> R<()> {
+        write_chunks(path, &self.to_chunks())
+    }
+
+    /// The WMO path of a MODF entry, resolved through MWID into MWMO.
+    pub fn get_wmo_path(&self, name_id: u32) -> Option<&str> {
+        let offset = *self.mwid.0.get(name_id as usize)?;
+        string_at_offset(&self.mwmo.0, offset)
+    }
+
+    /// Moves the tile from grid slot `from` to `to`, shifting MCNK positions and MDDF/MODF
+    /// positions and extents along. MH2O and MCLQ are relative to their MCNK and stay as they are.
+    pub fn relocate(&mut self, from: TileCoords, to: TileCoords) {
+        let dx = (to.x as f32 - from.x as f32) * TILE_SIZE;
+        let dy = (to.y as f32 - from.y as f32) * TILE_SIZE;
+        // tile x grows toward -Y and tile y toward -X, placements are stored as `[-y, z, -x]`.
+        for chunk in &mut self.mcnk.items {
+            chunk.header.position[0] -= dy;
+            chunk.header.position[1] -= dx;
+        }
+        for placement in &mut self.mddf.0 {
+            placement.position[0] += dx;
+            placement.position[2] += dy;
+        }
+        for placement in &mut self.modf.0 {
+            placement.position[0] += dx;
+            placement.position[2] += dy;
+            for corner in placement.extents.chunks_mut(3) {
+                corner[0] += dx;
+                corner[2] += dy;
+            }
+        }
+    }
+}
+
+/// Reads the `(x, y)` tile coordinates from a `<Map>_<x>_<y>.adt` file name.
+pub fn tile_coords_from_path<P: AsRef<Path>>(path: P) -> Option<(u32, u32)> {
+    let stem = path.as_ref().file_stem()?.to_str()?;
+    let mut parts = stem.rsplitn(3, '_');
+    let y = parts.next()?.parse().ok()?;
+    let x = parts.next()?.parse().ok()?;
+    parts.next()?;
+    Some((x, y))
+}
+
+/// `<x>_<y>` tile coordinates, as found in `<Map>_<x>_<y>.adt`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TileCoords {
+    pub x: u32,
+    pub y: u32,
+}
+
+impl std::str::FromStr for TileCoords {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<Self, &'static str> {
+        let mut parts = s.splitn(2, '_').map(|it| it.parse().ok());
+        match (parts.next().flatten(), parts.next().flatten()) {
+            (Some(x), Some(y)) if x < 64 && y < 64 => Ok(TileCoords { x, y }),
+            _ => Err("Must be <x>_<y> with both between 0 and 63, e.g. 32_48\n"),
+        }
+    }
+}
+
+/// A rectangle of MCNKs within a tile, `<x>_<y>` or `<x1>_<y1>:<x2>_<y2>` with both corners included.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ChunkRange {
+    pub min: (u32, u32),
+    pub max: (u32, u32),
+}
+
+impl ChunkRange {
+    pub fn contains(&self, x: u32, y: u32) -> bool {
+        (self.min.0..=self.max.0).contains(&x) && (self.min.1..=self.max.1).contains(&y)
+    }
+}
+
+impl std::str::FromStr for ChunkRange {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<Self, &'static str> {
+        const ERROR: &str = "Must be <x>_<y> or <x1>_<y1>:<x2>_<y2> with every value between 0 and 15, e.g. 0_0:7_15\n";
+        let mut corners = s.splitn(2, ':').map(|corner| match corner.parse::<TileCoords>() {
+            Ok(TileCoords { x, y }) if x < 16 && y < 16 => Ok((x, y)),
+            _ => Err(ERROR),
+        });
+        let first = corners.next().ok_or(ERROR)??;
+        let second = corners.next().transpose()?.unwrap_or(first);
+        Ok(ChunkRange {
+            min: (first.0.min(second.0), first.1.min(second.1)),
+            max: (first.0.max(second.0), first.1.max(second.1)),
+        })
+    }
+}
+
+/// A flat tile with empty MCNKs, MCNK (x, y) sits at height `x + y`.
+#[cfg(test)]
+pub fn test_adt() -> AdtFile {
+    use crate::byte_utils::BinRead;
+
+    let items = (0..256u32)
+        .map(|i| {
+            let mut header = ChunkMcnkHeader::from_bytes(&[0; ChunkMcnkHeader::SIZE]).unwrap();
+            header.index_x = i % 16;
+            header.index_y = i / 16;
+            header.position = [0.0, 0.0, (i % 16 + i / 16) as f32];
+            ChunkMcnkItem {
+                header,
+                mcvt: ChunkMcvt { heights: vec![0.0; MCNK_VERTEX_COUNT] },
+                mcnr: ChunkMcnr { normals: vec![[0, 0, 127]; MCNK_VERTEX_COUNT], padding: vec![0; 13] },
+                mcly: ChunkMcly(vec![]),
+                mcrf: ChunkMcrf { doodad_refs: vec![], map_obj_refs: vec![] },
+                mcsh: None,
+                mcal: None,
+                mclq: None,
+                mcse: Some(ChunkMcse(vec![])),
+                mccv: None,
+            }
+        })
+        .collect();
+    AdtFile {
+        mver: ChunkMver { map_version: 18 },
+        mhdr: ChunkMhdr::from_bytes(&[0; ChunkMhdr::SIZE]).unwrap(),
+        mcin: ChunkMcin::read_all(&[0; 256 * ChunkMcin::SIZE]).unwrap(),
+        mtex: ChunkMtex(vec!["tileset\\generic\\black.blp".to_string()]),
+        mmdx: ChunkMmdx(vec![]),
+        mmid: ChunkMmid(vec![]),
+        mwmo: ChunkMwmo(vec![]),
+        mwid: ChunkMwid(vec![]),
+        mddf: ChunkMddf(vec![]),
+        modf: ChunkModf(vec![]),
+        mh2o: None,
+        mcnk: ChunkMcnk { items },
+        unparsed_chunks: vec![Chunk::new("MFBO", vec![0; 36])],
+    }
+}
+
+/// A MDDF entry of an unrotated, unscaled doodad.
+#[cfg(test)]
+pub fn test_doodad(mmid_entry: u32, unique_id: u32, position: [f32; 3]) -> ChunkMddfItem {
+    ChunkMddfItem {
+        mmid_entry,
+        unique_id,
+        position,
+        rotation: [0.0; 3],
+        scale: 1024,
+        flags: 0,
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn adt_write_round_trip() {
+    let bytes = chunks_to_bytes(&test_adt().to_chunks());
+    let chunks = ChunkRef::from_bytes(&bytes).unwrap();
+    let adt = AdtFile::new(&chunks).unwrap();
+
+    let mcin = chunks.get_chunk_of_type("MCIN").unwrap();
+    assert_eq!(adt.mhdr.offs_mcin as usize + 0x14 + 8, mcin.offset + 8);
+    let mcnk = chunks.get_all_chunks_of_type("MCNK")[17].offset;
+    assert_eq!(adt.mcin[17].offs_mcnk as usize, mcnk);
+    assert_eq!(adt.mcnk.items[17].header.index_x, 1);
+    assert_eq!(adt.mcnk.items[17].mcnr.padding.len(), 13);
+    assert_eq!(chunks_to_bytes(&adt.to_chunks()), bytes);
+}
+
+#[cfg(test)]
+#[test]
+fn relocate_moves_world_positions() {
+    let mut adt = test_adt();
+    adt.mcnk.items[0].header.position = [MAP_ORIGIN - 48.0 * TILE_SIZE, MAP_ORIGIN - 32.0 * TILE_SIZE, 5.0];
+    adt.mddf.0.push(test_doodad(0, 1, [32.5 * TILE_SIZE, 5.0, 48.5 * TILE_SIZE]));
+
+    adt.relocate(TileCoords { x: 32, y: 48 }, TileCoords { x: 40, y: 40 });
+    let near = |a: f32, b: f32| (a - b).abs() < 1e-2;
+    let position = adt.mcnk.items[0].header.position;
+    assert!(near(position[0], MAP_ORIGIN - 40.0 * TILE_SIZE) && near(position[1], MAP_ORIGIN - 40.0 * TILE_SIZE));
+    assert_eq!(position[2], 5.0);
+    let position = adt.mddf.0[0].position;
+    assert!(near(position[0], 40.5 * TILE_SIZE) && near(position[2], 40.5 * TILE_SIZE));
+}
@@ -0,0 +1,236 @@
+use crate::common::{err, R};
+use crate::formats::chunk::{
+    ChunkMcal, ChunkMcly, ChunkMclyItem, ChunkMcnkItem, MCLY_FLAG_ALPHA_MAP_COMPRESSED, MCLY_FLAG_USE_ALPHA_MAP,
+    MCNK_FLAG_DO_NOT_FIX_ALPHA_MAP,
+};
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+/// Alpha maps are 64x64 samples per MCNK.
+pub const ALPHA_MAP_SIZE: usize = 64;
+const ALPHA_MAP_LEN: usize = ALPHA_MAP_SIZE * ALPHA_MAP_SIZE;
+
+/// How the alpha map of a single MCLY layer is stored in MCAL.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum AlphaMapFormat {
+    /// 2048 bytes, two 4 bit samples per byte, low nibble first.
+    Uncompressed4Bit,
+    /// 4096 bytes, one sample per byte.
+    Uncompressed8Bit,
+    /// 8 bit samples, run length encoded. Only used with big alpha.
+    Compressed,
+}
+
+impl std::str::FromStr for AlphaMapFormat {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<Self, &'static str> {
+        match s.to_lowercase().as_str() {
+            "4bit" => Ok(Self::Uncompressed4Bit),
+            "8bit" => Ok(Self::Uncompressed8Bit),
+            "compressed" => Ok(Self::Compressed),
+            _ => Err("Must be one of ( 4bit, 8bit, compressed )\n"),
+        }
+    }
+}
+
+impl AlphaMapFormat {
+    /// Whether the WDT has to flag big alpha for alpha maps in this format to be read as such.
+    pub fn needs_big_alpha(self) -> bool {
+        self != AlphaMapFormat::Uncompressed4Bit
+    }
+}
+
+impl ChunkMcnkItem {
+    /// Decodes the alpha map of every MCLY layer to 64x64 samples, row by row. The base layer and
+    /// layers without [MCLY_FLAG_USE_ALPHA_MAP] have none. `big_alpha` comes from the WDT
+    /// ([crate::formats::chunk::ChunkMphd::has_big_alpha]), it is guessed from the layer sizes when unknown.
+    pub fn alpha_maps(&self, big_alpha: Option<bool>) -> R<Vec<Option<Vec<u8>>>> {
+        let mut alpha_maps = Vec::with_capacity(self.mcly.0.len());
+        for i in 0..self.mcly.0.len() {
+            let (data, format) = match self.get_alpha_map_data(i, big_alpha)? {
+                Some(it) => it,
+                None => {
+                    alpha_maps.push(None);
+                    continue;
+                }
+            };
+            let fix_alpha_map = self.header.flags & MCNK_FLAG_DO_NOT_FIX_ALPHA_MAP == 0;
+            let alpha_map = decode_alpha_map(data, format, fix_alpha_map)
+                .with_context(|| format!("Failed to decode the alpha map of MCLY layer {}", i))?;
+            alpha_maps.push(Some(alpha_map));
+        }
+        Ok(alpha_maps)
+    }
+
+    /// How the alpha map of MCLY layer `layer` is stored, see [ChunkMcnkItem::alpha_maps].
+    pub fn alpha_map_format(&self, layer: usize, big_alpha: Option<bool>) -> R<Option<AlphaMapFormat>> {
+        Ok(self.get_alpha_map_data(layer, big_alpha)?.map(|(_, format)| format))
+    }
+
+    /// Replaces MCLY and MCAL. Every layer comes with its 64x64 alpha map, except for the base layer.
+    /// The MCAL offsets and alpha map flags of the layers are overwritten, their other fields are kept.
+    pub fn set_alpha_maps(&mut self, layers: Vec<(ChunkMclyItem, Option<Vec<u8>>)>, format: AlphaMapFormat) {
+        let mut mcly = Vec::with_capacity(layers.len());
+        let mut mcal = vec![];
+        for (mut layer, alpha_map) in layers {
+            layer.flags &= !(MCLY_FLAG_USE_ALPHA_MAP | MCLY_FLAG_ALPHA_MAP_COMPRESSED);
+            layer.offset_in_mcal = 0;
+            if let Some(alpha_map) = alpha_map.filter(|_| !mcly.is_empty()) {
+                layer.flags |= MCLY_FLAG_USE_ALPHA_MAP;
+                if format == AlphaMapFormat::Compressed {
+                    layer.flags |= MCLY_FLAG_ALPHA_MAP_COMPRESSED;
+                }
+                layer.offset_in_mcal = mcal.len() as u32;
+                mcal.append(&mut encode_alpha_map(&alpha_map, format));
+            }
+            mcly.push(layer);
+        }
+        self.mcly = ChunkMcly(mcly);
+        self.mcal = Some(ChunkMcal { data: mcal });
+        if format == AlphaMapFormat::Uncompressed4Bit {
+            // every one of the 64x64 samples is written.
+            self.header.flags |= MCNK_FLAG_DO_NOT_FIX_ALPHA_MAP;
+        }
+    }
+
+    fn get_alpha_map_data(&self, layer: usize, big_alpha: Option<bool>) -> R<Option<(&[u8], AlphaMapFormat)>> {
+        let layers = &self.mcly.0;
+        let mcal = self.mcal.as_ref().map_or(&[][..], |it| it.data.as_slice());
+        let item = match layers.get(layer) {
+            Some(item) if layer > 0 && item.flags & MCLY_FLAG_USE_ALPHA_MAP != 0 => item,
+            _ => return Ok(None),
+        };
+        let start = item.offset_in_mcal as usize;
+        // layers are stored in order, the next one starting where this one ends.
+        let end = layers[layer + 1..].iter()
+            .find(|it| it.flags & MCLY_FLAG_USE_ALPHA_MAP != 0)
+            .map_or(mcal.len(), |it| it.offset_in_mcal as usize);
+        let data = mcal.get(start..end.max(start))
+            .with_context(|| format!("MCLY layer {} points outside of MCAL ({:#x})", layer, start))?;
+
+        let format = if item.flags & MCLY_FLAG_ALPHA_MAP_COMPRESSED != 0 {
+            AlphaMapFormat::Compressed
+        } else if big_alpha.unwrap_or(data.len() >= ALPHA_MAP_LEN) {
+            AlphaMapFormat::Uncompressed8Bit
+        } else {
+            AlphaMapFormat::Uncompressed4Bit
+        };
+        Ok(Some((data, format)))
+    }
+}
+
+/// Decodes one alpha map to 64x64 8 bit samples. With `fix_alpha_map`, 4 bit maps only hold
+/// 63x63 meaningful samples and the last row and column repeat the ones before them.
+pub fn decode_alpha_map(data: &[u8], format: AlphaMapFormat, fix_alpha_map: bool) -> R<Vec<u8>> {
+    match format {
+        AlphaMapFormat::Uncompressed8Bit => match data.get(..ALPHA_MAP_LEN) {
+            Some(data) => Ok(data.to_vec()),
+            None => err(format!("Expected {} bytes of alpha map but got {}", ALPHA_MAP_LEN, data.len())),
+        },
+        AlphaMapFormat::Uncompressed4Bit => {
+            let data = match data.get(..ALPHA_MAP_LEN / 2) {
+                Some(data) => data,
+                None => return err(format!("Expected {} bytes of alpha map but got {}", ALPHA_MAP_LEN / 2, data.len())),
+            };
+            let mut alpha_map: Vec<u8> = data.iter()
+                .flat_map(|it| [(it & 0x0F) * 17, (it >> 4) * 17])
+                .collect();
+            if fix_alpha_map {
+                for row in 0..ALPHA_MAP_SIZE {
+                    let i = row * ALPHA_MAP_SIZE + ALPHA_MAP_SIZE - 1;
+                    alpha_map[i] = alpha_map[i - 1];
+                }
+                let (rows, last_row) = alpha_map.split_at_mut(ALPHA_MAP_LEN - ALPHA_MAP_SIZE);
+                last_row.copy_from_slice(&rows[rows.len() - ALPHA_MAP_SIZE..]);
+            }
+            Ok(alpha_map)
+        }
+        AlphaMapFormat::Compressed => {
+            let mut alpha_map = Vec::with_capacity(ALPHA_MAP_LEN);
+            let mut offset = 0;
+            while alpha_map.len() < ALPHA_MAP_LEN {
+                let control = *data.get(offset).context("Compressed alpha map ends early")?;
+                let count = (control & 0x7F) as usize;
+                offset += 1;
+                if control & 0x80 != 0 {
+                    let value = *data.get(offset).context("Compressed alpha map ends early")?;
+                    alpha_map.resize(alpha_map.len() + count, value);
+                    offset += 1;
+                } else {
+                    let values = data.get(offset..offset + count).context("Compressed alpha map ends early")?;
+                    alpha_map.extend_from_slice(values);
+                    offset += count;
+                }
+            }
+            // runs may not cross the end of a row, but tolerate files where they do.
+            alpha_map.truncate(ALPHA_MAP_LEN);
+            Ok(alpha_map)
+        }
+    }
+}
+
+/// Inverse of [decode_alpha_map], 4 bit maps are written at their full 64x64 size.
+pub fn encode_alpha_map(alpha_map: &[u8], format: AlphaMapFormat) -> Vec<u8> {
+    match format {
+        AlphaMapFormat::Uncompressed8Bit => alpha_map.to_vec(),
+        AlphaMapFormat::Uncompressed4Bit => alpha_map.chunks(2)
+            .map(|it| {
+                let low = (it[0] as u16 + 8) / 17;
+                let high = it.get(1).map_or(0, |it| (*it as u16 + 8) / 17);
+                (low | high << 4) as u8
+            })
+            .collect(),
+        AlphaMapFormat::Compressed => {
+            let mut data = vec![];
+            for row in alpha_map.chunks(ALPHA_MAP_SIZE) {
+                let mut i = 0;
+                while i < row.len() {
+                    let run = row[i..].iter().take(0x7F).take_while(|it| **it == row[i]).count();
+                    if run >= 3 {
+                        data.push(0x80 | run as u8);
+                        data.push(row[i]);
+                        i += run;
+                        continue;
+                    }
+                    // copy until the next run worth filling.
+                    let start = i;
+                    while i < row.len() && i - start < 0x7F {
+                        if row[i..].iter().take(3).filter(|it| **it == row[i]).count() == 3 {
+                            break;
+                        }
+                        i += 1;
+                    }
+                    data.push((i - start) as u8);
+                    data.extend_from_slice(&row[start..i]);
+                }
+            }
+            data
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn alpha_map_decoding() {
+    let mut compressed = vec![];
+    for _ in 0..64 {
+        compressed.extend_from_slice(&[0x80 | 32, 0xFF, 2, 0x10, 0x20, 0x80 | 30, 0x00]);
+    }
+    let alpha_map = decode_alpha_map(&compressed, AlphaMapFormat::Compressed, true).unwrap();
+    assert_eq!(alpha_map.len(), ALPHA_MAP_LEN);
+    assert_eq!(&alpha_map[30..36], &[0xFF, 0xFF, 0x10, 0x20, 0, 0]);
+
+    let mut packed = vec![0x21; ALPHA_MAP_LEN / 2];
+    packed[31] = 0xF1;
+    let alpha_map = decode_alpha_map(&packed, AlphaMapFormat::Uncompressed4Bit, true).unwrap();
+    assert_eq!(&alpha_map[..2], &[17, 34]);
+    assert_eq!(alpha_map[63], 17);
+    assert_eq!(&alpha_map[ALPHA_MAP_LEN - ALPHA_MAP_SIZE..], &alpha_map[ALPHA_MAP_LEN - 2 * ALPHA_MAP_SIZE..ALPHA_MAP_LEN - ALPHA_MAP_SIZE]);
+    let alpha_map = decode_alpha_map(&packed, AlphaMapFormat::Uncompressed4Bit, false).unwrap();
+    assert_eq!(alpha_map[63], 255);
+    assert_eq!(encode_alpha_map(&alpha_map, AlphaMapFormat::Uncompressed4Bit), packed);
+
+    let alpha_map: Vec<u8> = (0..ALPHA_MAP_LEN).map(|i| if i % 7 < 4 { 0x40 } else { i as u8 }).collect();
+    let compressed = encode_alpha_map(&alpha_map, AlphaMapFormat::Compressed);
+    assert_eq!(decode_alpha_map(&compressed, AlphaMapFormat::Compressed, true).unwrap(), alpha_map);
+}
@@ -1,52 +1,115 @@
-use std::str::from_utf8;
 use std::fs::File;
-use std::io::Read;
+use std::io::Write;
 use crate::byte_utils::*;
+use crate::bin_layout;
 use std::convert::TryInto;
 use anyhow::Context;
 use serde::{Serialize, Deserialize};
-use crate::common::{R};
+use crate::common::{R, err, map_file};
 use std::path::Path;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Chunk {
     pub id: [u8; 4],
     pub size: u32,
     pub data: Vec<u8>,
 }
 
-impl Chunk {
-    pub fn get_id_as_string(&self) -> String { from_utf8(&self.id).unwrap().chars().rev().collect() }
+/// Borrowed view of a chunk, `data` points straight into the parsed file.
+/// `offset` is the position of the chunk header within that file.
+#[derive(Debug, Clone, Copy)]
+pub struct ChunkRef<'a> {
+    pub id: [u8; 4],
+    pub size: u32,
+    pub offset: usize,
+    pub data: &'a [u8],
+}
 
-    pub fn from_path<P: AsRef<Path>>(path: P) -> R<Vec<Chunk>> {
-        let path = path.as_ref();
-        let mut file = File::open(path)
-            .with_context(|| format!("Could not open file {:?}", path.display()))?;
-        let file_size = file.metadata()?.len() as usize;
-        let mut buffered_file = Vec::with_capacity(file_size);
-        file.read_to_end(&mut buffered_file)?;
+impl<'a> ChunkRef<'a> {
+    pub fn get_id_as_string(&self) -> String { String::from_utf8_lossy(&self.id).chars().rev().collect() }
+
+    /// Compares against the readable FourCC without allocating.
+    pub fn is_type(&self, chunk_type: &str) -> bool {
+        chunk_type.len() == 4 && self.id.iter().rev().eq(chunk_type.as_bytes())
+    }
 
+    /// Human readable location of this chunk, used as error context.
+    pub fn describe(&self) -> String {
+        format!("Chunk {} at offset {:#x}", self.get_id_as_string(), self.offset)
+    }
+
+    pub fn expect_type(&self, chunk_type: &str) -> R<()> {
+        if self.is_type(chunk_type) {
+            Ok(())
+        } else {
+            err(format!("{}: expected a {} chunk", self.describe(), chunk_type))
+        }
+    }
+
+    pub fn expect_size(&self, expected: usize) -> R<()> {
+        if self.size as usize == expected {
+            Ok(())
+        } else {
+            err(format!("{}: expected size {} but got {}", self.describe(), expected, self.size))
+        }
+    }
+
+    pub fn expect_size_at_least(&self, expected: usize) -> R<()> {
+        if self.size as usize >= expected {
+            Ok(())
+        } else {
+            err(format!("{}: expected at least {} bytes but got {}", self.describe(), expected, self.size))
+        }
+    }
+
+    pub fn expect_size_multiple_of(&self, record_size: usize) -> R<()> {
+        if (self.size as usize).is_multiple_of(record_size) {
+            Ok(())
+        } else {
+            err(format!(
+                "{}: expected size to be a multiple of {} but got {}",
+                self.describe(),
+                record_size,
+                self.size
+            ))
+        }
+    }
+
+    pub fn get_null_terminated_strings(&self) -> R<Vec<String>> {
+        self.data.get_null_terminated_strings()
+            .with_context(|| format!("{}: invalid string table", self.describe()))
+    }
+
+    pub fn from_bytes(bytes: &'a [u8]) -> R<Vec<ChunkRef<'a>>> {
         let mut offset: usize = 0;
-        let mut builder: Vec<Chunk> = Vec::new();
-        let max_size = buffered_file.len();
+        let mut builder: Vec<ChunkRef<'a>> = Vec::new();
+        let max_size = bytes.len();
 
         loop {
             if offset + 4 > max_size {
                 break;
             }
-            let id: [u8; 4] = buffered_file[offset..offset + 4].try_into()?;
-            let size = Chunk::get_chunk_size(&id, &buffered_file, offset)?;
-            let data = buffered_file[offset + 8..offset + 8 + size].to_vec();
-
-            offset += 8 + size;
-
-            builder.push(Chunk {
+            let id: [u8; 4] = bytes[offset..offset + 4].try_into()?;
+            let size = bytes.get_u32(offset + 4)? as usize;
+            let data = bytes.get(offset + 8..offset + 8 + size)
+                .with_context(|| format!(
+                    "Chunk {} at offset {:#x}: expected size {} but only {} bytes are left",
+                    String::from_utf8_lossy(&id).chars().rev().collect::<String>(),
+                    offset,
+                    size,
+                    max_size.saturating_sub(offset + 8)
+                ))?;
+
+            builder.push(ChunkRef {
                 id,
                 size: size as u32,
+                offset,
                 data,
             });
 
-            if offset == buffered_file.len() {
+            offset += 8 + size;
+
+            if offset == bytes.len() {
                 break;
             }
         }
@@ -54,318 +117,390 @@ impl Chunk {
         Ok(builder)
     }
 
-    fn get_chunk_size(chunk_id: &[u8; 4], buffered_file: &Vec<u8>, offset: usize) -> R<usize> {
-        // The MOGP chunk breaks our parsing system
-        // by having an unexpected size provided in the header..
-        // This function adds special casing for a chunk of this type.
-        // If any other chunk happens to have a similar issue, just add
-        // the correct [ChunkFix] entry into the `const` array [#CHUNK_SIZE_FIXES]
-        struct ChunkFix {
-            chunk_id: [u8; 4],
-            chunk_size_correction: usize,
+    /// Parses the chunks nested in this chunk's data, starting `skip` bytes in.
+    /// Offsets of the nested chunks stay relative to the file.
+    pub fn nested(&self, skip: usize) -> R<Vec<ChunkRef<'a>>> {
+        let data = self.data.get(skip..)
+            .with_context(|| format!("{}: expected at least {} bytes but got {}", self.describe(), skip, self.size))?;
+        let mut chunks = ChunkRef::from_bytes(data)
+            .with_context(|| format!("{}: invalid nested chunks", self.describe()))?;
+        for chunk in &mut chunks {
+            chunk.offset += self.offset + 8 + skip;
         }
+        Ok(chunks)
+    }
 
-        const CHUNK_SIZE_FIXES: &[ChunkFix] = &[
-            ChunkFix {
-                chunk_id: [80, 71, 79, 77], // MOGP
-                chunk_size_correction: 68,
-            },
-            ChunkFix {
-                chunk_id: [1, 0, 0, 0], // no clue
-                chunk_size_correction: 40,
-            }
-        ];
+    pub fn to_owned_chunk(&self) -> Chunk {
+        Chunk {
+            id: self.id,
+            size: self.size,
+            data: self.data.to_vec(),
+        }
+    }
+}
+
+impl Chunk {
+    pub fn get_id_as_string(&self) -> String { String::from_utf8_lossy(&self.id).chars().rev().collect() }
+
+    pub fn new(chunk_type: &str, data: Vec<u8>) -> Chunk {
+        let mut id = [0u8; 4];
+        id.copy_from_slice(chunk_type.as_bytes());
+        id.reverse();
+        Chunk {
+            id,
+            size: data.len() as u32,
+            data,
+        }
+    }
+
+    pub fn from_path<P: AsRef<Path>>(path: P) -> R<Vec<Chunk>> {
+        let mapped_file = map_file(path)?;
+        Chunk::from_bytes(&mapped_file)
+    }
 
-        let requires_fix = CHUNK_SIZE_FIXES
+    pub fn from_bytes(bytes: &[u8]) -> R<Vec<Chunk>> {
+        let chunks = ChunkRef::from_bytes(bytes)?
             .iter()
-            .find(|&it| it.chunk_id.ends_with(chunk_id));
+            .map(ChunkRef::to_owned_chunk)
+            .collect();
+        Ok(chunks)
+    }
 
-        match requires_fix {
-            Some(fix) => {
-                // use correction
-                Ok(fix.chunk_size_correction)
-            }
-            None => {
+    pub fn as_chunk_ref(&self) -> ChunkRef<'_> {
+        ChunkRef {
+            id: self.id,
+            size: self.size,
+            offset: 0,
+            data: &self.data,
+        }
+    }
 
-                // usual size extraction, 99% of the chunks.
-                let size = buffered_file[offset + 4..offset + 8].to_vec().get_u32(0)? as usize;
-                Ok(size)
-            }
+    /// Serializes a single chunk: the reversed FourCC, the data length and the data itself.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(8 + self.data.len());
+        bytes.extend_from_slice(&self.id);
+        bytes.extend_from_slice(&(self.data.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&self.data);
+        bytes
+    }
+}
+
+/// Inverse of [Chunk::from_bytes].
+pub fn chunks_to_bytes(chunks: &[Chunk]) -> Vec<u8> {
+    let total_size: usize = chunks.iter().map(|c| 8 + c.data.len()).sum();
+    let mut bytes = Vec::with_capacity(total_size);
+    for chunk in chunks {
+        bytes.append(&mut chunk.to_bytes());
+    }
+    bytes
+}
+
+/// Finds the string starting at `offset` in a table parsed by [ChunkRef::get_null_terminated_strings],
+/// which is how MMID and MWID point into MMDX and MWMO.
+pub fn string_at_offset(strings: &[String], offset: u32) -> Option<&str> {
+    let mut current = 0;
+    for string in strings {
+        if current == offset as usize {
+            return Some(string);
         }
+        current += string.len() + 1;
     }
+    None
 }
 
-pub trait ChunkVecUtils {
-    fn get_chunk_of_type_optionally(&self, chunk_type: &str) -> Option<&Chunk>;
-    fn get_all_chunks_of_type(&self, chunk_type: &str) -> Vec<&Chunk>;
-    fn get_chunk_of_type(&self, chunk_type: &str) -> &Chunk;
-    fn get_chunk_of_type_checked(&self, chunk_type: &str) -> R<&Chunk>;
+/// Inverse of [ChunkRef::get_null_terminated_strings].
+pub fn strings_to_bytes(strings: &[String]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(strings.iter().map(|it| it.len() + 1).sum());
+    for string in strings {
+        bytes.extend_from_slice(string.as_bytes());
+        bytes.push(0);
+    }
+    bytes
+}
+
+pub fn write_chunks<P: AsRef<Path>>(path: P, chunks: &[Chunk]) -> R<()> {
+    let path = path.as_ref();
+    let mut file = File::create(path)
+        .with_context(|| format!("Could not create file {:?}", path.display()))?;
+    file.write_all(&chunks_to_bytes(chunks))
+        .with_context(|| format!("Could not write file {:?}", path.display()))?;
+    Ok(())
+}
+
+/// Every chunk type with a typed parser, either in [ChunkVecUtils] or a format specific extension.
+pub const PARSED_CHUNK_TYPES: &[&str] = &[
+    "MVER", "MPHD", "MAIN", "MODF", "MHDR", "MCIN", "MTEX", "MMDX", "MMID", "MWMO", "MWID", "MDDF",
+    "MH2O", "MCNK", "MCVT", "MCNR", "MCLY", "MCRF", "MCSH", "MCAL", "MCLQ", "MCSE", "MCCV",
+    "MOTX", "MOGN", "MODN", "MOHD", "MOGI", "MOGP", "MOLR", "MOVT", "MOVI", "MONR", "MOTV", "MOPY",
+];
+
+pub trait ChunkVecUtils<'a> {
+    fn get_chunk_of_type_optionally(&self, chunk_type: &str) -> Option<&ChunkRef<'a>>;
+    fn get_all_chunks_of_type(&self, chunk_type: &str) -> Vec<&ChunkRef<'a>>;
+    fn get_chunk_of_type(&self, chunk_type: &str) -> R<&ChunkRef<'a>>;
     fn get_mver_chunk(&self) -> R<ChunkMver>;
-    fn get_mphd_chunk(&self) -> ChunkMphd;
-    fn get_main(&self) -> ChunkMain;
-    fn get_modf(&self) -> Option<ChunkModf>;
-    fn get_mhdr(&self) -> ChunkMhdr;
-    fn get_mcin(&self) -> Vec<ChunkMcin>;
-    fn get_mtex(&self) -> ChunkMtex;
-    fn get_mmdx(&self) -> ChunkMmdx;
-    fn get_mmid(&self) -> ChunkMmid;
-    fn get_mwmo(&self) -> ChunkMwmo;
-    fn get_mwid(&self) -> ChunkMwid;
-    fn get_mddf(&self) -> ChunkMddf;
-    fn get_motx(&self) -> ChunkMotx;
-    fn get_mogn(&self) -> ChunkMogn;
-    fn get_modn(&self) -> ChunkModn;
-    fn get_mohd(&self) -> ChunkMohd;
-    fn get_molr(&self) -> Option<ChunkMolr>;
-    fn get_mcnk(&self) -> ChunkMcnk;
-}
-
-impl ChunkVecUtils for Vec<Chunk> {
-    fn get_chunk_of_type_optionally(&self, chunk_type: &str) -> Option<&Chunk> {
-        self.iter().find(|it| it.get_id_as_string() == chunk_type.to_owned())
-    }
-
-    fn get_all_chunks_of_type(&self, chunk_type: &str) -> Vec<&Chunk> {
-        self.iter()
-            .filter(|it| it.get_id_as_string() == chunk_type.to_owned())
-            .collect()
+    fn get_mphd_chunk(&self) -> R<ChunkMphd>;
+    fn get_main(&self) -> R<ChunkMain>;
+    fn get_modf(&self) -> R<Option<ChunkModf>>;
+    fn get_mhdr(&self) -> R<ChunkMhdr>;
+    fn get_mcin(&self) -> R<Vec<ChunkMcin>>;
+    fn get_mtex(&self) -> R<ChunkMtex>;
+    fn get_mmdx(&self) -> R<ChunkMmdx>;
+    fn get_mmid(&self) -> R<ChunkMmid>;
+    fn get_mwmo(&self) -> R<ChunkMwmo>;
+    fn get_mwid(&self) -> R<ChunkMwid>;
+    fn get_mddf(&self) -> R<ChunkMddf>;
+    fn get_motx(&self) -> R<ChunkMotx>;
+    fn get_mogn(&self) -> R<ChunkMogn>;
+    fn get_modn(&self) -> R<ChunkModn>;
+    fn get_mohd(&self) -> R<ChunkMohd>;
+    fn get_molr(&self) -> R<Option<ChunkMolr>>;
+    fn get_mcnk(&self) -> R<ChunkMcnk>;
+}
+
+impl<'a> ChunkVecUtils<'a> for [ChunkRef<'a>] {
+    fn get_chunk_of_type_optionally(&self, chunk_type: &str) -> Option<&ChunkRef<'a>> {
+        self.iter().find(|it| it.is_type(chunk_type))
     }
 
-    fn get_chunk_of_type(&self, chunk_type: &str) -> &Chunk {
-        self.get_chunk_of_type_optionally(chunk_type)
-            .expect(format!("Failed to find chunk of type {}", chunk_type).as_str())
+    fn get_all_chunks_of_type(&self, chunk_type: &str) -> Vec<&ChunkRef<'a>> {
+        self.iter()
+            .filter(|it| it.is_type(chunk_type))
+            .collect()
     }
 
-    fn get_chunk_of_type_checked(&self, chunk_type: &str) -> R<&Chunk> {
+    fn get_chunk_of_type(&self, chunk_type: &str) -> R<&ChunkRef<'a>> {
         self.get_chunk_of_type_optionally(chunk_type)
             .with_context(|| format!("Failed to find chunk of type {}", chunk_type))
     }
 
-    fn get_mver_chunk(&self) -> R<ChunkMver> {
-        let chunk = self.get_chunk_of_type_checked("MVER")?;
-        ChunkMver::from_chunk(chunk)
-    }
+    fn get_mver_chunk(&self) -> R<ChunkMver> { ChunkMver::from_chunk(self.get_chunk_of_type("MVER")?) }
 
-    fn get_mphd_chunk(&self) -> ChunkMphd {
-        ChunkMphd::from_chunk(self.get_chunk_of_type("MPHD"))
-    }
+    fn get_mphd_chunk(&self) -> R<ChunkMphd> { ChunkMphd::from_chunk(self.get_chunk_of_type("MPHD")?) }
 
-    fn get_main(&self) -> ChunkMain { ChunkMain::from_chunk(self.get_chunk_of_type("MAIN")) }
+    fn get_main(&self) -> R<ChunkMain> { ChunkMain::from_chunk(self.get_chunk_of_type("MAIN")?) }
 
-    fn get_modf(&self) -> Option<ChunkModf> { self.get_chunk_of_type_optionally("MODF").and_then(ChunkModf::from_chunk) }
-
-    fn get_mhdr(&self) -> ChunkMhdr {
-        ChunkMhdr::from_chunk(self.get_chunk_of_type("MHDR"))
+    fn get_modf(&self) -> R<Option<ChunkModf>> {
+        self.get_chunk_of_type_optionally("MODF")
+            .map(ChunkModf::from_chunk)
+            .transpose()
     }
 
-    fn get_mcin(&self) -> Vec<ChunkMcin> { ChunkMcin::from_chunk(self.get_chunk_of_type("MCIN")) }
+    fn get_mhdr(&self) -> R<ChunkMhdr> { ChunkMhdr::from_chunk(self.get_chunk_of_type("MHDR")?) }
+
+    fn get_mcin(&self) -> R<Vec<ChunkMcin>> { ChunkMcin::from_chunk(self.get_chunk_of_type("MCIN")?) }
 
-    fn get_mtex(&self) -> ChunkMtex { ChunkMtex::from_chunk(self.get_chunk_of_type("MTEX")) }
+    fn get_mtex(&self) -> R<ChunkMtex> { ChunkMtex::from_chunk(self.get_chunk_of_type("MTEX")?) }
 
-    fn get_mmdx(&self) -> ChunkMmdx { ChunkMmdx::from_chunk(self.get_chunk_of_type("MMDX")) }
+    fn get_mmdx(&self) -> R<ChunkMmdx> { ChunkMmdx::from_chunk(self.get_chunk_of_type("MMDX")?) }
 
-    fn get_mmid(&self) -> ChunkMmid { ChunkMmid::from_chunk(self.get_chunk_of_type("MMID")) }
+    fn get_mmid(&self) -> R<ChunkMmid> { ChunkMmid::from_chunk(self.get_chunk_of_type("MMID")?) }
 
-    fn get_mwmo(&self) -> ChunkMwmo { ChunkMwmo::from_chunk(self.get_chunk_of_type("MWMO")) }
+    fn get_mwmo(&self) -> R<ChunkMwmo> { ChunkMwmo::from_chunk(self.get_chunk_of_type("MWMO")?) }
 
-    fn get_mwid(&self) -> ChunkMwid { ChunkMwid::from_chunk(self.get_chunk_of_type("MWID")) }
+    fn get_mwid(&self) -> R<ChunkMwid> { ChunkMwid::from_chunk(self.get_chunk_of_type("MWID")?) }
 
-    fn get_mddf(&self) -> ChunkMddf { ChunkMddf::from_chunk(self.get_chunk_of_type("MDDF")) }
+    fn get_mddf(&self) -> R<ChunkMddf> { ChunkMddf::from_chunk(self.get_chunk_of_type("MDDF")?) }
 
-    fn get_motx(&self) -> ChunkMotx { ChunkMotx::from_chunk(self.get_chunk_of_type("MOTX")) }
+    fn get_motx(&self) -> R<ChunkMotx> { ChunkMotx::from_chunk(self.get_chunk_of_type("MOTX")?) }
 
-    fn get_mogn(&self) -> ChunkMogn { ChunkMogn::from_chunk(self.get_chunk_of_type("MOGN")) }
+    fn get_mogn(&self) -> R<ChunkMogn> { ChunkMogn::from_chunk(self.get_chunk_of_type("MOGN")?) }
 
-    fn get_modn(&self) -> ChunkModn { ChunkModn::from_chunk(self.get_chunk_of_type("MODN")) }
+    fn get_modn(&self) -> R<ChunkModn> { ChunkModn::from_chunk(self.get_chunk_of_type("MODN")?) }
 
-    fn get_mohd(&self) -> ChunkMohd { ChunkMohd::from_chunk(self.get_chunk_of_type("MOHD")) }
+    fn get_mohd(&self) -> R<ChunkMohd> { ChunkMohd::from_chunk(self.get_chunk_of_type("MOHD")?) }
 
-    fn get_molr(&self) -> Option<ChunkMolr> {
+    fn get_molr(&self) -> R<Option<ChunkMolr>> {
         self.get_chunk_of_type_optionally("MOLR")
             .map(ChunkMolr::from_chunk)
+            .transpose()
     }
 
-    fn get_mcnk(&self) -> ChunkMcnk {
+    fn get_mcnk(&self) -> R<ChunkMcnk> {
         let chunks = self.get_all_chunks_of_type("MCNK");
         ChunkMcnk::from_chunks(chunks)
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ChunkMver {
-    pub map_version: u32,
+bin_layout! {
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct ChunkMver {
+        pub map_version: u32,
+    }
 }
 
 impl ChunkMver {
-    pub fn from_chunk(chunk: &Chunk) -> R<ChunkMver> {
-        assert_eq!(chunk.get_id_as_string(), "MVER");
-        assert_eq!(chunk.size, 4);
-        let map_version = chunk.data.get_u32(0);
-        map_version.map(|map_version|
-            ChunkMver {
-                map_version,
-            }
-        )
+    pub fn from_chunk(chunk: &ChunkRef) -> R<ChunkMver> {
+        chunk.expect_type("MVER")?;
+        chunk.expect_size(ChunkMver::SIZE)?;
+        ChunkMver::from_bytes(chunk.data)
+    }
+
+    pub fn to_chunk(&self) -> Chunk {
+        Chunk::new("MVER", self.to_bytes())
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ChunkMphd {
-    pub flags: u32,
-    pub something: u32,
-    pub unused: [u8; 6],
+/// The map has no terrain, only the WMO placed by the WDT MODF.
+pub const MPHD_FLAG_WMO_ONLY: u32 = 0x1;
+/// The ADTs of the map have MCCV vertex shading.
+pub const MPHD_FLAG_ADT_HAS_MCCV: u32 = 0x2;
+/// The ADTs of the map use 8 bit alpha maps, see [ChunkMphd::has_big_alpha].
+pub const MPHD_FLAG_ADT_HAS_BIG_ALPHA: u32 = 0x4;
+/// Implies big alpha as well.
+pub const MPHD_FLAG_ADT_HAS_HEIGHT_TEXTURING: u32 = 0x80;
+
+bin_layout! {
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct ChunkMphd {
+        pub flags: u32,
+        pub something: u32,
+        pub unused: [u32; 6],
+    }
 }
 
 impl ChunkMphd {
-    pub fn from_chunk(c: &Chunk) -> ChunkMphd {
-        assert_eq!(c.get_id_as_string(), "MPHD");
-        assert_eq!(c.size, 32);
-        ChunkMphd {
-            flags: c.data.get_u32(0).unwrap(),
-            something: c.data.get_u32(4).unwrap(),
-            unused: c.data[8..14].try_into().unwrap(),
-        }
+    pub fn from_chunk(c: &ChunkRef) -> R<ChunkMphd> {
+        c.expect_type("MPHD")?;
+        c.expect_size(ChunkMphd::SIZE)?;
+        ChunkMphd::from_bytes(c.data)
+    }
+
+    pub fn to_chunk(&self) -> Chunk {
+        Chunk::new("MPHD", self.to_bytes())
+    }
+
+    pub fn has_big_alpha(&self) -> bool {
+        self.flags & (MPHD_FLAG_ADT_HAS_BIG_ALPHA | MPHD_FLAG_ADT_HAS_HEIGHT_TEXTURING) != 0
     }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ChunkMain(pub Vec<ChunkMainItem>);
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ChunkMainItem {
-    pub flags: u32,
-    pub area: u32,
+/// The tile has an ADT file.
+pub const MAIN_FLAG_HAS_ADT: u32 = 0x1;
+
+bin_layout! {
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct ChunkMainItem {
+        pub flags: u32,
+        pub area: u32,
+    }
 }
 
 impl ChunkMain {
-    pub fn from_chunk(c: &Chunk) -> ChunkMain {
-        assert_eq!(c.get_id_as_string(), "MAIN");
-        assert_eq!(c.size, 32768);
-        let items = c.data.chunks(8)
-            .map(|c| {
-                let c = c.to_vec();
-                let flags = c.get_u32(0).unwrap();
-                let area = c.get_u32(4).unwrap();
-                ChunkMainItem { flags, area }
-            })
-            .collect();
+    pub fn from_chunk(c: &ChunkRef) -> R<ChunkMain> {
+        c.expect_type("MAIN")?;
+        c.expect_size(64 * 64 * ChunkMainItem::SIZE)?;
+        Ok(ChunkMain(ChunkMainItem::read_all(c.data)?))
+    }
+
+    /// Tiles flagged as having an ADT file, as `(x, y)` in the `<Map>_<x>_<y>.adt` naming.
+    pub fn adt_tiles(&self) -> Vec<(u32, u32)> {
+        self.0.iter()
+            .enumerate()
+            .filter(|(_, item)| item.flags & MAIN_FLAG_HAS_ADT != 0)
+            .map(|(i, _)| ((i % 64) as u32, (i / 64) as u32))
+            .collect()
+    }
+
+    /// The entry of tile `(x, y)`, in the `<Map>_<x>_<y>.adt` naming.
+    pub fn tile(&self, x: u32, y: u32) -> &ChunkMainItem {
+        &self.0[(y * 64 + x) as usize]
+    }
 
-        ChunkMain(items)
+    pub fn tile_mut(&mut self, x: u32, y: u32) -> &mut ChunkMainItem {
+        &mut self.0[(y * 64 + x) as usize]
+    }
+
+    pub fn to_chunk(&self) -> Chunk {
+        Chunk::new("MAIN", self.0.to_bytes())
     }
 }
 
+/// WMO placements, a WDT of a WMO only map holds a single one.
 #[derive(Debug, Serialize, Deserialize)]
-pub struct ChunkModf {
-    id: u32,
-    dynamic_id: u32,
-    pos: [f32; 3],
-    orientation: [f32; 3],
-    bounding_box: [f32; 6],
-    flags: u16,
-    doodad_set_index: u16,
-    name_set: u16,
-    padding: u16,
+pub struct ChunkModf(pub Vec<ChunkModfItem>);
+
+bin_layout! {
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct ChunkModfItem {
+        /// Index into MWID.
+        pub name_id: u32,
+        pub unique_id: u32,
+        pub position: [f32; 3],
+        /// Degrees.
+        pub rotation: [f32; 3],
+        /// Lower then upper corner, in ADT placement space like `position`.
+        pub extents: [f32; 6],
+        pub flags: u16,
+        pub doodad_set: u16,
+        pub name_set: u16,
+        /// Unused before Legion, 1024 == 1.0.
+        pub scale: u16,
+    }
 }
 
 impl ChunkModf {
-    pub fn from_chunk(c: &Chunk) -> Option<ChunkModf> {
-        assert_eq!(c.get_id_as_string(), "MODF");
-        assert!([64, 0].contains(&c.size), "MODF size should either be 64 or 0.");
-
-        if c.size == 0 {
-            return None;
-        }
+    pub fn from_chunk(c: &ChunkRef) -> R<ChunkModf> {
+        c.expect_type("MODF")?;
+        c.expect_size_multiple_of(ChunkModfItem::SIZE)?;
+        Ok(ChunkModf(ChunkModfItem::read_all(c.data)?))
+    }
 
-        let id = c.data.get_u32(0).unwrap();
-        let dynamic_id = c.data.get_u32(4).unwrap();
-        let pos_x = c.data.get_f32(8).unwrap();
-        let pos_y = c.data.get_f32(12).unwrap();
-        let pos_z = c.data.get_f32(16).unwrap();
-        let pos = [pos_x, pos_y, pos_z];
-        let orient_x = c.data.get_f32(20).unwrap();
-        let orient_y = c.data.get_f32(24).unwrap();
-        let orient_z = c.data.get_f32(28).unwrap();
-        let orientation = [orient_x, orient_y, orient_z];
-        let b1 = c.data.get_f32(32).unwrap();
-        let b2 = c.data.get_f32(36).unwrap();
-        let b3 = c.data.get_f32(40).unwrap();
-        let b4 = c.data.get_f32(44).unwrap();
-        let b5 = c.data.get_f32(48).unwrap();
-        let b6 = c.data.get_f32(52).unwrap();
-        let bounding_box = [b1, b2, b3, b4, b5, b6];
-        let flags = c.data.get_u16(56).unwrap();
-        let doodad_set_index = c.data.get_u16(58).unwrap();
-        let name_set = c.data.get_u16(60).unwrap();
-        let padding = c.data.get_u16(62).unwrap();
-        Some(ChunkModf {
-            id,
-            dynamic_id,
-            pos,
-            orientation,
-            bounding_box,
-            flags,
-            doodad_set_index,
-            name_set,
-            padding,
-        })
+    pub fn to_chunk(&self) -> Chunk {
+        Chunk::new("MODF", self.0.to_bytes())
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ChunkMhdr {
-    pub flags: u32,
-    pub offs_mcin: u32,
-    pub offs_mtex: u32,
-    pub offs_mmdx: u32,
-    pub offs_mmid: u32,
-    pub offs_mwmo: u32,
-    pub offs_mwid: u32,
-    pub offs_mddf: u32,
-    pub offs_modf: u32,
+bin_layout! {
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct ChunkMhdr {
+        pub flags: u32,
+        pub offs_mcin: u32,
+        pub offs_mtex: u32,
+        pub offs_mmdx: u32,
+        pub offs_mmid: u32,
+        pub offs_mwmo: u32,
+        pub offs_mwid: u32,
+        pub offs_mddf: u32,
+        pub offs_modf: u32,
+        pub offs_mfbo: u32,
+        pub offs_mh2o: u32,
+        pub offs_mtxf: u32,
+        pub unused: [u32; 4],
+    }
 }
 
 impl ChunkMhdr {
-    pub fn from_chunk(c: &Chunk) -> ChunkMhdr {
-        assert_eq!(c.get_id_as_string(), "MHDR");
-        assert_eq!(c.size, 64);
-        ChunkMhdr {
-            flags: c.data.get_u32(0).unwrap(),
-            offs_mcin: c.data.get_u32(4).unwrap(),
-            offs_mtex: c.data.get_u32(8).unwrap(),
-            offs_mmdx: c.data.get_u32(12).unwrap(),
-            offs_mmid: c.data.get_u32(16).unwrap(),
-            offs_mwmo: c.data.get_u32(20).unwrap(),
-            offs_mwid: c.data.get_u32(24).unwrap(),
-            offs_mddf: c.data.get_u32(28).unwrap(),
-            offs_modf: c.data.get_u32(32).unwrap(),
-        }
+    pub fn from_chunk(c: &ChunkRef) -> R<ChunkMhdr> {
+        c.expect_type("MHDR")?;
+        c.expect_size(ChunkMhdr::SIZE)?;
+        ChunkMhdr::from_bytes(c.data)
+    }
+
+    pub fn to_chunk(&self) -> Chunk {
+        Chunk::new("MHDR", self.to_bytes())
     }
 }
 
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ChunkMcin {
-    pub offs_mcnk: u32,
-    pub size: u32,
-    pub flags: u32,
-    pub async_id: u32,
+bin_layout! {
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct ChunkMcin {
+        pub offs_mcnk: u32,
+        pub size: u32,
+        pub flags: u32,
+        pub async_id: u32,
+    }
 }
 
 impl ChunkMcin {
-    pub fn from_chunk(c: &Chunk) -> Vec<ChunkMcin> {
-        assert_eq!(c.get_id_as_string(), "MCIN");
-        assert_eq!(c.size, 4096);
-        c.data.chunks(16)
-            .map(|chunk| {
-                let chunk = chunk.to_vec();
-                ChunkMcin {
-                    offs_mcnk: chunk.get_u32(0).unwrap(),
-                    size: chunk.get_u32(4).unwrap(),
-                    flags: chunk.get_u32(8).unwrap(),
-                    async_id: chunk.get_u32(12).unwrap(),
-                }
-            })
-            .collect()
+    pub fn from_chunk(c: &ChunkRef) -> R<Vec<ChunkMcin>> {
+        c.expect_type("MCIN")?;
+        c.expect_size(16 * 16 * ChunkMcin::SIZE)?;
+        ChunkMcin::read_all(c.data)
     }
 }
 
@@ -373,9 +508,13 @@ impl ChunkMcin {
 pub struct ChunkMtex(pub Vec<String>);
 
 impl ChunkMtex {
-    pub fn from_chunk(c: &Chunk) -> ChunkMtex {
-        assert_eq!(c.get_id_as_string(), "MTEX");
-        ChunkMtex(c.data.get_null_terminated_strings().unwrap())
+    pub fn from_chunk(c: &ChunkRef) -> R<ChunkMtex> {
+        c.expect_type("MTEX")?;
+        Ok(ChunkMtex(c.get_null_terminated_strings()?))
+    }
+
+    pub fn to_chunk(&self) -> Chunk {
+        Chunk::new("MTEX", strings_to_bytes(&self.0))
     }
 }
 
@@ -383,9 +522,13 @@ impl ChunkMtex {
 pub struct ChunkMmdx(pub Vec<String>);
 
 impl ChunkMmdx {
-    pub fn from_chunk(c: &Chunk) -> ChunkMmdx {
-        assert_eq!(c.get_id_as_string(), "MMDX");
-        ChunkMmdx(c.data.get_null_terminated_strings().unwrap())
+    pub fn from_chunk(c: &ChunkRef) -> R<ChunkMmdx> {
+        c.expect_type("MMDX")?;
+        Ok(ChunkMmdx(c.get_null_terminated_strings()?))
+    }
+
+    pub fn to_chunk(&self) -> Chunk {
+        Chunk::new("MMDX", strings_to_bytes(&self.0))
     }
 }
 
@@ -393,12 +536,14 @@ impl ChunkMmdx {
 pub struct ChunkMmid(pub Vec<u32>);
 
 impl ChunkMmid {
-    pub fn from_chunk(c: &Chunk) -> ChunkMmid {
-        assert_eq!(c.get_id_as_string(), "MMID");
-        let offsets = c.data.chunks(4)
-            .map(|it| it.to_vec().get_u32(0).unwrap())
-            .collect();
-        ChunkMmid(offsets)
+    pub fn from_chunk(c: &ChunkRef) -> R<ChunkMmid> {
+        c.expect_type("MMID")?;
+        c.expect_size_multiple_of(u32::SIZE)?;
+        Ok(ChunkMmid(u32::read_all(c.data)?))
+    }
+
+    pub fn to_chunk(&self) -> Chunk {
+        Chunk::new("MMID", self.0.to_bytes())
     }
 }
 
@@ -406,9 +551,13 @@ impl ChunkMmid {
 pub struct ChunkMwmo(pub Vec<String>);
 
 impl ChunkMwmo {
-    pub fn from_chunk(c: &Chunk) -> ChunkMwmo {
-        assert_eq!(c.get_id_as_string(), "MWMO");
-        ChunkMwmo(c.data.get_null_terminated_strings().unwrap())
+    pub fn from_chunk(c: &ChunkRef) -> R<ChunkMwmo> {
+        c.expect_type("MWMO")?;
+        Ok(ChunkMwmo(c.get_null_terminated_strings()?))
+    }
+
+    pub fn to_chunk(&self) -> Chunk {
+        Chunk::new("MWMO", strings_to_bytes(&self.0))
     }
 }
 
@@ -416,59 +565,41 @@ impl ChunkMwmo {
 pub struct ChunkMwid(pub Vec<u32>);
 
 impl ChunkMwid {
-    pub fn from_chunk(c: &Chunk) -> ChunkMwid {
-        assert_eq!(c.get_id_as_string(), "MWID");
-        assert_eq!(c.size % 4, 0);
-        let offsets = c.data.chunks(4)
-            .map(|it| it.to_vec().get_u32(0).unwrap())
-            .collect();
-        ChunkMwid(offsets)
+    pub fn from_chunk(c: &ChunkRef) -> R<ChunkMwid> {
+        c.expect_type("MWID")?;
+        c.expect_size_multiple_of(u32::SIZE)?;
+        Ok(ChunkMwid(u32::read_all(c.data)?))
+    }
+
+    pub fn to_chunk(&self) -> Chunk {
+        Chunk::new("MWID", self.0.to_bytes())
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ChunkMddfItem {
-    pub mmid_entry: u32,
-    pub unique_id: u32,
-    pub position: [f32; 3],
-    pub rotation: [f32; 3],
-    pub scale: u16,
-    pub flags: u16,
+bin_layout! {
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct ChunkMddfItem {
+        pub mmid_entry: u32,
+        pub unique_id: u32,
+        pub position: [f32; 3],
+        pub rotation: [f32; 3],
+        pub scale: u16,
+        pub flags: u16,
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ChunkMddf(pub Vec<ChunkMddfItem>);
 
 impl ChunkMddf {
-    pub fn from_chunk(c: &Chunk) -> ChunkMddf {
-        assert_eq!(c.get_id_as_string(), "MDDF");
-        assert_eq!(c.size % 36, 0);
-
-        let items: Vec<ChunkMddfItem> = c.data.chunks(36).map(|data| {
-            let data = data.to_vec();
-            let mmid_entry = data.get_u32(0).unwrap();
-            let unique_id = data.get_u32(4).unwrap();
-            let pos_x = data.get_f32(8).unwrap();
-            let pos_y = data.get_f32(12).unwrap();
-            let pos_z = data.get_f32(16).unwrap();
-            let position = [pos_x, pos_y, pos_z];
-            let rot_x = data.get_f32(20).unwrap();
-            let rot_y = data.get_f32(24).unwrap();
-            let rot_z = data.get_f32(28).unwrap();
-            let rotation = [rot_x, rot_y, rot_z];
-            let scale = data.get_u16(32).unwrap();
-            let flags = data.get_u16(34).unwrap();
-            ChunkMddfItem {
-                mmid_entry,
-                unique_id,
-                position,
-                rotation,
-                scale,
-                flags,
-            }
-        }).collect();
+    pub fn from_chunk(c: &ChunkRef) -> R<ChunkMddf> {
+        c.expect_type("MDDF")?;
+        c.expect_size_multiple_of(ChunkMddfItem::SIZE)?;
+        Ok(ChunkMddf(ChunkMddfItem::read_all(c.data)?))
+    }
 
-        ChunkMddf(items)
+    pub fn to_chunk(&self) -> Chunk {
+        Chunk::new("MDDF", self.0.to_bytes())
     }
 }
 
@@ -477,15 +608,14 @@ impl ChunkMddf {
 pub struct ChunkMotx(pub Vec<String>);
 
 impl ChunkMotx {
-    pub fn from_chunk(c: &Chunk) -> ChunkMotx {
-        assert_eq!(c.get_id_as_string(), "MOTX");
-        let strings = c.data.get_null_terminated_strings()
-            .unwrap()
+    pub fn from_chunk(c: &ChunkRef) -> R<ChunkMotx> {
+        c.expect_type("MOTX")?;
+        let strings = c.get_null_terminated_strings()?
             .into_iter()
             .filter(|it| !it.is_empty())
             .collect();
 
-        ChunkMotx(strings)
+        Ok(ChunkMotx(strings))
     }
 }
 
@@ -493,14 +623,13 @@ impl ChunkMotx {
 pub struct ChunkMogn(pub Vec<String>);
 
 impl ChunkMogn {
-    pub fn from_chunk(c: &Chunk) -> ChunkMogn {
-        assert_eq!(c.get_id_as_string(), "MOGN");
-        let strings = c.data.get_null_terminated_strings()
-            .unwrap_or(vec![])
+    pub fn from_chunk(c: &ChunkRef) -> R<ChunkMogn> {
+        c.expect_type("MOGN")?;
+        let strings = c.get_null_terminated_strings()?
             .into_iter()
             .filter(|it| !it.is_empty())
             .collect();
-        ChunkMogn(strings)
+        Ok(ChunkMogn(strings))
     }
 }
 
@@ -508,58 +637,40 @@ impl ChunkMogn {
 pub struct ChunkModn(pub Vec<String>);
 
 impl ChunkModn {
-    pub fn from_chunk(c: &Chunk) -> ChunkModn {
-        assert_eq!(c.get_id_as_string(), "MODN");
-        let strings = c.data.get_null_terminated_strings()
-            .unwrap_or(vec![])
+    pub fn from_chunk(c: &ChunkRef) -> R<ChunkModn> {
+        c.expect_type("MODN")?;
+        let strings = c.get_null_terminated_strings()?
             .into_iter()
             .filter(|it| !it.is_empty())
             .collect();
-        ChunkModn(strings)
+        Ok(ChunkModn(strings))
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ChunkMohd {
-    pub n_materials: u32,
-    pub n_groups: u32,
-    pub n_ports: u32,
-    pub n_lights: u32,
-    pub n_models: u32,
-    pub n_doodads: u32,
-    pub n_doodad_sets: u32,
-    pub ambient_color: u32,
-    pub wmo_id: u32,
-    pub bounding_box_corner_1: [f32; 3],
-    pub bounding_box_corner_2: [f32; 3],
+bin_layout! {
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct ChunkMohd {
+        pub n_materials: u32,
+        pub n_groups: u32,
+        pub n_ports: u32,
+        pub n_lights: u32,
+        pub n_models: u32,
+        pub n_doodads: u32,
+        pub n_doodad_sets: u32,
+        pub ambient_color: u32,
+        pub wmo_id: u32,
+        pub bounding_box_corner_1: [f32; 3],
+        pub bounding_box_corner_2: [f32; 3],
+        pub flags: u16,
+        pub num_lod: u16,
+    }
 }
 
 impl ChunkMohd {
-    pub fn from_chunk(c: &Chunk) -> ChunkMohd {
-        assert_eq!(c.get_id_as_string(), "MOHD");
-        ChunkMohd {
-            n_materials: c.data.get_u32(0).unwrap(),
-            n_groups: c.data.get_u32(4).unwrap(),
-            n_ports: c.data.get_u32(8).unwrap(),
-            n_lights: c.data.get_u32(12).unwrap(),
-            n_models: c.data.get_u32(16).unwrap(),
-            n_doodads: c.data.get_u32(20).unwrap(),
-            n_doodad_sets: c.data.get_u32(24).unwrap(),
-            ambient_color: c.data.get_u32(28).unwrap(),
-            wmo_id: c.data.get_u32(32).unwrap(),
-            bounding_box_corner_1: {
-                let b1 = c.data.get_f32(36).unwrap();
-                let b2 = c.data.get_f32(40).unwrap();
-                let b3 = c.data.get_f32(44).unwrap();
-                [b1, b2, b3]
-            },
-            bounding_box_corner_2: {
-                let b1 = c.data.get_f32(48).unwrap();
-                let b2 = c.data.get_f32(52).unwrap();
-                let b3 = c.data.get_f32(56).unwrap();
-                [b1, b2, b3]
-            },
-        }
+    pub fn from_chunk(c: &ChunkRef) -> R<ChunkMohd> {
+        c.expect_type("MOHD")?;
+        c.expect_size(ChunkMohd::SIZE)?;
+        ChunkMohd::from_bytes(c.data)
     }
 }
 
@@ -568,15 +679,10 @@ impl ChunkMohd {
 pub struct ChunkMolr(pub Vec<u16>);
 
 impl ChunkMolr {
-    pub fn from_chunk(c: &Chunk) -> ChunkMolr {
-        assert_eq!(c.get_id_as_string(), "MOLR");
-        let strings = c.data.chunks(2)
-            .map(|c| {
-                let c = c.to_vec();
-                c.get_u16(0).unwrap()
-            })
-            .collect();
-        ChunkMolr(strings)
+    pub fn from_chunk(c: &ChunkRef) -> R<ChunkMolr> {
+        c.expect_type("MOLR")?;
+        c.expect_size_multiple_of(u16::SIZE)?;
+        Ok(ChunkMolr(u16::read_all(c.data)?))
     }
 }
 
@@ -586,29 +692,513 @@ pub struct ChunkMcnk {
     pub items: Vec<ChunkMcnkItem>,
 }
 
+pub const MCNK_FLAG_HAS_MCSH: u32 = 0x1;
+pub const MCNK_FLAG_HAS_MCCV: u32 = 0x40;
+/// 4 bit alpha maps are stored at their full 64x64 size instead of 63x63 with the last row/column repeated.
+pub const MCNK_FLAG_DO_NOT_FIX_ALPHA_MAP: u32 = 0x8000;
+
+/// 9x9 outer and 8x8 inner vertices, interleaved row by row (9, 8, 9, 8, ...).
+pub const MCNK_VERTEX_COUNT: usize = 9 * 9 + 8 * 8;
+
+bin_layout! {
+    /// Sub-chunk offsets are relative to the start of the MCNK chunk, its 8 byte header included.
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct ChunkMcnkHeader {
+        pub flags: u32,
+        pub index_x: u32,
+        pub index_y: u32,
+        pub n_layers: u32,
+        pub n_doodad_refs: u32,
+        pub ofs_mcvt: u32,
+        pub ofs_mcnr: u32,
+        pub ofs_mcly: u32,
+        pub ofs_mcrf: u32,
+        pub ofs_mcal: u32,
+        pub size_mcal: u32,
+        pub ofs_mcsh: u32,
+        pub size_mcsh: u32,
+        pub area_id: u32,
+        pub n_map_obj_refs: u32,
+        pub holes: u16,
+        pub holes_padding: u16,
+        pub low_quality_texture_map: [u16; 8],
+        pub pred_tex: u32,
+        pub n_effect_doodad: u32,
+        pub ofs_mcse: u32,
+        pub n_snd_emitters: u32,
+        pub ofs_mclq: u32,
+        pub size_mclq: u32,
+        pub position: [f32; 3],
+        pub ofs_mccv: u32,
+        pub ofs_mclv: u32,
+        pub unused: u32,
+    }
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ChunkMcnkItem {
-    pub flags: u32,
-    pub area_id: u32,
+    pub header: ChunkMcnkHeader,
+    pub mcvt: ChunkMcvt,
+    pub mcnr: ChunkMcnr,
+    pub mcly: ChunkMcly,
+    pub mcrf: ChunkMcrf,
+    pub mcsh: Option<ChunkMcsh>,
+    pub mcal: Option<ChunkMcal>,
+    pub mclq: Option<ChunkMclq>,
+    pub mcse: Option<ChunkMcse>,
+    pub mccv: Option<ChunkMccv>,
+}
+
+/// Heights relative to [ChunkMcnkHeader::position], see [MCNK_VERTEX_COUNT] for the layout.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChunkMcvt {
+    pub heights: Vec<f32>,
+}
+
+/// One normal per MCVT vertex, 127 == 1.0. The chunk is followed by 13 bytes
+/// not accounted for in its size, they are kept so the chunk can be written back.
+/// Some editors count them in the size (448), they end up in `padding` all the same.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChunkMcnr {
+    pub normals: Vec<[i8; 3]>,
+    pub padding: Vec<u8>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChunkMcly(pub Vec<ChunkMclyItem>);
+
+pub const MCLY_FLAG_USE_ALPHA_MAP: u32 = 0x100;
+pub const MCLY_FLAG_ALPHA_MAP_COMPRESSED: u32 = 0x200;
+
+bin_layout! {
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct ChunkMclyItem {
+        pub texture_id: u32,
+        pub flags: u32,
+        pub offset_in_mcal: u32,
+        pub effect_id: u32,
+    }
+}
+
+/// Indices into MDDF followed by indices into MODF, split using the MCNK header counts.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChunkMcrf {
+    pub doodad_refs: Vec<u32>,
+    pub map_obj_refs: Vec<u32>,
+}
+
+/// 64x64 bit shadow map, one row per 8 bytes.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChunkMcsh {
+    pub shadow_map: Vec<u8>,
+}
+
+/// Alpha maps of every MCLY layer, each one starting at [ChunkMclyItem::offset_in_mcal].
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChunkMcal {
+    pub data: Vec<u8>,
+}
+
+/// Pre-WotLK liquid. The size in its own chunk header is usually 0,
+/// [ChunkMcnkHeader::size_mclq] holds the real one.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChunkMclq {
+    pub declared_size: u32,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChunkMcse(pub Vec<ChunkMcseItem>);
+
+bin_layout! {
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct ChunkMcseItem {
+        pub sound_point_id: u32,
+        pub sound_name_id: u32,
+        pub position: [f32; 3],
+        pub min_distance: f32,
+        pub max_distance: f32,
+    }
+}
+
+/// Vertex colors as BGRA, one per MCVT vertex.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChunkMccv {
+    pub colors: Vec<[u8; 4]>,
 }
 
 impl ChunkMcnk {
-    pub fn from_chunks(chunks: Vec<&Chunk>) -> ChunkMcnk {
+    pub fn from_chunks(chunks: Vec<&ChunkRef>) -> R<ChunkMcnk> {
         let items = chunks
             .into_iter()
-            .map(|c| {
-                assert_eq!(c.get_id_as_string(), "MCNK");
-                let flags = c.data.get_u32(0x00).unwrap();
-                let area_id = c.data.get_u32(0x034).unwrap();
-                ChunkMcnkItem {
-                    flags,
-                    area_id,
-                }
-            })
-            .collect();
-        ChunkMcnk {
+            .map(ChunkMcnkItem::from_chunk)
+            .collect::<R<Vec<ChunkMcnkItem>>>()?;
+        Ok(ChunkMcnk {
             items
+        })
+    }
+}
+
+impl ChunkMcnkItem {
+    pub fn from_chunk(c: &ChunkRef) -> R<ChunkMcnkItem> {
+        c.expect_type("MCNK")?;
+        c.expect_size_at_least(ChunkMcnkHeader::SIZE)?;
+        let header = ChunkMcnkHeader::from_bytes(c.data)?;
+
+        let mcvt = ChunkMcnkItem::get_sub_chunk(c, header.ofs_mcvt, "MCVT", None)?
+            .with_context(|| format!("{}: missing MCVT", c.describe()))?;
+        let mcnr = ChunkMcnkItem::get_sub_chunk(c, header.ofs_mcnr, "MCNR", Some(MCNK_VERTEX_COUNT * 3 + 13))?
+            .with_context(|| format!("{}: missing MCNR", c.describe()))?;
+        let mcly = ChunkMcnkItem::get_sub_chunk(c, header.ofs_mcly, "MCLY", None)?;
+        let mcrf = ChunkMcnkItem::get_sub_chunk(c, header.ofs_mcrf, "MCRF", None)?;
+        let mcsh = ChunkMcnkItem::get_optional_sub_chunk(c, header.ofs_mcsh, "MCSH", None)?;
+        let mcal = ChunkMcnkItem::get_optional_sub_chunk(c, header.ofs_mcal, "MCAL", None)?;
+        let mclq_size = (header.size_mclq as usize).saturating_sub(8);
+        let mclq = ChunkMcnkItem::get_optional_sub_chunk(c, header.ofs_mclq, "MCLQ", Some(mclq_size))?;
+        let mcse = ChunkMcnkItem::get_optional_sub_chunk(c, header.ofs_mcse, "MCSE", None)?;
+        let mccv = ChunkMcnkItem::get_optional_sub_chunk(c, header.ofs_mccv, "MCCV", None)?;
+
+        Ok(ChunkMcnkItem {
+            mcvt: ChunkMcvt::from_chunk(&mcvt)?,
+            mcnr: ChunkMcnr::from_chunk(&mcnr)?,
+            mcly: match mcly {
+                Some(mcly) => ChunkMcly::from_chunk(&mcly)?,
+                None => ChunkMcly(vec![]),
+            },
+            mcrf: match mcrf {
+                Some(mcrf) => ChunkMcrf::from_chunk(&mcrf, &header)?,
+                None => ChunkMcrf { doodad_refs: vec![], map_obj_refs: vec![] },
+            },
+            mcsh: mcsh.map(|c| ChunkMcsh { shadow_map: c.data.to_vec() }),
+            mcal: mcal.map(|c| ChunkMcal { data: c.data.to_vec() }),
+            mclq: mclq.map(|c| ChunkMclq { declared_size: c.size, data: c.data.to_vec() }),
+            mcse: mcse.map(|c| ChunkMcse::from_chunk(&c)).transpose()?,
+            mccv: mccv.map(|c| ChunkMccv::from_chunk(&c)).transpose()?,
+            header,
+        })
+    }
+
+    /// Locates a sub-chunk through its MCNK header offset, `None` if the offset is 0.
+    /// `data_size` replaces the declared size when the sub-chunk header is known to lie about it,
+    /// the declared size is kept in [ChunkRef::size].
+    fn get_sub_chunk<'a>(
+        mcnk: &ChunkRef<'a>,
+        ofs: u32,
+        chunk_type: &str,
+        data_size: Option<usize>,
+    ) -> R<Option<ChunkRef<'a>>> {
+        if ofs == 0 {
+            return Ok(None);
+        }
+        let chunk = ChunkMcnkItem::read_sub_chunk(mcnk, ofs, data_size)
+            .with_context(|| format!("{}: invalid {} offset {:#x}", mcnk.describe(), chunk_type, ofs))?;
+        chunk.expect_type(chunk_type)?;
+        Ok(Some(chunk))
+    }
+
+    /// Like [ChunkMcnkItem::get_sub_chunk], but an offset pointing at another chunk means absent.
+    /// Files commonly leave stale offsets behind for chunks they don't have.
+    fn get_optional_sub_chunk<'a>(
+        mcnk: &ChunkRef<'a>,
+        ofs: u32,
+        chunk_type: &str,
+        data_size: Option<usize>,
+    ) -> R<Option<ChunkRef<'a>>> {
+        if ofs == 0 {
+            return Ok(None);
         }
+        let chunk = ChunkMcnkItem::read_sub_chunk(mcnk, ofs, data_size)
+            .with_context(|| format!("{}: invalid {} offset {:#x}", mcnk.describe(), chunk_type, ofs))?;
+        Ok(Some(chunk).filter(|chunk| chunk.is_type(chunk_type)))
     }
-}
\ No newline at end of file
+
+    /// Serializes the MCNK with its sub-chunks laid out one after another,
+    /// the header offsets, sizes and counts are recomputed to match.
+    pub fn to_chunk(&self) -> Chunk {
+        fn push_sub_chunk(data: &mut Vec<u8>, chunk_type: &str, declared_size: usize, bytes: &[u8]) -> u32 {
+            let offset = 8 + data.len() as u32;
+            let mut id = [0u8; 4];
+            id.copy_from_slice(chunk_type.as_bytes());
+            id.reverse();
+            data.extend_from_slice(&id);
+            data.extend_from_slice(&(declared_size as u32).to_le_bytes());
+            data.extend_from_slice(bytes);
+            offset
+        }
+
+        let mut header = self.header.clone();
+        let mut data = vec![0u8; ChunkMcnkHeader::SIZE];
+
+        header.ofs_mcvt = push_sub_chunk(&mut data, "MCVT", self.mcvt.heights.len() * f32::SIZE, &self.mcvt.heights.to_bytes());
+        header.ofs_mccv = match &self.mccv {
+            Some(mccv) => push_sub_chunk(&mut data, "MCCV", mccv.colors.len() * 4, &mccv.colors.to_bytes()),
+            None => 0,
+        };
+        let mut mcnr = self.mcnr.normals.to_bytes();
+        let mcnr_size = mcnr.len();
+        mcnr.extend_from_slice(&self.mcnr.padding);
+        header.ofs_mcnr = push_sub_chunk(&mut data, "MCNR", mcnr_size, &mcnr);
+
+        let mcly = self.mcly.0.to_bytes();
+        header.ofs_mcly = push_sub_chunk(&mut data, "MCLY", mcly.len(), &mcly);
+        header.n_layers = self.mcly.0.len() as u32;
+
+        let mut mcrf = self.mcrf.doodad_refs.to_bytes();
+        mcrf.append(&mut self.mcrf.map_obj_refs.to_bytes());
+        header.ofs_mcrf = push_sub_chunk(&mut data, "MCRF", mcrf.len(), &mcrf);
+        header.n_doodad_refs = self.mcrf.doodad_refs.len() as u32;
+        header.n_map_obj_refs = self.mcrf.map_obj_refs.len() as u32;
+
+        let (ofs_mcsh, size_mcsh) = match &self.mcsh {
+            Some(mcsh) => (push_sub_chunk(&mut data, "MCSH", mcsh.shadow_map.len(), &mcsh.shadow_map), mcsh.shadow_map.len()),
+            None => (0, 0),
+        };
+        header.ofs_mcsh = ofs_mcsh;
+        header.size_mcsh = size_mcsh as u32;
+
+        let (ofs_mcal, size_mcal) = match &self.mcal {
+            Some(mcal) => (push_sub_chunk(&mut data, "MCAL", mcal.data.len(), &mcal.data), mcal.data.len() + 8),
+            None => (0, 0),
+        };
+        header.ofs_mcal = ofs_mcal;
+        header.size_mcal = size_mcal as u32;
+
+        let (ofs_mclq, size_mclq) = match &self.mclq {
+            Some(mclq) => (push_sub_chunk(&mut data, "MCLQ", mclq.declared_size as usize, &mclq.data), mclq.data.len() + 8),
+            None => (0, 0),
+        };
+        header.ofs_mclq = ofs_mclq;
+        header.size_mclq = size_mclq as u32;
+
+        let (ofs_mcse, n_snd_emitters) = match &self.mcse {
+            Some(mcse) => {
+                let bytes = mcse.0.to_bytes();
+                (push_sub_chunk(&mut data, "MCSE", bytes.len(), &bytes), mcse.0.len())
+            }
+            None => (0, 0),
+        };
+        header.ofs_mcse = ofs_mcse;
+        header.n_snd_emitters = n_snd_emitters as u32;
+        header.ofs_mclv = 0;
+
+        data[..ChunkMcnkHeader::SIZE].copy_from_slice(&header.to_bytes());
+        Chunk::new("MCNK", data)
+    }
+
+    fn read_sub_chunk<'a>(mcnk: &ChunkRef<'a>, ofs: u32, data_size: Option<usize>) -> R<ChunkRef<'a>> {
+        let start = (ofs as usize).checked_sub(8)
+            .with_context(|| "offset points into the MCNK chunk header".to_string())?;
+        let id: [u8; 4] = mcnk.data.get_four_bytes(start)?;
+        let size = mcnk.data.get_u32(start + 4)?;
+        let available = mcnk.data.len().saturating_sub(start + 8);
+        let data_size = match data_size {
+            // the override is only a hint, truncated files still parse with what is there.
+            Some(data_size) if data_size > size as usize => data_size.min(available),
+            _ => size as usize,
+        };
+        let data = mcnk.data.get(start + 8..start + 8 + data_size)
+            .with_context(|| format!("expected size {} but only {} bytes are left", data_size, available))?;
+        Ok(ChunkRef {
+            id,
+            size,
+            offset: mcnk.offset + ofs as usize,
+            data,
+        })
+    }
+}
+
+impl ChunkMcvt {
+    pub fn from_chunk(c: &ChunkRef) -> R<ChunkMcvt> {
+        c.expect_type("MCVT")?;
+        c.expect_size(MCNK_VERTEX_COUNT * f32::SIZE)?;
+        Ok(ChunkMcvt { heights: f32::read_all(c.data)? })
+    }
+}
+
+impl ChunkMcnr {
+    pub fn from_chunk(c: &ChunkRef) -> R<ChunkMcnr> {
+        c.expect_type("MCNR")?;
+        c.expect_size_at_least(MCNK_VERTEX_COUNT * 3)?;
+        let (normals, padding) = c.data.split_at(MCNK_VERTEX_COUNT * 3);
+        let normals = <[i8; 3]>::read_all(normals)?;
+        Ok(ChunkMcnr { normals, padding: padding.to_vec() })
+    }
+}
+
+impl ChunkMcly {
+    pub fn from_chunk(c: &ChunkRef) -> R<ChunkMcly> {
+        c.expect_type("MCLY")?;
+        c.expect_size_multiple_of(ChunkMclyItem::SIZE)?;
+        Ok(ChunkMcly(ChunkMclyItem::read_all(c.data)?))
+    }
+}
+
+impl ChunkMcrf {
+    pub fn from_chunk(c: &ChunkRef, header: &ChunkMcnkHeader) -> R<ChunkMcrf> {
+        c.expect_type("MCRF")?;
+        let n_doodad_refs = header.n_doodad_refs as usize;
+        let n_map_obj_refs = header.n_map_obj_refs as usize;
+        c.expect_size_at_least((n_doodad_refs + n_map_obj_refs) * u32::SIZE)?;
+        let mut refs = u32::read_all(&c.data[..(n_doodad_refs + n_map_obj_refs) * u32::SIZE])?;
+        let map_obj_refs = refs.split_off(n_doodad_refs);
+        Ok(ChunkMcrf {
+            doodad_refs: refs,
+            map_obj_refs,
+        })
+    }
+}
+
+impl ChunkMcse {
+    pub fn from_chunk(c: &ChunkRef) -> R<ChunkMcse> {
+        c.expect_type("MCSE")?;
+        c.expect_size_multiple_of(ChunkMcseItem::SIZE)?;
+        Ok(ChunkMcse(ChunkMcseItem::read_all(c.data)?))
+    }
+}
+
+impl ChunkMccv {
+    pub fn from_chunk(c: &ChunkRef) -> R<ChunkMccv> {
+        c.expect_type("MCCV")?;
+        c.expect_size(MCNK_VERTEX_COUNT * 4)?;
+        Ok(ChunkMccv { colors: <[u8; 4]>::read_all(c.data)? })
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn chunks_write_is_byte_exact() {
+    fn raw_chunk(id: &[u8; 4], size: u32, data: &[u8]) -> Vec<u8> {
+        let mut bytes = id.to_vec();
+        bytes.extend_from_slice(&size.to_le_bytes());
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    let mopy = raw_chunk(b"YPOM", 4, &[1, 2, 3, 4]);
+    let movi = raw_chunk(b"IVOM", 6, &[0, 0, 1, 0, 2, 0]);
+    let mut file = raw_chunk(b"REVM", 4, &17u32.to_le_bytes());
+    let mogp_size = 68 + mopy.len() + movi.len();
+    file.append(&mut raw_chunk(b"PGOM", mogp_size as u32, &[7u8; 68]));
+    file.extend_from_slice(&mopy);
+    file.extend_from_slice(&movi);
+
+    let chunks: Vec<Chunk> = ChunkRef::from_bytes(&file).unwrap().iter().map(ChunkRef::to_owned_chunk).collect();
+    assert_eq!(chunks.len(), 2);
+    assert_eq!(chunks[1].data.len(), mogp_size);
+    assert_eq!(chunks_to_bytes(&chunks), file);
+}
+
+#[cfg(test)]
+#[test]
+fn truncated_chunks_are_reported() {
+    let mut truncated = b"RDHM".to_vec();
+    truncated.extend_from_slice(&64u32.to_le_bytes());
+    truncated.extend_from_slice(&[0u8; 60]);
+    let error = ChunkRef::from_bytes(&truncated).unwrap_err().to_string();
+    assert_eq!(error, "Chunk MHDR at offset 0x0: expected size 64 but only 60 bytes are left");
+
+    let mut short_mhdr = b"RDHM".to_vec();
+    short_mhdr.extend_from_slice(&60u32.to_le_bytes());
+    short_mhdr.extend_from_slice(&[0u8; 60]);
+    let chunks = ChunkRef::from_bytes(&short_mhdr).unwrap();
+    let error = chunks.get_mhdr().unwrap_err().to_string();
+    assert_eq!(error, "Chunk MHDR at offset 0x0: expected size 64 but got 60");
+}
+
+#[cfg(test)]
+#[test]
+fn mcnr_accepts_padding_in_its_size() {
+    let data = vec![1; MCNK_VERTEX_COUNT * 3 + 13];
+    let chunk = ChunkRef { id: *b"RNCM", size: data.len() as u32, offset: 0, data: &data };
+    let mcnr = ChunkMcnr::from_chunk(&chunk).unwrap();
+    assert_eq!(mcnr.normals.len(), MCNK_VERTEX_COUNT);
+    assert_eq!(mcnr.padding.len(), 13);
+}
+
+#[cfg(test)]
+#[test]
+fn parsed_chunk_types_match_the_fixtures() {
+    use crate::formats::adt::{test_adt, AdtFile};
+    use crate::formats::liquid::{ChunkMh2o, ChunkMh2oChunk};
+    use crate::formats::wdt::WdtFile;
+    use crate::formats::wmo::{ChunkMogpHeader, WmoFileVariant};
+    use std::collections::BTreeSet;
+
+    fn chunk_types(bytes: &[u8]) -> Vec<String> {
+        let mut types = vec![];
+        for chunk in ChunkRef::from_bytes(bytes).unwrap() {
+            match chunk.get_id_as_string().as_str() {
+                // MCNR padding is outside of its size, the sub-chunks are found through the header.
+                "MCNK" => {
+                    let h = ChunkMcnkHeader::from_bytes(chunk.data).unwrap();
+                    let offsets = [h.ofs_mcvt, h.ofs_mcnr, h.ofs_mcly, h.ofs_mcrf, h.ofs_mcal, h.ofs_mcsh, h.ofs_mcse, h.ofs_mclq, h.ofs_mccv];
+                    types.extend(offsets.iter()
+                        .filter(|it| **it != 0)
+                        .map(|it| chunk.data.get_reversed_string(*it as usize - 8, 4).unwrap()));
+                }
+                "MOGP" => types.extend(chunk_types(&chunk.data[ChunkMogpHeader::SIZE..])),
+                _ => {}
+            }
+            types.push(chunk.get_id_as_string());
+        }
+        types
+    }
+
+    let mut adt = test_adt();
+    let mut mh2o = ChunkMh2o { chunks: vec![ChunkMh2oChunk::default(); 256] };
+    mh2o.chunks[0].add_liquid(u64::MAX, 1, 5.0, &adt.mcnk.items[0]);
+    adt.mh2o = Some(mh2o);
+    let mcnk = &mut adt.mcnk.items[0];
+    mcnk.mcly.0.push(ChunkMclyItem { texture_id: 0, flags: MCLY_FLAG_USE_ALPHA_MAP, offset_in_mcal: 0, effect_id: 0 });
+    mcnk.mcal = Some(ChunkMcal { data: vec![0; 2048] });
+    mcnk.mcsh = Some(ChunkMcsh { shadow_map: vec![0; 512] });
+    mcnk.mclq = Some(ChunkMclq { declared_size: 4, data: vec![0; 4] });
+    mcnk.mccv = Some(ChunkMccv { colors: vec![[0x7F; 4]; MCNK_VERTEX_COUNT] });
+    let adt_bytes = chunks_to_bytes(&adt.to_chunks());
+    AdtFile::new(&ChunkRef::from_bytes(&adt_bytes).unwrap()).unwrap();
+
+    let mut wdt = WdtFile::empty(0);
+    wdt.modf = Some(ChunkModf(vec![]));
+    let wdt_bytes = chunks_to_bytes(&wdt.to_chunks());
+    WdtFile::new(&ChunkRef::from_bytes(&wdt_bytes).unwrap()).unwrap();
+
+    let mut mohd = vec![0u8; ChunkMohd::SIZE];
+    mohd[4] = 1;
+    let wmo_root_bytes = chunks_to_bytes(&[
+        Chunk::new("MVER", 17u32.to_le_bytes().to_vec()),
+        Chunk::new("MOHD", mohd),
+        Chunk::new("MOTX", b"a.blp\0".to_vec()),
+        Chunk::new("MOMT", vec![]),
+        Chunk::new("MOGN", b"\0group\0".to_vec()),
+        Chunk::new("MOGI", vec![0; 32]),
+        Chunk::new("MOSB", vec![]),
+        Chunk::new("MOVV", vec![]),
+        Chunk::new("MODN", b"a.m2\0".to_vec()),
+    ]);
+    match WmoFileVariant::new(&ChunkRef::from_bytes(&wmo_root_bytes).unwrap()).unwrap() {
+        WmoFileVariant::ROOT(root) => assert_eq!(root.mogn.0, vec!["group"]),
+        WmoFileVariant::GROUP(_) => panic!("expected a WMO root"),
+    }
+
+    let mut mogp_data = vec![0u8; ChunkMogpHeader::SIZE];
+    for (chunk_type, size) in &[("MOPY", 2), ("MOVI", 6), ("MOVT", 36), ("MONR", 36), ("MOTV", 24), ("MOLR", 2)] {
+        mogp_data.append(&mut Chunk::new(chunk_type, vec![0; *size]).to_bytes());
+    }
+    let wmo_group_bytes = chunks_to_bytes(&[
+        Chunk::new("MVER", 17u32.to_le_bytes().to_vec()),
+        Chunk::new("MOGP", mogp_data),
+    ]);
+    match WmoFileVariant::new(&ChunkRef::from_bytes(&wmo_group_bytes).unwrap()).unwrap() {
+        WmoFileVariant::GROUP(group) => assert_eq!(group.mesh().unwrap().indices, vec![0, 0, 0]),
+        WmoFileVariant::ROOT(_) => panic!("expected a WMO group"),
+    }
+
+    // chunks the fixtures need but nothing parses.
+    let unparsed = ["MFBO", "MOMT", "MOSB", "MOVV"];
+    let found: BTreeSet<String> = [adt_bytes, wdt_bytes, wmo_root_bytes, wmo_group_bytes].iter()
+        .flat_map(|it| chunk_types(it))
+        .filter(|it| !unparsed.contains(&it.as_str()))
+        .collect();
+    let listed: BTreeSet<String> = PARSED_CHUNK_TYPES.iter().map(|it| it.to_string()).collect();
+    assert_eq!(found, listed);
+}
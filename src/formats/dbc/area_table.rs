@@ -4,17 +4,17 @@ use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AreaTableDbcRow {
-    id: u32,
-    map_id: u32,
-    area_id: u32,
-    explore_flag: u32,
-    flags: u32,
-    sound_ambience_ref_id: u32,
-    zone_music_ref_id: u32,
-    zone_intro_music_ref_id: u32,
-    area_level: u32,
-    area_name: String,
-    faction_group_id: u32,
+    pub id: u32,
+    pub map_id: u32,
+    pub area_id: u32,
+    pub explore_flag: u32,
+    pub flags: u32,
+    pub sound_ambience_ref_id: u32,
+    pub zone_music_ref_id: u32,
+    pub zone_intro_music_ref_id: u32,
+    pub area_level: u32,
+    pub area_name: String,
+    pub faction_group_id: u32,
 }
 
 impl DbcRowMapper for AreaTableDbcRow {
@@ -0,0 +1,428 @@
+use crate::common::{err, R};
+use crate::byte_utils::BinRead;
+use crate::formats::adt::{AdtFile, TILE_SIZE};
+use crate::formats::chunk::MCNK_VERTEX_COUNT;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// Distance between two samples in world units.
+pub const SAMPLE_SPACING: f32 = TILE_SIZE / (TILE_GRID_SIZE - 1) as f32;
+
+/// Samples per chunk side, the last row and column are shared with the neighbouring chunk.
+pub const CHUNK_GRID_SIZE: usize = 17;
+/// Samples per tile side, the last row and column are shared with the neighbouring tile.
+pub const TILE_GRID_SIZE: usize = 16 * (CHUNK_GRID_SIZE - 1) + 1;
+
+/// Absolute terrain heights at half vertex resolution. MCVT outer vertices land on
+/// even/even samples and inner vertices on odd/odd samples, the remaining samples are
+/// interpolated from their neighbours. Samples not covered by any tile are NaN.
+#[derive(Debug)]
+pub struct Heightmap {
+    pub width: usize,
+    pub height: usize,
+    pub heights: Vec<f32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum HeightmapFormat {
+    /// 16 bit grayscale PNG, heights normalized between min and max.
+    Png,
+    /// 16 bit binary PGM (P5), heights normalized between min and max.
+    Pgm,
+    /// Little endian f32 per sample, row by row, heights as is.
+    Raw,
+}
+
+/// Written next to every exported heightmap as `<file>.json`, needed to map the values back.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HeightmapMetadata {
+    pub format: HeightmapFormat,
+    pub width: usize,
+    pub height: usize,
+    /// Height stored as 0 in the 16 bit formats.
+    pub min_height: f32,
+    /// Height stored as 65535 in the 16 bit formats.
+    pub max_height: f32,
+    /// `(x, y)` of the tile in the top left corner, if known.
+    pub origin_tile: Option<(u32, u32)>,
+}
+
+impl HeightmapMetadata {
+    /// Reads the `<path>.json` written along with the heightmap at `path`, if there is one.
+    pub fn read_for<P: AsRef<Path>>(path: P) -> R<Option<HeightmapMetadata>> {
+        let metadata_path = format!("{}.json", path.as_ref().display());
+        if !Path::new(&metadata_path).exists() {
+            return Ok(None);
+        }
+        let file = File::open(&metadata_path).with_context(|| format!("Failed to open {:?}", metadata_path))?;
+        let metadata = serde_json::from_reader(file).with_context(|| format!("Invalid metadata {:?}", metadata_path))?;
+        Ok(Some(metadata))
+    }
+}
+
+impl HeightmapFormat {
+    pub fn from_path(path: &Path) -> R<HeightmapFormat> {
+        let extension = path.extension()
+            .and_then(|it| it.to_str())
+            .map(|it| it.to_lowercase());
+        match extension.as_deref() {
+            Some("png") => Ok(HeightmapFormat::Png),
+            Some("pgm") => Ok(HeightmapFormat::Pgm),
+            Some("raw") | Some("r32") => Ok(HeightmapFormat::Raw),
+            _ => err(format!(
+                "Unsupported heightmap file {:?}, expected a .png, .pgm or .raw extension",
+                path.display()
+            )),
+        }
+    }
+}
+
+/// Index into MCVT of the vertex at the given chunk local sample, if there is one.
+pub fn mcvt_index(local_x: usize, local_y: usize) -> Option<usize> {
+    match (local_x % 2, local_y % 2) {
+        (0, 0) => Some(local_y / 2 * CHUNK_GRID_SIZE + local_x / 2),
+        (1, 1) => Some(local_y / 2 * CHUNK_GRID_SIZE + 9 + local_x / 2),
+        _ => None,
+    }
+}
+
+impl Heightmap {
+    pub fn new(width: usize, height: usize) -> Heightmap {
+        Heightmap {
+            width,
+            height,
+            heights: vec![f32::NAN; width * height],
+        }
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> f32 {
+        self.heights[y * self.width + x]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, height: f32) {
+        self.heights[y * self.width + x] = height;
+    }
+
+    /// Copies the heights of every MCNK of `adt` into the grid, with the tile's
+    /// top left sample at `(origin_x, origin_y)`. Call [Heightmap::fill_gaps] once done.
+    pub fn blit_adt(&mut self, adt: &AdtFile, origin_x: usize, origin_y: usize) -> R<()> {
+        if origin_x + TILE_GRID_SIZE > self.width || origin_y + TILE_GRID_SIZE > self.height {
+            return err(format!("Tile at sample ({}, {}) does not fit in the heightmap", origin_x, origin_y));
+        }
+        for mcnk in &adt.mcnk.items {
+            let (index_x, index_y) = (mcnk.header.index_x as usize, mcnk.header.index_y as usize);
+            if index_x >= 16 || index_y >= 16 {
+                return err(format!("Invalid MCNK index ({}, {})", index_x, index_y));
+            }
+            let chunk_x = origin_x + index_x * (CHUNK_GRID_SIZE - 1);
+            let chunk_y = origin_y + index_y * (CHUNK_GRID_SIZE - 1);
+            for local_y in 0..CHUNK_GRID_SIZE {
+                for local_x in 0..CHUNK_GRID_SIZE {
+                    if let Some(i) = mcvt_index(local_x, local_y) {
+                        let height = mcnk.header.position[2] + mcnk.mcvt.heights[i];
+                        self.set(chunk_x + local_x, chunk_y + local_y, height);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Interpolates the samples between vertices from their direct neighbours.
+    pub fn fill_gaps(&mut self) {
+        for y in 0..self.height {
+            for x in ((y + 1) % 2..self.width).step_by(2) {
+                if !self.get(x, y).is_nan() {
+                    continue;
+                }
+                let neighbours = [
+                    (x.wrapping_sub(1), y),
+                    (x + 1, y),
+                    (x, y.wrapping_sub(1)),
+                    (x, y + 1),
+                ];
+                let (sum, count) = neighbours.iter()
+                    .filter(|(x, y)| *x < self.width && *y < self.height)
+                    .map(|(x, y)| self.get(*x, *y))
+                    .filter(|height| !height.is_nan())
+                    .fold((0.0, 0), |(sum, count), height| (sum + height, count + 1));
+                if count > 0 {
+                    self.set(x, y, sum / count as f32);
+                }
+            }
+        }
+    }
+
+    /// Lowest and highest known heights, `(0, 0)` for an empty grid.
+    pub fn min_max(&self) -> (f32, f32) {
+        let known = self.heights.iter().filter(|it| !it.is_nan());
+        let (min, max) = known.fold((f32::MAX, f32::MIN), |(min, max), &it| (min.min(it), max.max(it)));
+        if min > max { (0.0, 0.0) } else { (min, max) }
+    }
+
+    /// Reads a heightmap, every sample becomes `base_height + value * height_scale`, where value
+    /// is normalized to 0..=1 for the png and pgm formats and taken as is for raw files.
+    /// Raw files carry no dimensions, `raw_size` defaults to a square grid.
+    pub fn read<P: AsRef<Path>>(
+        path: P,
+        base_height: f32,
+        height_scale: f32,
+        raw_size: Option<(usize, usize)>,
+    ) -> R<Heightmap> {
+        let path = path.as_ref();
+        let (width, height, values) = match HeightmapFormat::from_path(path)? {
+            HeightmapFormat::Png => read_png(path)?,
+            HeightmapFormat::Pgm => read_pgm(path)?,
+            HeightmapFormat::Raw => {
+                let bytes = std::fs::read(path).with_context(|| format!("Failed to read {:?}", path.display()))?;
+                let values = f32::read_all(&bytes)?;
+                let (width, height) = match raw_size {
+                    Some(size) => size,
+                    None => {
+                        let side = (values.len() as f64).sqrt() as usize;
+                        (side, side)
+                    }
+                };
+                (width, height, values)
+            }
+        };
+        if values.len() != width * height {
+            return err(format!(
+                "Heightmap {:?} holds {} samples, expected {}x{}",
+                path.display(), values.len(), width, height
+            ));
+        }
+        Ok(Heightmap {
+            width,
+            height,
+            heights: values.into_iter().map(|it| base_height + it * height_scale).collect(),
+        })
+    }
+
+    /// Inverse of [Heightmap::blit_adt]: rewrites MCVT and position.z of every MCNK of `adt`
+    /// from the samples starting at `(origin_x, origin_y)` and recomputes MCNR. Normals are
+    /// computed on the whole grid, so shared edges of neighbouring tiles stay seamless.
+    pub fn apply_to_adt(&self, adt: &mut AdtFile, origin_x: usize, origin_y: usize) -> R<()> {
+        if origin_x + TILE_GRID_SIZE > self.width || origin_y + TILE_GRID_SIZE > self.height {
+            return err(format!(
+                "Heightmap of {}x{} does not cover the tile at sample ({}, {})",
+                self.width, self.height, origin_x, origin_y
+            ));
+        }
+        for mcnk in &mut adt.mcnk.items {
+            let (index_x, index_y) = (mcnk.header.index_x as usize, mcnk.header.index_y as usize);
+            if index_x >= 16 || index_y >= 16 {
+                return err(format!("Invalid MCNK index ({}, {})", index_x, index_y));
+            }
+            let chunk_x = origin_x + index_x * (CHUNK_GRID_SIZE - 1);
+            let chunk_y = origin_y + index_y * (CHUNK_GRID_SIZE - 1);
+
+            let mut heights = vec![0.0; MCNK_VERTEX_COUNT];
+            let mut normals = vec![[0i8; 3]; MCNK_VERTEX_COUNT];
+            for local_y in 0..CHUNK_GRID_SIZE {
+                for local_x in 0..CHUNK_GRID_SIZE {
+                    if let Some(i) = mcvt_index(local_x, local_y) {
+                        heights[i] = self.get(chunk_x + local_x, chunk_y + local_y);
+                        normals[i] = self.normal_at(chunk_x + local_x, chunk_y + local_y);
+                    }
+                }
+            }
+            if heights.iter().any(|it| it.is_nan()) {
+                return err(format!("Heightmap has no data for MCNK ({}, {})", index_x, index_y));
+            }
+
+            let base = heights.iter().cloned().fold(f32::MAX, f32::min);
+            mcnk.header.position[2] = base;
+            mcnk.mcvt.heights = heights.into_iter().map(|it| it - base).collect();
+            mcnk.mcnr.normals = normals;
+        }
+        Ok(())
+    }
+
+    /// MCNR normal at a sample, ordered X, Y, Z (up) with 127 == 1.0. Samples advance along -Y
+    /// horizontally and along -X vertically, see MCNK position.
+    fn normal_at(&self, x: usize, y: usize) -> [i8; 3] {
+        let height_at = |x: usize, y: usize| {
+            let height = self.get(x.min(self.width - 1), y.min(self.height - 1));
+            if height.is_nan() { self.get(x, y) } else { height }
+        };
+        let (left, right) = (x.saturating_sub(1), (x + 1).min(self.width - 1));
+        let (up, down) = (y.saturating_sub(1), (y + 1).min(self.height - 1));
+        let d_row = (height_at(x, down) - height_at(x, up)) / ((down - up).max(1) as f32 * SAMPLE_SPACING);
+        let d_col = (height_at(right, y) - height_at(left, y)) / ((right - left).max(1) as f32 * SAMPLE_SPACING);
+
+        let normal = [d_row, d_col, 1.0];
+        let length = normal.iter().map(|it| it * it).sum::<f32>().sqrt();
+        let mut packed = [0i8; 3];
+        for (packed, component) in packed.iter_mut().zip(normal.iter()) {
+            *packed = (component / length * 127.0).round().clamp(-127.0, 127.0) as i8;
+        }
+        packed
+    }
+
+    /// Writes the grid along with its `<path>.json` metadata, unknown samples are written as the minimum.
+    pub fn write<P: AsRef<Path>>(&self, path: P, origin_tile: Option<(u32, u32)>) -> R<HeightmapMetadata> {
+        let path = path.as_ref();
+        let format = HeightmapFormat::from_path(path)?;
+        let (min_height, max_height) = self.min_max();
+        let heights = self.heights.iter().map(|it| if it.is_nan() { min_height } else { *it });
+
+        let file = File::create(path).with_context(|| format!("Failed to create {:?}", path.display()))?;
+        let mut writer = BufWriter::new(file);
+        match format {
+            HeightmapFormat::Raw => {
+                for height in heights {
+                    writer.write_all(&height.to_le_bytes())?;
+                }
+            }
+            HeightmapFormat::Png | HeightmapFormat::Pgm => {
+                let range = max_height - min_height;
+                let mut samples = Vec::with_capacity(self.heights.len() * 2);
+                for height in heights {
+                    let normalized = if range > 0.0 { (height - min_height) / range } else { 0.0 };
+                    let sample = (normalized * u16::MAX as f32).round() as u16;
+                    samples.extend_from_slice(&sample.to_be_bytes());
+                }
+                if format == HeightmapFormat::Png {
+                    let mut encoder = png::Encoder::new(&mut writer, self.width as u32, self.height as u32);
+                    encoder.set_color(png::ColorType::Grayscale);
+                    encoder.set_depth(png::BitDepth::Sixteen);
+                    encoder.write_header()?.write_image_data(&samples)?;
+                } else {
+                    write!(writer, "P5\n{} {}\n{}\n", self.width, self.height, u16::MAX)?;
+                    writer.write_all(&samples)?;
+                }
+            }
+        }
+        writer.flush()?;
+
+        let metadata = HeightmapMetadata {
+            format,
+            width: self.width,
+            height: self.height,
+            min_height,
+            max_height,
+            origin_tile,
+        };
+        let metadata_path = format!("{}.json", path.display());
+        let metadata_file = File::create(&metadata_path)
+            .with_context(|| format!("Failed to create {:?}", metadata_path))?;
+        serde_json::to_writer_pretty(metadata_file, &metadata)?;
+        Ok(metadata)
+    }
+}
+
+/// Reads a grayscale png, 8 bit samples are scaled to the 16 bit range.
+fn read_png(path: &Path) -> R<(usize, usize, Vec<f32>)> {
+    let file = File::open(path).with_context(|| format!("Failed to open {:?}", path.display()))?;
+    let decoder = png::Decoder::new(file);
+    let (info, mut reader) = decoder.read_info()?;
+    if info.color_type != png::ColorType::Grayscale {
+        return err(format!("{:?} is not a grayscale png but {:?}", path.display(), info.color_type));
+    }
+    let mut buf = vec![0; info.buffer_size()];
+    reader.next_frame(&mut buf)?;
+    let values = match info.bit_depth {
+        png::BitDepth::Sixteen => buf.chunks_exact(2)
+            .map(|it| u16::from_be_bytes([it[0], it[1]]) as f32 / u16::MAX as f32)
+            .collect(),
+        png::BitDepth::Eight => buf.iter().map(|it| *it as f32 / u8::MAX as f32).collect(),
+        depth => return err(format!("Unsupported png bit depth {:?} in {:?}", depth, path.display())),
+    };
+    Ok((info.width as usize, info.height as usize, values))
+}
+
+/// Reads a binary (P5) pgm of any max value.
+fn read_pgm(path: &Path) -> R<(usize, usize, Vec<f32>)> {
+    let bytes = std::fs::read(path).with_context(|| format!("Failed to read {:?}", path.display()))?;
+    let mut fields = Vec::with_capacity(4);
+    let mut offset = 0;
+    while fields.len() < 4 {
+        while offset < bytes.len() && bytes[offset].is_ascii_whitespace() {
+            offset += 1;
+        }
+        if bytes.get(offset) == Some(&b'#') {
+            while offset < bytes.len() && bytes[offset] != b'\n' {
+                offset += 1;
+            }
+            continue;
+        }
+        let start = offset;
+        while offset < bytes.len() && !bytes[offset].is_ascii_whitespace() {
+            offset += 1;
+        }
+        if start == offset {
+            return err(format!("Truncated pgm header in {:?}", path.display()));
+        }
+        fields.push(String::from_utf8_lossy(&bytes[start..offset]).to_string());
+    }
+    // a single whitespace separates the header from the samples.
+    offset += 1;
+
+    if fields[0] != "P5" {
+        return err(format!("{:?} is not a binary pgm (P5)", path.display()));
+    }
+    let parse = |field: &str| field.parse::<usize>()
+        .with_context(|| format!("Invalid pgm header value {:?} in {:?}", field, path.display()));
+    let (width, height, max_value) = (parse(&fields[1])?, parse(&fields[2])?, parse(&fields[3])?);
+    let samples = bytes.get(offset..).unwrap_or(&[]);
+    let values = if max_value > u8::MAX as usize {
+        samples.chunks_exact(2)
+            .map(|it| u16::from_be_bytes([it[0], it[1]]) as f32 / max_value as f32)
+            .collect()
+    } else {
+        samples.iter().map(|it| *it as f32 / max_value as f32).collect()
+    };
+    Ok((width, height, values))
+}
+
+#[cfg(test)]
+#[test]
+fn heightmap_mcvt_layout() {
+    assert_eq!(mcvt_index(0, 0), Some(0));
+    assert_eq!(mcvt_index(16, 0), Some(8));
+    assert_eq!(mcvt_index(1, 1), Some(9));
+    assert_eq!(mcvt_index(15, 15), Some(7 * 17 + 9 + 7));
+    assert_eq!(mcvt_index(16, 16), Some(144));
+    assert_eq!(mcvt_index(1, 0), None);
+}
+
+#[cfg(test)]
+#[test]
+fn heightmap_adt_round_trip() {
+    use crate::formats::adt::test_adt;
+
+    // integer heights continuous across MCNK edges, each MCNK based at its lowest vertex.
+    let height_at = |x: usize, y: usize| ((x * 3 + y * 5) % 11) as f32 + (x / 8) as f32;
+    let mut adt = test_adt();
+    for mcnk in &mut adt.mcnk.items {
+        let chunk_x = mcnk.header.index_x as usize * (CHUNK_GRID_SIZE - 1);
+        let chunk_y = mcnk.header.index_y as usize * (CHUNK_GRID_SIZE - 1);
+        let mut heights = vec![0.0; MCNK_VERTEX_COUNT];
+        for local_y in 0..CHUNK_GRID_SIZE {
+            for local_x in 0..CHUNK_GRID_SIZE {
+                if let Some(i) = mcvt_index(local_x, local_y) {
+                    heights[i] = height_at(chunk_x + local_x, chunk_y + local_y);
+                }
+            }
+        }
+        let base = heights.iter().cloned().fold(f32::MAX, f32::min);
+        mcnk.header.position[2] = base;
+        mcnk.mcvt.heights = heights.iter().map(|it| it - base).collect();
+    }
+
+    let mut heightmap = Heightmap::new(TILE_GRID_SIZE, TILE_GRID_SIZE);
+    heightmap.blit_adt(&adt, 0, 0).unwrap();
+    heightmap.fill_gaps();
+    assert!(heightmap.heights.iter().all(|it| !it.is_nan()));
+
+    let mut imported = test_adt();
+    heightmap.apply_to_adt(&mut imported, 0, 0).unwrap();
+    for (mcnk, original) in imported.mcnk.items.iter().zip(&adt.mcnk.items) {
+        assert_eq!(mcnk.header.position[2], original.header.position[2]);
+        assert_eq!(mcnk.mcvt.heights, original.mcvt.heights);
+    }
+}
@@ -0,0 +1,536 @@
+use crate::bin_layout;
+use crate::byte_utils::{BinRead, BinWrite};
+use crate::common::{err, R};
+use crate::formats::adt::{AdtFile, TILE_SIZE};
+use crate::formats::chunk::{Chunk, ChunkMcnkItem, ChunkRef};
+use crate::formats::mesh::{world_to_mesh_position, Mesh};
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+/// Liquid tiles per MCNK side, every tile spans one MCVT quad.
+pub const LIQUID_TILES_PER_CHUNK: usize = 8;
+const LIQUID_TILE_SIZE: f32 = TILE_SIZE / 16.0 / LIQUID_TILES_PER_CHUNK as f32;
+
+/// Liquid vertex formats (LVF), which per vertex arrays the vertex data holds.
+pub const LIQUID_VERTEX_FORMAT_HEIGHT_DEPTH: u16 = 0;
+pub const LIQUID_VERTEX_FORMAT_HEIGHT_UV: u16 = 1;
+pub const LIQUID_VERTEX_FORMAT_DEPTH: u16 = 2;
+pub const LIQUID_VERTEX_FORMAT_HEIGHT_UV_DEPTH: u16 = 3;
+
+bin_layout! {
+    /// One per MCNK, offsets are relative to the start of the MH2O data.
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct ChunkMh2oHeader {
+        pub offset_instances: u32,
+        pub layer_count: u32,
+        pub offset_attributes: u32,
+    }
+}
+
+bin_layout! {
+    /// 8x8 bit masks, one bit per liquid tile.
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct ChunkMh2oAttributes {
+        pub fishable: u64,
+        pub deep: u64,
+    }
+}
+
+bin_layout! {
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct ChunkMh2oInstanceHeader {
+        /// LiquidType.dbc id.
+        pub liquid_type: u16,
+        pub vertex_format: u16,
+        pub min_height: f32,
+        pub max_height: f32,
+        pub x_offset: u8,
+        pub y_offset: u8,
+        pub width: u8,
+        pub height: u8,
+        pub offset_exists_bitmap: u32,
+        pub offset_vertex_data: u32,
+    }
+}
+
+/// A liquid layer of a MCNK, covering `width` x `height` liquid tiles from `(x_offset, y_offset)`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChunkMh2oInstance {
+    pub liquid_type: u16,
+    pub vertex_format: u16,
+    pub min_height: f32,
+    pub max_height: f32,
+    pub x_offset: u8,
+    pub y_offset: u8,
+    pub width: u8,
+    pub height: u8,
+    /// Bit `y * width + x` tells whether the tile holds liquid, every tile does when missing.
+    pub exists: Option<u64>,
+    /// Flat at `min_height` when missing.
+    pub vertex_data: Option<ChunkMh2oVertexData>,
+}
+
+/// `(width + 1) * (height + 1)` vertices, row by row. Arrays the vertex format lacks are empty.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ChunkMh2oVertexData {
+    pub heights: Vec<f32>,
+    pub uvs: Vec<[u16; 2]>,
+    pub depths: Vec<u8>,
+}
+
+/// The liquids of one MCNK.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ChunkMh2oChunk {
+    pub instances: Vec<ChunkMh2oInstance>,
+    pub attributes: Option<ChunkMh2oAttributes>,
+}
+
+/// 3.3.5 water, one entry per MCNK in MCNK order.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChunkMh2o {
+    pub chunks: Vec<ChunkMh2oChunk>,
+}
+
+impl ChunkMh2oInstance {
+    pub fn vertex_count(&self) -> usize {
+        (self.width as usize + 1) * (self.height as usize + 1)
+    }
+
+    /// Whether liquid tile `(x, y)`, relative to the instance, holds liquid.
+    pub fn has_tile(&self, x: usize, y: usize) -> bool {
+        match self.exists {
+            Some(exists) => exists & (1 << (y * self.width as usize + x)) != 0,
+            None => true,
+        }
+    }
+
+    /// The tiles holding liquid as bits `y * 8 + x` of the whole MCNK.
+    pub fn chunk_mask(&self) -> u64 {
+        let mut mask = 0;
+        for y in 0..self.height as usize {
+            for x in 0..self.width as usize {
+                if self.has_tile(x, y) {
+                    mask |= 1 << ((y + self.y_offset as usize) * LIQUID_TILES_PER_CHUNK + x + self.x_offset as usize);
+                }
+            }
+        }
+        mask
+    }
+
+    /// Inverse of [ChunkMh2oInstance::chunk_mask], tiles outside of the instance are ignored.
+    pub fn set_chunk_mask(&mut self, mask: u64) {
+        let mut exists = 0;
+        for y in 0..self.height as usize {
+            for x in 0..self.width as usize {
+                let bit = (y + self.y_offset as usize) * LIQUID_TILES_PER_CHUNK + x + self.x_offset as usize;
+                if mask & (1 << bit) != 0 {
+                    exists |= 1 << (y * self.width as usize + x);
+                }
+            }
+        }
+        let tile_count = self.width as usize * self.height as usize;
+        let all_tiles = if tile_count == 64 { u64::MAX } else { (1 << tile_count) - 1 };
+        self.exists = if exists == all_tiles { None } else { Some(exists) };
+    }
+
+    fn exists_bitmap_size(&self) -> usize {
+        (self.width as usize * self.height as usize).div_ceil(8)
+    }
+
+    /// Height of vertex `(x, y)`, relative to the instance.
+    pub fn height_at(&self, x: usize, y: usize) -> f32 {
+        self.vertex_data.as_ref()
+            .and_then(|it| it.heights.get(y * (self.width as usize + 1) + x))
+            .copied()
+            .unwrap_or(self.min_height)
+    }
+
+    fn from_bytes(data: &[u8], header: ChunkMh2oInstanceHeader) -> R<ChunkMh2oInstance> {
+        if header.x_offset as usize + header.width as usize > LIQUID_TILES_PER_CHUNK
+            || header.y_offset as usize + header.height as usize > LIQUID_TILES_PER_CHUNK {
+            return err(format!(
+                "Liquid instance {}x{} at ({}, {}) exceeds its MCNK",
+                header.width, header.height, header.x_offset, header.y_offset
+            ));
+        }
+        let mut instance = ChunkMh2oInstance {
+            liquid_type: header.liquid_type,
+            vertex_format: header.vertex_format,
+            min_height: header.min_height,
+            max_height: header.max_height,
+            x_offset: header.x_offset,
+            y_offset: header.y_offset,
+            width: header.width,
+            height: header.height,
+            exists: None,
+            vertex_data: None,
+        };
+
+        if header.offset_exists_bitmap != 0 {
+            let start = header.offset_exists_bitmap as usize;
+            let size = instance.exists_bitmap_size();
+            let bitmap = data.get(start..start + size)
+                .with_context(|| format!("Liquid exists bitmap at {:#x} is out of range", start))?;
+            let mut bytes = [0; 8];
+            bytes[..size].copy_from_slice(bitmap);
+            instance.exists = Some(u64::from_le_bytes(bytes));
+        }
+
+        if header.offset_vertex_data != 0 {
+            let count = instance.vertex_count();
+            let (has_heights, has_uvs, has_depths) = match header.vertex_format {
+                LIQUID_VERTEX_FORMAT_HEIGHT_DEPTH => (true, false, true),
+                LIQUID_VERTEX_FORMAT_HEIGHT_UV => (true, true, false),
+                LIQUID_VERTEX_FORMAT_DEPTH => (false, false, true),
+                LIQUID_VERTEX_FORMAT_HEIGHT_UV_DEPTH => (true, true, true),
+                format => return err(format!("Unknown liquid vertex format {}", format)),
+            };
+            let mut offset = header.offset_vertex_data as usize;
+            let mut read = |record_size: usize| {
+                let bytes = data.get(offset..offset + count * record_size)
+                    .with_context(|| format!("Liquid vertex data at {:#x} is out of range", offset));
+                offset += count * record_size;
+                bytes
+            };
+            let mut vertex_data = ChunkMh2oVertexData::default();
+            if has_heights {
+                vertex_data.heights = f32::read_all(read(f32::SIZE)?)?;
+            }
+            if has_uvs {
+                vertex_data.uvs = <[u16; 2]>::read_all(read(<[u16; 2]>::SIZE)?)?;
+            }
+            if has_depths {
+                vertex_data.depths = read(1)?.to_vec();
+            }
+            instance.vertex_data = Some(vertex_data);
+        }
+        Ok(instance)
+    }
+}
+
+impl ChunkMh2oChunk {
+    /// Replaces the liquids of the MCNK by a single layer of `liquid_type` flat at `height` on the
+    /// liquid tiles in `mask` (bit `y * 8 + x`). Depths are measured down to `terrain`.
+    pub fn set_liquid(&mut self, mask: u64, liquid_type: u16, height: f32, terrain: &ChunkMcnkItem) {
+        self.instances.clear();
+        self.attributes = None;
+        if mask == 0 {
+            return;
+        }
+        let tiles = (0..LIQUID_TILES_PER_CHUNK * LIQUID_TILES_PER_CHUNK).filter(|i| mask & (1 << i) != 0);
+        let (columns, rows): (Vec<usize>, Vec<usize>) = tiles
+            .map(|i| (i % LIQUID_TILES_PER_CHUNK, i / LIQUID_TILES_PER_CHUNK))
+            .unzip();
+        let (x_offset, y_offset) = (*columns.iter().min().unwrap(), *rows.iter().min().unwrap());
+        let width = columns.iter().max().unwrap() + 1 - x_offset;
+        let height_in_tiles = rows.iter().max().unwrap() + 1 - y_offset;
+
+        let mut instance = ChunkMh2oInstance {
+            liquid_type,
+            vertex_format: LIQUID_VERTEX_FORMAT_HEIGHT_DEPTH,
+            min_height: height,
+            max_height: height,
+            x_offset: x_offset as u8,
+            y_offset: y_offset as u8,
+            width: width as u8,
+            height: height_in_tiles as u8,
+            exists: None,
+            vertex_data: None,
+        };
+        instance.set_chunk_mask(mask);
+        // liquid vertices sit on the outer MCVT vertices.
+        let depths = (0..=height_in_tiles)
+            .flat_map(|y| (0..=width).map(move |x| (x + x_offset, y + y_offset)))
+            .map(|(x, y)| {
+                let terrain_height = terrain.header.position[2] + terrain.mcvt.heights.get(y * 17 + x).copied().unwrap_or(0.0);
+                (height - terrain_height).clamp(0.0, 255.0) as u8
+            })
+            .collect();
+        instance.vertex_data = Some(ChunkMh2oVertexData {
+            heights: vec![height; instance.vertex_count()],
+            uvs: vec![],
+            depths,
+        });
+        self.instances.push(instance);
+        self.attributes = Some(ChunkMh2oAttributes { fishable: mask, deep: 0 });
+    }
+
+    /// Like [ChunkMh2oChunk::set_liquid], only touching the liquid tiles in `mask`: they are taken
+    /// out of the layers already there and covered by a new layer, other tiles keep their liquid
+    /// type, height and attributes.
+    pub fn add_liquid(&mut self, mask: u64, liquid_type: u16, height: f32, terrain: &ChunkMcnkItem) {
+        let mut layer = ChunkMh2oChunk::default();
+        layer.set_liquid(mask, liquid_type, height, terrain);
+        self.clear_liquid(mask);
+        let (fishable, deep) = self.attributes.take().map_or((0, 0), |it| (it.fishable, it.deep));
+        self.instances.append(&mut layer.instances);
+        if !self.instances.is_empty() {
+            self.attributes = Some(ChunkMh2oAttributes { fishable: fishable | mask, deep });
+        }
+    }
+
+    /// Removes the liquid tiles in `mask` (bit `y * 8 + x`) from every layer, dropping emptied layers.
+    pub fn clear_liquid(&mut self, mask: u64) {
+        for instance in &mut self.instances {
+            instance.set_chunk_mask(instance.chunk_mask() & !mask);
+        }
+        self.instances.retain(|it| it.chunk_mask() != 0);
+        self.attributes = match (&self.attributes, self.instances.is_empty()) {
+            (Some(attributes), false) => Some(ChunkMh2oAttributes {
+                fishable: attributes.fishable & !mask,
+                deep: attributes.deep & !mask,
+            }),
+            _ => None,
+        };
+    }
+}
+
+impl ChunkMh2o {
+    /// Whether any MCNK holds liquid, an ADT without is written without MH2O.
+    pub fn is_empty(&self) -> bool {
+        self.chunks.iter().all(|it| it.instances.is_empty())
+    }
+
+    pub fn from_chunk(c: &ChunkRef) -> R<ChunkMh2o> {
+        c.expect_type("MH2O")?;
+        c.expect_size_at_least(256 * ChunkMh2oHeader::SIZE)?;
+        let headers = ChunkMh2oHeader::read_all(&c.data[..256 * ChunkMh2oHeader::SIZE])?;
+        let chunks = headers.iter().enumerate()
+            .map(|(i, header)| {
+                ChunkMh2o::read_chunk(c.data, header).with_context(|| format!("Failed to read liquids of MCNK {}", i))
+            })
+            .collect::<R<Vec<ChunkMh2oChunk>>>()?;
+        Ok(ChunkMh2o { chunks })
+    }
+
+    fn read_chunk(data: &[u8], header: &ChunkMh2oHeader) -> R<ChunkMh2oChunk> {
+        let mut chunk = ChunkMh2oChunk::default();
+        if header.layer_count > 0 {
+            let start = header.offset_instances as usize;
+            let end = start + header.layer_count as usize * ChunkMh2oInstanceHeader::SIZE;
+            let instances = data.get(start..end)
+                .with_context(|| format!("Liquid instances at {:#x} are out of range", start))?;
+            for instance in ChunkMh2oInstanceHeader::read_all(instances)? {
+                chunk.instances.push(ChunkMh2oInstance::from_bytes(data, instance)?);
+            }
+        }
+        if header.offset_attributes != 0 {
+            let start = header.offset_attributes as usize;
+            let attributes = data.get(start..start + ChunkMh2oAttributes::SIZE)
+                .with_context(|| format!("Liquid attributes at {:#x} are out of range", start))?;
+            chunk.attributes = Some(ChunkMh2oAttributes::from_bytes(attributes)?);
+        }
+        Ok(chunk)
+    }
+
+    /// Lays out the header table, then per MCNK its instances, attributes, bitmaps and vertex data.
+    pub fn to_chunk(&self) -> Chunk {
+        let mut headers = vec![];
+        let mut data = vec![];
+        let data_start = 256 * ChunkMh2oHeader::SIZE;
+        for chunk in &self.chunks {
+            let offset_of = |data: &Vec<u8>| (data_start + data.len()) as u32;
+            let offset_instances = if chunk.instances.is_empty() { 0 } else { offset_of(&data) };
+            let instances_start = data.len();
+            data.resize(data.len() + chunk.instances.len() * ChunkMh2oInstanceHeader::SIZE, 0);
+            let offset_attributes = match &chunk.attributes {
+                Some(attributes) => {
+                    let offset = offset_of(&data);
+                    attributes.write_to(&mut data);
+                    offset
+                }
+                None => 0,
+            };
+
+            let mut instance_headers = vec![];
+            for instance in &chunk.instances {
+                let offset_exists_bitmap = match instance.exists {
+                    Some(exists) => {
+                        let offset = offset_of(&data);
+                        data.extend_from_slice(&exists.to_le_bytes()[..instance.exists_bitmap_size()]);
+                        offset
+                    }
+                    None => 0,
+                };
+                let offset_vertex_data = match &instance.vertex_data {
+                    Some(vertex_data) => {
+                        let offset = offset_of(&data);
+                        vertex_data.heights.write_to(&mut data);
+                        vertex_data.uvs.write_to(&mut data);
+                        data.extend_from_slice(&vertex_data.depths);
+                        offset
+                    }
+                    None => 0,
+                };
+                instance_headers.push(ChunkMh2oInstanceHeader {
+                    liquid_type: instance.liquid_type,
+                    vertex_format: instance.vertex_format,
+                    min_height: instance.min_height,
+                    max_height: instance.max_height,
+                    x_offset: instance.x_offset,
+                    y_offset: instance.y_offset,
+                    width: instance.width,
+                    height: instance.height,
+                    offset_exists_bitmap,
+                    offset_vertex_data,
+                });
+            }
+            let instances = instance_headers.to_bytes();
+            data[instances_start..instances_start + instances.len()].copy_from_slice(&instances);
+
+            headers.push(ChunkMh2oHeader {
+                offset_instances,
+                layer_count: chunk.instances.len() as u32,
+                offset_attributes,
+            });
+        }
+        headers.resize(256, ChunkMh2oHeader { offset_instances: 0, layer_count: 0, offset_attributes: 0 });
+
+        let mut bytes = headers.to_bytes();
+        bytes.append(&mut data);
+        Chunk::new("MH2O", bytes)
+    }
+}
+
+impl AdtFile {
+    /// The surface of every liquid instance in world coordinates, two triangles per liquid tile.
+    /// UVs span 0 to 1 over each MCNK like the terrain ones.
+    pub fn water_mesh(&self) -> Mesh {
+        let mut mesh = Mesh::default();
+        let mh2o = match &self.mh2o {
+            Some(mh2o) => mh2o,
+            None => return mesh,
+        };
+        for (chunk, liquids) in self.mcnk.items.iter().zip(&mh2o.chunks) {
+            let [corner_x, corner_y, _] = chunk.header.position;
+            for instance in &liquids.instances {
+                let first_vertex = mesh.positions.len() as u32;
+                let (width, height) = (instance.width as usize, instance.height as usize);
+                for y in 0..=height {
+                    for x in 0..=width {
+                        let (row, column) = (y + instance.y_offset as usize, x + instance.x_offset as usize);
+                        mesh.positions.push(world_to_mesh_position([
+                            corner_x - row as f32 * LIQUID_TILE_SIZE,
+                            corner_y - column as f32 * LIQUID_TILE_SIZE,
+                            instance.height_at(x, y),
+                        ]));
+                        mesh.normals.push([0.0, 1.0, 0.0]);
+                        mesh.uvs.push([
+                            column as f32 / LIQUID_TILES_PER_CHUNK as f32,
+                            row as f32 / LIQUID_TILES_PER_CHUNK as f32,
+                        ]);
+                    }
+                }
+                for y in 0..height {
+                    for x in 0..width {
+                        if !instance.has_tile(x, y) {
+                            continue;
+                        }
+                        let top_left = first_vertex + (y * (width + 1) + x) as u32;
+                        let bottom_left = top_left + width as u32 + 1;
+                        mesh.indices.extend_from_slice(&[
+                            top_left, bottom_left + 1, top_left + 1,
+                            top_left, bottom_left, bottom_left + 1,
+                        ]);
+                    }
+                }
+            }
+        }
+        mesh
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn mh2o_round_trip() {
+    let mut chunks = vec![ChunkMh2oChunk::default(); 256];
+    chunks[1].instances.push(ChunkMh2oInstance {
+        liquid_type: 2,
+        vertex_format: LIQUID_VERTEX_FORMAT_HEIGHT_UV_DEPTH,
+        min_height: 10.0,
+        max_height: 12.0,
+        x_offset: 1,
+        y_offset: 2,
+        width: 3,
+        height: 2,
+        exists: Some(0b111011),
+        vertex_data: Some(ChunkMh2oVertexData {
+            heights: (0..12).map(|it| 10.0 + it as f32 / 6.0).collect(),
+            uvs: vec![[0, 0]; 12],
+            depths: vec![255; 12],
+        }),
+    });
+    chunks[1].attributes = Some(ChunkMh2oAttributes { fishable: u64::MAX, deep: 0 });
+    chunks[2].instances.push(ChunkMh2oInstance {
+        liquid_type: 5,
+        vertex_format: LIQUID_VERTEX_FORMAT_DEPTH,
+        min_height: 0.0,
+        max_height: 0.0,
+        x_offset: 0,
+        y_offset: 0,
+        width: 8,
+        height: 8,
+        exists: None,
+        vertex_data: None,
+    });
+    let chunk = ChunkMh2o { chunks }.to_chunk();
+    let mh2o = ChunkMh2o::from_chunk(&chunk.as_chunk_ref()).unwrap();
+    assert_eq!(mh2o.to_chunk().data, chunk.data);
+
+    let instance = &mh2o.chunks[1].instances[0];
+    assert!(!instance.has_tile(2, 0));
+    assert_eq!(instance.height_at(3, 2), 10.0 + 11.0 / 6.0);
+    assert_eq!(mh2o.chunks[2].instances[0].height_at(4, 4), 0.0);
+
+    let mut adt = crate::formats::adt::test_adt();
+    adt.mh2o = Some(mh2o);
+    let mesh = adt.water_mesh();
+    assert_eq!(mesh.positions.len(), 12 + 81);
+    assert_eq!(mesh.triangle_count(), (5 + 64) * 2);
+}
+
+#[cfg(test)]
+#[test]
+fn mh2o_set_and_clear_liquid() {
+    let adt = crate::formats::adt::test_adt();
+    let terrain = &adt.mcnk.items[0];
+    let mut chunk = ChunkMh2oChunk::default();
+    // tiles (2, 1), (3, 1) and (3, 2).
+    let mask = 1 << 10 | 1 << 11 | 1 << 19;
+    chunk.set_liquid(mask, 1, 5.0, terrain);
+    let instance = &chunk.instances[0];
+    assert_eq!((instance.x_offset, instance.y_offset, instance.width, instance.height), (2, 1, 2, 2));
+    assert_eq!(instance.exists, Some(0b1011));
+    assert_eq!(instance.chunk_mask(), mask);
+    assert_eq!(instance.vertex_data.as_ref().unwrap().depths, vec![5; 9]);
+
+    chunk.clear_liquid(1 << 11);
+    assert_eq!(chunk.instances[0].chunk_mask(), 1 << 10 | 1 << 19);
+    assert_eq!(chunk.attributes.as_ref().unwrap().fishable, 1 << 10 | 1 << 19);
+    chunk.clear_liquid(u64::MAX);
+    assert!(chunk.instances.is_empty() && chunk.attributes.is_none());
+
+    chunk.set_liquid(u64::MAX, 2, 0.0, terrain);
+    assert_eq!(chunk.instances[0].exists, None);
+}
+
+#[cfg(test)]
+#[test]
+fn mh2o_add_liquid_keeps_existing_layers() {
+    let adt = crate::formats::adt::test_adt();
+    let terrain = &adt.mcnk.items[0];
+    let mut chunk = ChunkMh2oChunk::default();
+    // ocean at -5 over the whole MCNK, deep on tile (7, 7).
+    chunk.set_liquid(u64::MAX, 2, -5.0, terrain);
+    chunk.attributes.as_mut().unwrap().deep = 1 << 63;
+
+    // water at 20 on part of the MCNK, tiles (4, 0) and (5, 0).
+    let mask = 0b11_0000;
+    chunk.add_liquid(mask, 1, 20.0, terrain);
+    assert_eq!(chunk.instances.len(), 2);
+    let (ocean, water) = (&chunk.instances[0], &chunk.instances[1]);
+    assert_eq!((ocean.liquid_type, ocean.min_height, ocean.chunk_mask()), (2, -5.0, !mask));
+    assert_eq!((water.liquid_type, water.min_height, water.chunk_mask()), (1, 20.0, mask));
+    let attributes = chunk.attributes.as_ref().unwrap();
+    assert_eq!((attributes.fishable, attributes.deep), (u64::MAX, 1 << 63));
+}
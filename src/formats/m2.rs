@@ -1,15 +1,128 @@
 use anyhow::Context;
 use serde::{Deserialize, Serialize};
-use crate::common::R;
+use crate::common::{R, err, map_file};
 use std::path::{Path};
-use crate::byte_utils::VecUtils;
+use crate::byte_utils::{VecUtils, BinRead, ByteCursor};
+use crate::bin_layout;
+use crate::formats::mesh::{world_to_mesh_position, Mesh};
+use crate::command_handler::resolve_map_assets::join_path_ignoring_casing;
 
 
-#[derive(Debug, Serialize, Deserialize)]
-struct M2Array<T> {
-    pub size: u32,
-    pub offset: u32,
-    pub elements: Vec<T>,
+bin_layout! {
+    /// Count and offset of an array stored elsewhere in the file.
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct M2Array {
+        pub size: u32,
+        pub offset: u32,
+    }
+}
+
+impl M2Array {
+    /// Reads the `size` records starting at `offset`.
+    pub fn read<T: BinRead>(&self, bytes: &[u8]) -> R<Vec<T>> {
+        let start = self.offset as usize;
+        let end = start + self.size as usize * T::SIZE;
+        let data = bytes.get(start..end)
+            .with_context(|| format!("M2 array [{:#x}..{:#x}] is out of range of {} bytes", start, end, bytes.len()))?;
+        T::read_all(data)
+    }
+}
+
+bin_layout! {
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct M2Header {
+        pub magic: [u8; 4],
+        pub version: u32,
+        pub name: M2Array,
+        pub global_flags: u32,
+        pub global_loops: M2Array,
+        pub sequences: M2Array,
+        pub sequence_lookups: M2Array,
+        pub bones: M2Array,
+        pub key_bone_lookup: M2Array,
+        pub vertices: M2Array,
+        pub num_skin_profiles: u32,
+        pub colors: M2Array,
+        pub textures: M2Array,
+        pub texture_weights: M2Array,
+        pub texture_transforms: M2Array,
+        pub replaceable_texture_lookup: M2Array,
+        pub materials: M2Array,
+        pub bone_lookup_table: M2Array,
+        pub texture_lookup_table: M2Array,
+        pub tex_unit_lookup_table: M2Array,
+        pub transparency_lookup_table: M2Array,
+        pub texture_transforms_lookup_table: M2Array,
+        pub bounding_box: [f32; 6],
+        pub bounding_sphere_radius: f32,
+        pub collision_box: [f32; 6],
+        pub collision_sphere_radius: f32,
+        pub collision_triangles: M2Array,
+        pub collision_vertices: M2Array,
+        pub collision_normals: M2Array,
+        pub attachments: M2Array,
+        pub attachment_lookup_table: M2Array,
+        pub events: M2Array,
+        pub lights: M2Array,
+        pub cameras: M2Array,
+        pub camera_lookup_table: M2Array,
+        pub ribbon_emitters: M2Array,
+        pub particle_emitters: M2Array,
+    }
+}
+
+bin_layout! {
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct M2Texture {
+        pub texture_type: u32,
+        pub flags: u32,
+        pub filename: M2Array,
+    }
+}
+
+bin_layout! {
+    /// The leading part of a particle emitter, the animated tracks that follow are skipped.
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct M2ParticleHeader {
+        pub particle_id: u32,
+        pub flags_1: u16,
+        pub flags_2: u16,
+        pub pos: [f32; 3],
+        pub bone: u16,
+        pub texture_id: u16,
+        pub model_file_name: M2Array,
+        pub particle_name: M2Array,
+        pub blending_type: u8,
+        pub emitter_type: u8,
+        pub particle_dbc_color: u16,
+    }
+}
+
+bin_layout! {
+    #[derive(Debug)]
+    pub struct M2Vertex {
+        pub position: [f32; 3],
+        pub bone_weights: [u8; 4],
+        pub bone_indices: [u8; 4],
+        pub normal: [f32; 3],
+        pub tex_coords: [[f32; 2]; 2],
+    }
+}
+
+bin_layout! {
+    /// Header of a `<model>0<n>.skin` file, the triangles of a level of detail of the model.
+    #[derive(Debug)]
+    pub struct M2SkinHeader {
+        pub magic: [u8; 4],
+        /// Indices into the vertices of the model.
+        pub vertices: M2Array,
+        /// Triangles, as indices into `vertices`.
+        pub indices: M2Array,
+        pub bones: M2Array,
+        pub submeshes: M2Array,
+        pub batches: M2Array,
+        pub bone_count_max: u32,
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -40,25 +153,63 @@ pub struct M2File {
 impl M2File {
     pub fn from_path<P: AsRef<Path>>(path: P) -> R<M2File> {
         let path = path.as_ref().to_path_buf();
-        let data = std::fs::read(path.clone())
+        let data = map_file(&path)
             .with_context(|| format!("Failed to m2 file '{}'", path.display()))?;
-        M2File::from_bytes(data)
+        M2File::from_bytes(&data)
             .context("Failed to read M2 file.")
     }
 
-    fn from_bytes(bytes: Vec<u8>) -> R<M2File> {
+    /// The geometry of a model at its highest level of detail, from its first skin file
+    /// (`<model>00.skin` next to it, in any casing). Vertices are converted like world coordinates, see [world_to_mesh_position].
+    pub fn read_mesh<P: AsRef<Path>>(path: P) -> R<Mesh> {
+        let path = path.as_ref();
+        let data = map_file(path)?;
+        let header = M2Header::from_bytes(&data)
+            .with_context(|| format!("Failed to read M2 header of {:?}", path.display()))?;
+        if header.num_skin_profiles == 0 {
+            return err(format!("M2 {:?} has no skin profiles", path.display()));
+        }
+        let vertices: Vec<M2Vertex> = header.vertices.read(&data)
+            .with_context(|| format!("Failed to read M2 vertices of {:?}", path.display()))?;
+
+        let file_stem = path.file_stem().and_then(|it| it.to_str()).unwrap_or_default();
+        let skin_name = format!("{}00.skin", file_stem);
+        let folder = path.parent().filter(|it| !it.as_os_str().is_empty()).unwrap_or_else(|| Path::new("."));
+        let skin_path = join_path_ignoring_casing(folder, &skin_name).unwrap_or_else(|| folder.join(&skin_name));
+        let skin_data = map_file(&skin_path)?;
+        let skin = M2SkinHeader::from_bytes(&skin_data)
+            .with_context(|| format!("Failed to read skin header of {:?}", skin_path.display()))?;
+        let skin_vertices: Vec<u16> = skin.vertices.read(&skin_data)?;
+        let skin_indices: Vec<u16> = skin.indices.read(&skin_data)?;
+
+        let mut mesh = Mesh::default();
+        for index in skin_vertices {
+            let vertex = vertices.get(index as usize)
+                .with_context(|| format!("Skin {:?} uses missing vertex {}", skin_path.display(), index))?;
+            mesh.positions.push(world_to_mesh_position(vertex.position));
+            mesh.normals.push(world_to_mesh_position(vertex.normal));
+            mesh.uvs.push(vertex.tex_coords[0]);
+        }
+        for index in skin_indices {
+            if index as usize >= mesh.positions.len() {
+                return err(format!("Skin {:?} uses missing vertex {}", skin_path.display(), index));
+            }
+            mesh.indices.push(index as u32);
+        }
+        Ok(mesh)
+    }
+
+    fn from_bytes(bytes: &[u8]) -> R<M2File> {
+        let header = M2Header::from_bytes(bytes)?;
         let magic = bytes.get_string(0, 4)?;
-        let version = bytes.get_u32(4)?;
-        let name_offset = bytes.get_u32(12)?;
-        let name = bytes.get_string_null_terminated(name_offset as usize)?;
+        let name = bytes.get_string_null_terminated(header.name.offset as usize)?;
 
-        let n_textures = bytes.get_u32(80)?;
-        let text_offset = bytes.get_u32(84)?;
-        let mut texture_builder = Vec::with_capacity(n_textures as usize);
+        let mut texture_builder = Vec::with_capacity(header.textures.size as usize);
 
-        for i in 0..n_textures {
-            let name_offset = bytes.get_u32((text_offset + (12 * (i + 1)) + (i * 4)) as usize)?;
-            let name = bytes.get_string_null_terminated(name_offset as usize);
+        for i in 0..header.textures.size as usize {
+            let texture_offset = header.textures.offset as usize + i * M2Texture::SIZE;
+            let texture: M2Texture = ByteCursor::at(bytes, texture_offset).read()?;
+            let name = bytes.get_string_null_terminated(texture.filename.offset as usize);
             if let Ok(name) = name {
                 // a lot of garbage names.. filtering them out for now.
                 if name.ends_with("blp") || name.ends_with("BLP") {
@@ -67,63 +218,46 @@ impl M2File {
             }
         }
 
-        const PARTICLE_SIZE: u32 = 476;
+        const PARTICLE_SIZE: usize = 476;
 
-        let n_particles = bytes.get_u32(0x128)?;
-        let particle_offset = bytes.get_u32(0x12C)?;
+        let n_particles = header.particle_emitters.size;
         let mut particle_builder = Vec::with_capacity(n_particles as usize);
 
-        for i in 0..n_particles {
-            let offset = (particle_offset + (i * PARTICLE_SIZE)) as usize;
-            let particle_id = bytes.get_u32(offset)?;
-            let flags_1 = bytes.get_u16(offset + 4)?;
-            let flags_2 = bytes.get_u16(offset + 6)?;
-            let pos = {
-                let pos_1 = bytes.get_f32(offset + 8)?;
-                let pos_2 = bytes.get_f32(offset + 12)?;
-                let pos_3 = bytes.get_f32(offset + 16)?;
-                [pos_1, pos_2, pos_3]
-            };
-            let bone = bytes.get_u16(offset + 20)?;
-            let texture_id = bytes.get_u16(offset + 22)?;
-            let model_file_name = {
-                let len = bytes.get_u32(offset + 24)? as usize;
-                let offs = bytes.get_u32(offset + 28)? as usize;
-                bytes.get_string(offs, len)?
-            };
-            let particle_name = {
-                let len = bytes.get_u32(offset + 32)? as usize;
-                let offs = bytes.get_u32(offset + 36)? as usize;
-                bytes.get_string(offs, len)?
-            };
-
-            let blending_type = bytes.get_byte(offset + 40)?;
-            let emitter_type = bytes.get_byte(offset + 41)?;
-            let particle_dbc_color = bytes.get_u16(offset + 42)?;
+        for i in 0..n_particles as usize {
+            let offset = header.particle_emitters.offset as usize + i * PARTICLE_SIZE;
+            let particle: M2ParticleHeader = ByteCursor::at(bytes, offset).read()?;
+            let model_file_name = bytes.get_string(
+                particle.model_file_name.offset as usize,
+                particle.model_file_name.size as usize,
+            )?;
+            let particle_name = bytes.get_string(
+                particle.particle_name.offset as usize,
+                particle.particle_name.size as usize,
+            )?;
 
             particle_builder.push(M2Particle {
-                particle_id,
-                flags_1,
-                flags_2,
-                pos,
-                bone,
+                particle_id: particle.particle_id,
+                flags_1: particle.flags_1,
+                flags_2: particle.flags_2,
+                pos: particle.pos,
+                bone: particle.bone,
                 model_file_name,
                 particle_name,
-                texture_id,
-                blending_type,
-                emitter_type,
-                particle_dbc_color,
+                texture_id: particle.texture_id,
+                blending_type: particle.blending_type,
+                emitter_type: particle.emitter_type,
+                particle_dbc_color: particle.particle_dbc_color,
             });
         }
 
 
         Ok(M2File {
             magic,
-            version,
+            version: header.version,
             name,
             textures: texture_builder,
             particles: particle_builder,
             n_particles,
         })
     }
-}
\ No newline at end of file
+}
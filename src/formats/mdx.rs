@@ -2,7 +2,7 @@ use serde::{Deserialize, Serialize};
 use crate::common::R;
 use std::path::Path;
 use crate::byte_utils::VecUtils;
-use crate::formats::chunk::{Chunk, ChunkVecUtils};
+use crate::formats::chunk::{Chunk, ChunkRef, ChunkVecUtils};
 
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -34,14 +34,14 @@ trait ChunkExt {
     fn get_texs(&self) -> R<ChunkTexs>;
 }
 
-impl ChunkExt for Vec<Chunk> {
+impl ChunkExt for [ChunkRef<'_>] {
     fn get_texs(&self) -> R<ChunkTexs> {
-        ChunkTexs::from_chunk(self.get_chunk_of_type("TEXS"))
+        ChunkTexs::from_chunk(self.get_chunk_of_type("TEXS")?)
     }
 }
 
 impl ChunkTexs {
-    fn from_chunk(chunk: &Chunk) -> R<ChunkTexs> {
+    fn from_chunk(chunk: &ChunkRef) -> R<ChunkTexs> {
         let strs = chunk.data.get_null_terminated_strings()?;
         println!("{:?}", strs);
         Ok(ChunkTexs {
@@ -0,0 +1,304 @@
+use crate::common::R;
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// An indexed triangle list. Positions are right handed and Y up, as glTF and most modelling
+/// tools expect them, see [world_to_mesh_position]. Normals and UVs are either empty or given
+/// per vertex, triangles are counter clockwise.
+#[derive(Debug, Default, Clone)]
+pub struct Mesh {
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub uvs: Vec<[f32; 2]>,
+    pub indices: Vec<u32>,
+}
+
+/// World coordinates (X north, Y west, Z up) to mesh coordinates (X east, Y up, Z south).
+pub fn world_to_mesh_position(position: [f32; 3]) -> [f32; 3] {
+    [-position[1], position[2], -position[0]]
+}
+
+/// Unit quaternion `[x, y, z, w]` rotating by `degrees` counter clockwise around `axis`.
+pub fn quaternion_from_axis_angle(axis: [f32; 3], degrees: f32) -> [f32; 4] {
+    let (sin, cos) = (degrees.to_radians() / 2.0).sin_cos();
+    [axis[0] * sin, axis[1] * sin, axis[2] * sin, cos]
+}
+
+/// Rotation applying `b` first, then `a`.
+pub fn quaternion_mul(a: [f32; 4], b: [f32; 4]) -> [f32; 4] {
+    [
+        a[3] * b[0] + a[0] * b[3] + a[1] * b[2] - a[2] * b[1],
+        a[3] * b[1] - a[0] * b[2] + a[1] * b[3] + a[2] * b[0],
+        a[3] * b[2] + a[0] * b[1] - a[1] * b[0] + a[2] * b[3],
+        a[3] * b[3] - a[0] * b[0] - a[1] * b[1] - a[2] * b[2],
+    ]
+}
+
+impl Mesh {
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    pub fn append(&mut self, other: &Mesh) {
+        let offset = self.positions.len() as u32;
+        self.positions.extend_from_slice(&other.positions);
+        self.normals.extend_from_slice(&other.normals);
+        self.uvs.extend_from_slice(&other.uvs);
+        self.indices.extend(other.indices.iter().map(|it| it + offset));
+    }
+
+    fn has_normals(&self) -> bool {
+        !self.normals.is_empty() && self.normals.len() == self.positions.len()
+    }
+
+    fn has_uvs(&self) -> bool {
+        !self.uvs.is_empty() && self.uvs.len() == self.positions.len()
+    }
+}
+
+/// Writes named meshes as the objects of a Wavefront OBJ file.
+pub fn write_obj<P: AsRef<Path>>(path: P, objects: &[(String, Mesh)]) -> R<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    // OBJ indices are 1 based and shared by the whole file.
+    let (mut position_offset, mut normal_offset, mut uv_offset) = (1, 1, 1);
+    for (name, mesh) in objects {
+        writeln!(writer, "o {}", name)?;
+        for [x, y, z] in &mesh.positions {
+            writeln!(writer, "v {} {} {}", x, y, z)?;
+        }
+        let (has_normals, has_uvs) = (mesh.has_normals(), mesh.has_uvs());
+        if has_normals {
+            for [x, y, z] in &mesh.normals {
+                writeln!(writer, "vn {} {} {}", x, y, z)?;
+            }
+        }
+        if has_uvs {
+            for [u, v] in &mesh.uvs {
+                writeln!(writer, "vt {} {}", u, 1.0 - v)?;
+            }
+        }
+        for triangle in mesh.indices.chunks(3) {
+            write!(writer, "f")?;
+            for index in triangle {
+                let (position, uv, normal) = (index + position_offset, index + uv_offset, index + normal_offset);
+                match (has_uvs, has_normals) {
+                    (true, true) => write!(writer, " {}/{}/{}", position, uv, normal)?,
+                    (true, false) => write!(writer, " {}/{}", position, uv)?,
+                    (false, true) => write!(writer, " {}//{}", position, normal)?,
+                    (false, false) => write!(writer, " {}", position)?,
+                }
+            }
+            writeln!(writer)?;
+        }
+        position_offset += mesh.positions.len() as u32;
+        if has_normals {
+            normal_offset += mesh.normals.len() as u32;
+        }
+        if has_uvs {
+            uv_offset += mesh.uvs.len() as u32;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// A node of a glTF scene, placing an optional mesh. Rotation is a unit quaternion `[x, y, z, w]`.
+#[derive(Debug, Clone)]
+pub struct GltfNode {
+    pub name: String,
+    pub mesh: Option<usize>,
+    pub translation: [f32; 3],
+    pub rotation: [f32; 4],
+    pub scale: [f32; 3],
+    pub children: Vec<usize>,
+}
+
+impl GltfNode {
+    pub fn new(name: String, mesh: Option<usize>) -> GltfNode {
+        GltfNode {
+            name,
+            mesh,
+            translation: [0.0; 3],
+            rotation: [0.0, 0.0, 0.0, 1.0],
+            scale: [1.0; 3],
+            children: vec![],
+        }
+    }
+}
+
+/// Builds a single scene glTF 2.0 binary (`.glb`). Every mesh is stored once and may be placed
+/// by any number of nodes, nodes that are nobody's child are the roots of the scene.
+#[derive(Debug, Default)]
+pub struct GltfBuilder {
+    buffer: Vec<u8>,
+    buffer_views: Vec<Value>,
+    accessors: Vec<Value>,
+    meshes: Vec<Value>,
+    nodes: Vec<GltfNode>,
+}
+
+const GLTF_ARRAY_BUFFER: u32 = 34962;
+const GLTF_ELEMENT_ARRAY_BUFFER: u32 = 34963;
+const GLTF_FLOAT: u32 = 5126;
+const GLTF_UNSIGNED_INT: u32 = 5125;
+
+impl GltfBuilder {
+    pub fn new() -> GltfBuilder {
+        Default::default()
+    }
+
+    /// Adds a mesh and returns its index, meshes without triangles can't be stored and are skipped.
+    pub fn add_mesh(&mut self, name: &str, mesh: &Mesh) -> Option<usize> {
+        if mesh.is_empty() {
+            return None;
+        }
+        let (min, max) = mesh.positions.iter().fold(
+            ([f32::MAX; 3], [f32::MIN; 3]),
+            |(min, max), it| {
+                ([min[0].min(it[0]), min[1].min(it[1]), min[2].min(it[2])],
+                 [max[0].max(it[0]), max[1].max(it[1]), max[2].max(it[2])])
+            },
+        );
+        let mut attributes = serde_json::Map::new();
+        let positions = self.add_accessor(
+            mesh.positions.iter().flatten().map(|it| it.to_le_bytes()),
+            mesh.positions.len(),
+            "VEC3",
+            GLTF_FLOAT,
+            GLTF_ARRAY_BUFFER,
+        );
+        self.accessors[positions]["min"] = json!(min);
+        self.accessors[positions]["max"] = json!(max);
+        attributes.insert("POSITION".to_string(), json!(positions));
+        if mesh.has_normals() {
+            let normals = self.add_accessor(
+                mesh.normals.iter().flatten().map(|it| it.to_le_bytes()),
+                mesh.normals.len(),
+                "VEC3",
+                GLTF_FLOAT,
+                GLTF_ARRAY_BUFFER,
+            );
+            attributes.insert("NORMAL".to_string(), json!(normals));
+        }
+        if mesh.has_uvs() {
+            let uvs = self.add_accessor(
+                mesh.uvs.iter().flatten().map(|it| it.to_le_bytes()),
+                mesh.uvs.len(),
+                "VEC2",
+                GLTF_FLOAT,
+                GLTF_ARRAY_BUFFER,
+            );
+            attributes.insert("TEXCOORD_0".to_string(), json!(uvs));
+        }
+        let indices = self.add_accessor(
+            mesh.indices.iter().map(|it| it.to_le_bytes()),
+            mesh.indices.len(),
+            "SCALAR",
+            GLTF_UNSIGNED_INT,
+            GLTF_ELEMENT_ARRAY_BUFFER,
+        );
+        self.meshes.push(json!({
+            "name": name,
+            "primitives": [{ "attributes": attributes, "indices": indices }],
+        }));
+        Some(self.meshes.len() - 1)
+    }
+
+    /// Adds a node and returns its index, to be used as a child of nodes added later.
+    pub fn add_node(&mut self, node: GltfNode) -> usize {
+        self.nodes.push(node);
+        self.nodes.len() - 1
+    }
+
+    pub fn write_glb<P: AsRef<Path>>(&self, path: P) -> R<()> {
+        let children: HashSet<usize> = self.nodes.iter().flat_map(|it| it.children.iter().copied()).collect();
+        let nodes: Vec<Value> = self.nodes.iter()
+            .map(|node| {
+                let mut value = json!({
+                    "name": node.name,
+                    "translation": node.translation,
+                    "rotation": node.rotation,
+                    "scale": node.scale,
+                });
+                if let Some(mesh) = node.mesh {
+                    value["mesh"] = json!(mesh);
+                }
+                if !node.children.is_empty() {
+                    value["children"] = json!(node.children);
+                }
+                value
+            })
+            .collect();
+        let roots: Vec<usize> = (0..self.nodes.len()).filter(|i| !children.contains(i)).collect();
+        let mut document = json!({
+            "asset": { "version": "2.0", "generator": "wow-file-tools" },
+            "scene": 0,
+            "scenes": [{ "nodes": roots }],
+            "nodes": nodes,
+            "meshes": self.meshes,
+            "accessors": self.accessors,
+            "bufferViews": self.buffer_views,
+        });
+        if !self.buffer.is_empty() {
+            document["buffers"] = json!([{ "byteLength": self.buffer.len() }]);
+        }
+
+        let mut json = serde_json::to_vec(&document)?;
+        json.resize(json.len() + (4 - json.len() % 4) % 4, b' ');
+        let mut bin = self.buffer.clone();
+        bin.resize(bin.len() + (4 - bin.len() % 4) % 4, 0);
+        let mut length = 12 + 8 + json.len();
+        if !bin.is_empty() {
+            length += 8 + bin.len();
+        }
+
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(b"glTF")?;
+        writer.write_all(&2u32.to_le_bytes())?;
+        writer.write_all(&(length as u32).to_le_bytes())?;
+        writer.write_all(&(json.len() as u32).to_le_bytes())?;
+        writer.write_all(b"JSON")?;
+        writer.write_all(&json)?;
+        if !bin.is_empty() {
+            writer.write_all(&(bin.len() as u32).to_le_bytes())?;
+            writer.write_all(b"BIN\0")?;
+            writer.write_all(&bin)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    fn add_accessor<I: Iterator<Item = [u8; 4]>>(
+        &mut self,
+        values: I,
+        count: usize,
+        accessor_type: &str,
+        component_type: u32,
+        target: u32,
+    ) -> usize {
+        let offset = self.buffer.len();
+        for value in values {
+            self.buffer.extend_from_slice(&value);
+        }
+        self.buffer_views.push(json!({
+            "buffer": 0,
+            "byteOffset": offset,
+            "byteLength": self.buffer.len() - offset,
+            "target": target,
+        }));
+        self.accessors.push(json!({
+            "bufferView": self.buffer_views.len() - 1,
+            "componentType": component_type,
+            "count": count,
+            "type": accessor_type,
+        }));
+        self.accessors.len() - 1
+    }
+}
@@ -2,6 +2,13 @@ pub mod wdl;
 pub mod chunk;
 pub mod wdt;
 pub mod adt;
+pub mod heightmap;
+pub mod alpha_map;
+pub mod placement;
+pub mod liquid;
+pub mod split_adt;
+pub mod mesh;
+pub mod terrain;
 pub mod dbc;
 pub mod wmo;
 pub mod m2;
@@ -0,0 +1,349 @@
+use crate::formats::adt::{AdtFile, MAP_ORIGIN, TILE_SIZE};
+use crate::formats::chunk::{
+    string_at_offset, ChunkMddf, ChunkMddfItem, ChunkMmdx, ChunkMmid, ChunkModf, ChunkModfItem, ChunkMwid,
+    ChunkMwmo,
+};
+use crate::formats::mesh::{quaternion_from_axis_angle, quaternion_mul, world_to_mesh_position};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// MDDF scale of 1.0.
+pub const PLACEMENT_SCALE_ONE: f32 = 1024.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PlacementKind {
+    /// MDDF entry, an M2 doodad.
+    M2,
+    /// MODF entry.
+    Wmo,
+}
+
+/// A MDDF or MODF entry resolved to its model path, flat so it reads and writes as CSV as well.
+/// Positions are server coordinates, rotations are in degrees.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Placement {
+    pub kind: PlacementKind,
+    pub model: String,
+    pub unique_id: u32,
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    pub rotation_x: f32,
+    pub rotation_y: f32,
+    pub rotation_z: f32,
+    pub scale: f32,
+    pub flags: u16,
+    /// WMO only.
+    pub doodad_set: u16,
+    /// WMO only.
+    pub name_set: u16,
+}
+
+impl Placement {
+    /// Translation, rotation and scale placing the model's mesh in mesh coordinates, for a mesh
+    /// read with [crate::formats::mesh::world_to_mesh_position] like the terrain.
+    pub fn mesh_transform(&self) -> ([f32; 3], [f32; 4], f32) {
+        let translation = world_to_mesh_position([self.x, self.y, self.z]);
+        // ADT space shares its axes with mesh space, models are rotated around Y, then Z, then X
+        // there, after turning their own axes into ADT space (a quarter turn away from mesh space).
+        let rotation = [
+            quaternion_from_axis_angle([0.0, 1.0, 0.0], self.rotation_y - 90.0),
+            quaternion_from_axis_angle([0.0, 0.0, 1.0], -self.rotation_x),
+            quaternion_from_axis_angle([1.0, 0.0, 0.0], self.rotation_z),
+            quaternion_from_axis_angle([0.0, 1.0, 0.0], -90.0),
+        ].iter().fold([0.0, 0.0, 0.0, 1.0], |rotation, it| quaternion_mul(rotation, *it));
+        (translation, rotation, self.scale)
+    }
+}
+
+/// ADT files store placements as `[y, z, x]`, measured from the north west corner of the map.
+pub fn adt_to_server_position(position: [f32; 3]) -> [f32; 3] {
+    [MAP_ORIGIN - position[2], MAP_ORIGIN - position[0], position[1]]
+}
+
+/// Inverse of [adt_to_server_position].
+pub fn server_to_adt_position(position: [f32; 3]) -> [f32; 3] {
+    [MAP_ORIGIN - position[1], position[2], MAP_ORIGIN - position[0]]
+}
+
+/// Axis aligned extents of a WMO placed at `position` (ADT space), from its MOHD bounding box.
+/// Only the height of the box is used as is, its horizontal radius covers any rotation.
+pub fn wmo_extents(position: [f32; 3], bounding_box: ([f32; 3], [f32; 3]), scale: f32) -> [f32; 6] {
+    let (min, max) = bounding_box;
+    let reach_x = min[0].abs().max(max[0].abs());
+    let reach_y = min[1].abs().max(max[1].abs());
+    let radius = (reach_x * reach_x + reach_y * reach_y).sqrt() * scale;
+    // WMOs are Z up, ADT space is Y up.
+    [
+        position[0] - radius,
+        position[1] + min[2].min(max[2]) * scale,
+        position[2] - radius,
+        position[0] + radius,
+        position[1] + min[2].max(max[2]) * scale,
+        position[2] + radius,
+    ]
+}
+
+/// Deduplicated path table as stored in MMDX/MWMO, along with the MMID/MWID offsets into it
+/// and the MMID/MWID index of every given path.
+fn build_string_table<'a, I: Iterator<Item = &'a str>>(paths: I) -> (Vec<String>, Vec<u32>, Vec<u32>) {
+    let mut strings: Vec<String> = vec![];
+    let mut offsets = vec![];
+    let mut indices = vec![];
+    let mut size = 0;
+    for path in paths {
+        let index = match strings.iter().position(|it| it.eq_ignore_ascii_case(path)) {
+            Some(index) => index,
+            None => {
+                strings.push(path.to_string());
+                offsets.push(size);
+                size += path.len() as u32 + 1;
+                strings.len() - 1
+            }
+        };
+        indices.push(index as u32);
+    }
+    (strings, offsets, indices)
+}
+
+/// The ADT position of `placement`, `original` when the placement still sits where that ADT
+/// position lists it. Comparing in server space is exact as listed positions are written as is.
+fn adt_position(placement: &Placement, original: Option<&[f32; 3]>) -> [f32; 3] {
+    let position = [placement.x, placement.y, placement.z];
+    match original {
+        Some(original) if adt_to_server_position(*original) == position => *original,
+        _ => server_to_adt_position(position),
+    }
+}
+
+impl AdtFile {
+    /// The M2 path of a MDDF entry, resolved through MMID into MMDX.
+    pub fn get_m2_path(&self, mmid_entry: u32) -> Option<&str> {
+        let offset = *self.mmid.0.get(mmid_entry as usize)?;
+        string_at_offset(&self.mmdx.0, offset)
+    }
+
+    /// Every MDDF entry followed by every MODF entry. Unresolvable model paths are left empty.
+    pub fn placements(&self) -> Vec<Placement> {
+        let doodads = self.mddf.0.iter().map(|it| {
+            let [x, y, z] = adt_to_server_position(it.position);
+            Placement {
+                kind: PlacementKind::M2,
+                model: self.get_m2_path(it.mmid_entry).unwrap_or_default().to_string(),
+                unique_id: it.unique_id,
+                x,
+                y,
+                z,
+                rotation_x: it.rotation[0],
+                rotation_y: it.rotation[1],
+                rotation_z: it.rotation[2],
+                scale: it.scale as f32 / PLACEMENT_SCALE_ONE,
+                flags: it.flags,
+                doodad_set: 0,
+                name_set: 0,
+            }
+        });
+        let wmos = self.modf.0.iter().map(|it| {
+            let [x, y, z] = adt_to_server_position(it.position);
+            Placement {
+                kind: PlacementKind::Wmo,
+                model: self.get_wmo_path(it.name_id).unwrap_or_default().to_string(),
+                unique_id: it.unique_id,
+                x,
+                y,
+                z,
+                rotation_x: it.rotation[0],
+                rotation_y: it.rotation[1],
+                rotation_z: it.rotation[2],
+                // scale only exists since Legion, 0 before that.
+                scale: if it.scale == 0 { 1.0 } else { it.scale as f32 / PLACEMENT_SCALE_ONE },
+                flags: it.flags,
+                doodad_set: it.doodad_set,
+                name_set: it.name_set,
+            }
+        });
+        doodads.chain(wmos).collect()
+    }
+
+    /// Replaces MMDX, MMID, MWMO, MWID, MDDF and MODF with `placements` and rebuilds MCRF.
+    /// `wmo_extents` holds the extents of every WMO placement, in order. Placements whose unique id
+    /// and server position match an entry already there keep that entry's ADT position, so that
+    /// untouched entries don't drift through the conversion.
+    pub fn set_placements(&mut self, placements: &[Placement], wmo_extents: &[[f32; 6]]) {
+        let (m2s, wmos): (Vec<&Placement>, Vec<&Placement>) = placements.iter()
+            .partition(|it| it.kind == PlacementKind::M2);
+        let original_m2s: HashMap<u32, [f32; 3]> = self.mddf.0.iter().map(|it| (it.unique_id, it.position)).collect();
+        let original_wmos: HashMap<u32, [f32; 3]> = self.modf.0.iter().map(|it| (it.unique_id, it.position)).collect();
+
+        let (mmdx, mmid, m2_indices) = build_string_table(m2s.iter().map(|it| it.model.as_str()));
+        self.mmdx = ChunkMmdx(mmdx);
+        self.mmid = ChunkMmid(mmid);
+        self.mddf = ChunkMddf(m2s.iter()
+            .zip(m2_indices)
+            .map(|(it, mmid_entry)| ChunkMddfItem {
+                mmid_entry,
+                unique_id: it.unique_id,
+                position: adt_position(it, original_m2s.get(&it.unique_id)),
+                rotation: [it.rotation_x, it.rotation_y, it.rotation_z],
+                scale: (it.scale * PLACEMENT_SCALE_ONE).round() as u16,
+                flags: it.flags,
+            })
+            .collect());
+
+        let (mwmo, mwid, wmo_indices) = build_string_table(wmos.iter().map(|it| it.model.as_str()));
+        self.mwmo = ChunkMwmo(mwmo);
+        self.mwid = ChunkMwid(mwid);
+        self.modf = ChunkModf(wmos.iter()
+            .zip(wmo_indices)
+            .zip(wmo_extents)
+            .map(|((it, name_id), extents)| ChunkModfItem {
+                name_id,
+                unique_id: it.unique_id,
+                position: adt_position(it, original_wmos.get(&it.unique_id)),
+                rotation: [it.rotation_x, it.rotation_y, it.rotation_z],
+                extents: *extents,
+                flags: it.flags,
+                doodad_set: it.doodad_set,
+                name_set: it.name_set,
+                // pre-Legion clients read this as padding.
+                scale: if it.scale == 1.0 { 0 } else { (it.scale * PLACEMENT_SCALE_ONE).round() as u16 },
+            })
+            .collect());
+
+        self.rebuild_mcrf();
+    }
+
+    /// Points the MCRF of every MCNK at the doodads standing on it and the WMOs overlapping it.
+    pub fn rebuild_mcrf(&mut self) {
+        const CHUNK_SIZE: f32 = TILE_SIZE / 16.0;
+        let doodads: Vec<[f32; 3]> = self.mddf.0.iter().map(|it| adt_to_server_position(it.position)).collect();
+        // MODF extents in server coordinates, as (min x, max x, min y, max y).
+        let wmos: Vec<[f32; 4]> = self.modf.0.iter()
+            .map(|it| {
+                let lower = adt_to_server_position([it.extents[0], it.extents[1], it.extents[2]]);
+                let upper = adt_to_server_position([it.extents[3], it.extents[4], it.extents[5]]);
+                [lower[0].min(upper[0]), lower[0].max(upper[0]), lower[1].min(upper[1]), lower[1].max(upper[1])]
+            })
+            .collect();
+
+        for mcnk in &mut self.mcnk.items {
+            // MCNKs are positioned at their north west corner, extending towards -X and -Y.
+            let [max_x, max_y, _] = mcnk.header.position;
+            let (min_x, min_y) = (max_x - CHUNK_SIZE, max_y - CHUNK_SIZE);
+            mcnk.mcrf.doodad_refs = doodads.iter()
+                .enumerate()
+                .filter(|(_, it)| it[0] > min_x && it[0] <= max_x && it[1] > min_y && it[1] <= max_y)
+                .map(|(i, _)| i as u32)
+                .collect();
+            mcnk.mcrf.map_obj_refs = wmos.iter()
+                .enumerate()
+                .filter(|(_, it)| it[0] <= max_x && it[1] >= min_x && it[2] <= max_y && it[3] >= min_y)
+                .map(|(i, _)| i as u32)
+                .collect();
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn placements_resolve_paths_and_coordinates() {
+    use crate::formats::adt::{test_adt, test_doodad};
+
+    let mut adt = test_adt();
+    adt.mmdx.0 = vec!["a.m2".to_string(), "world\\b.m2".to_string()];
+    adt.mmid.0 = vec![0, 5];
+    adt.mddf.0.push(ChunkMddfItem {
+        rotation: [0.0, 90.0, 0.0],
+        scale: 2048,
+        ..test_doodad(1, 7, [17000.0, 12.5, 16000.0])
+    });
+
+    let placements = adt.placements();
+    assert_eq!(placements.len(), 1);
+    let placement = &placements[0];
+    assert_eq!(placement.model, "world\\b.m2");
+    assert_eq!(placement.scale, 2.0);
+    assert_eq!(server_to_adt_position([placement.x, placement.y, placement.z]), [17000.0, 12.5, 16000.0]);
+
+    // turned a quarter counter clockwise from facing south, the model faces east.
+    let (translation, rotation, scale) = placement.mesh_transform();
+    assert_eq!(translation, [17000.0 - MAP_ORIGIN, 12.5, 16000.0 - MAP_ORIGIN]);
+    assert!((rotation[1] + std::f32::consts::FRAC_1_SQRT_2).abs() < 1e-6);
+    assert!((rotation[3] - std::f32::consts::FRAC_1_SQRT_2).abs() < 1e-6);
+    assert_eq!(scale, 2.0);
+}
+
+#[cfg(test)]
+#[test]
+fn set_placements_keeps_untouched_positions() {
+    use crate::formats::adt::{test_adt, test_doodad};
+
+    let mut adt = test_adt();
+    adt.mmdx.0 = vec!["a.m2".to_string()];
+    adt.mmid.0 = vec![0];
+    let original = [0.1, 12.3, 16000.7];
+    adt.mddf.0 = (1..=2).map(|unique_id| test_doodad(0, unique_id, original)).collect();
+    // the listed position doesn't convert back to the ADT position bit for bit.
+    assert_ne!(server_to_adt_position(adt_to_server_position(original)), original);
+
+    let mut placements = adt.placements();
+    placements[1].z += 1.0;
+    adt.set_placements(&placements, &[]);
+    assert_eq!(adt.mddf.0[0].position, original);
+    assert_eq!(adt.mddf.0[1].position, server_to_adt_position([placements[1].x, placements[1].y, placements[1].z]));
+}
+
+#[cfg(test)]
+#[test]
+fn set_placements_builds_string_tables_and_mcrf() {
+    use crate::formats::adt::test_adt;
+
+    const CHUNK_SIZE: f32 = TILE_SIZE / 16.0;
+    // tile 32_32, its north west corner is the server origin.
+    let mut adt = test_adt();
+    for mcnk in &mut adt.mcnk.items {
+        let header = &mut mcnk.header;
+        header.position = [-(header.index_y as f32) * CHUNK_SIZE, -(header.index_x as f32) * CHUNK_SIZE, 0.0];
+    }
+    let placement = |kind, model: &str, unique_id, x, y| Placement {
+        kind,
+        model: model.to_string(),
+        unique_id,
+        x,
+        y,
+        z: 0.0,
+        rotation_x: 0.0,
+        rotation_y: 0.0,
+        rotation_z: 0.0,
+        scale: 1.0,
+        flags: 0,
+        doodad_set: 0,
+        name_set: 0,
+    };
+    let placements = vec![
+        placement(PlacementKind::M2, "a.m2", 1, -1.0, -1.0),
+        placement(PlacementKind::Wmo, "w.wmo", 2, -3.0, -CHUNK_SIZE / 2.0),
+        placement(PlacementKind::M2, "world\\b.m2", 3, -1.0, -CHUNK_SIZE - 1.0),
+        placement(PlacementKind::M2, "A.M2", 4, -CHUNK_SIZE - 1.0, -1.0),
+    ];
+    // spans MCNK (0, 0) and (1, 0) along server y.
+    let lower = server_to_adt_position([-5.0, -CHUNK_SIZE - 5.0, 0.0]);
+    let upper = server_to_adt_position([-1.0, -5.0, 10.0]);
+    adt.set_placements(&placements, &[[lower[0], lower[1], lower[2], upper[0], upper[1], upper[2]]]);
+
+    assert_eq!(adt.mmdx.0, vec!["a.m2".to_string(), "world\\b.m2".to_string()]);
+    assert_eq!(adt.mmid.0, vec![0, 5]);
+    assert_eq!(adt.mddf.0.iter().map(|it| it.mmid_entry).collect::<Vec<_>>(), vec![0, 1, 0]);
+    assert_eq!(adt.mwmo.0, vec!["w.wmo".to_string()]);
+    assert_eq!(adt.mwid.0, vec![0]);
+
+    let refs = |i: usize| {
+        let mcrf = &adt.mcnk.items[i].mcrf;
+        (mcrf.doodad_refs.clone(), mcrf.map_obj_refs.clone())
+    };
+    assert_eq!(refs(0), (vec![0], vec![0]));
+    assert_eq!(refs(1), (vec![1], vec![0]));
+    assert_eq!(refs(2), (vec![], vec![]));
+    assert_eq!(refs(16), (vec![2], vec![]));
+}
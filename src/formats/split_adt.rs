@@ -0,0 +1,260 @@
+use crate::byte_utils::{BinRead, VecUtils};
+use crate::common::{err, map_file, R};
+use crate::formats::adt::AdtFile;
+use crate::formats::chunk::*;
+use crate::formats::liquid::ChunkMh2o;
+use anyhow::Context;
+use std::path::{Path, PathBuf};
+
+/// MoP and later: [ChunkMcnkHeader::ofs_mcly] and [ChunkMcnkHeader::ofs_mcrf] hold a 8x8 hole mask.
+pub const MCNK_FLAG_HIGH_RES_HOLES: u32 = 0x10000;
+
+/// Top level chunks of the split files carried over as is, the 3.3.5 client reads them the same way.
+const CARRIED_OVER_CHUNKS: &[&str] = &["MFBO", "MTXF"];
+
+/// The `<Map>_<x>_<y>_tex0.adt` and `<Map>_<x>_<y>_obj0.adt` files next to a split root ADT.
+pub fn split_adt_paths(root: &Path) -> R<(PathBuf, PathBuf)> {
+    let stem = root.file_stem()
+        .and_then(|it| it.to_str())
+        .with_context(|| format!("Invalid ADT file name {:?}", root.display()))?;
+    let folder = root.parent().unwrap_or_else(|| Path::new(""));
+    Ok((folder.join(format!("{}_tex0.adt", stem)), folder.join(format!("{}_obj0.adt", stem))))
+}
+
+/// Sub-chunks of a split MCNK, laid out one after another from `skip` bytes in. The root MCNK
+/// starts with the usual header, the _tex0 and _obj0 ones go straight to their sub-chunks.
+/// MCNR may be followed by the 13 bytes its size doesn't account for, see [ChunkMcnr].
+fn split_sub_chunks<'a>(mcnk: &ChunkRef<'a>, skip: usize) -> R<Vec<ChunkRef<'a>>> {
+    let looks_like_sub_chunk = |offset: usize| mcnk.data.get(offset + 2..offset + 4) == Some(b"CM");
+    let mut chunks = vec![];
+    let mut offset = skip;
+    while offset + 8 <= mcnk.data.len() {
+        let id = mcnk.data.get_four_bytes(offset)?;
+        let size = mcnk.data.get_u32(offset + 4)? as usize;
+        let start = offset + 8;
+        let mut data_size = size;
+        if &id == b"RNCM" && !looks_like_sub_chunk(start + size) {
+            data_size = (size + 13).min(mcnk.data.len() - start);
+        }
+        let data = mcnk.data.get(start..start + data_size)
+            .with_context(|| format!("{}: sub-chunk at {:#x} runs past its end", mcnk.describe(), offset))?;
+        chunks.push(ChunkRef {
+            id,
+            size: size as u32,
+            offset: mcnk.offset + 8 + offset,
+            data,
+        });
+        offset = start + data_size;
+    }
+    Ok(chunks)
+}
+
+impl ChunkMcnkItem {
+    /// Merges the three parts of a split MCNK: heights, normals, vertex colors, liquid and sound
+    /// emitters with the header from `root`, texture layers, alpha maps and shadows from `tex`,
+    /// MCRD/MCRW object references from `obj`.
+    pub fn from_split_chunks(root: &ChunkRef, tex: &ChunkRef, obj: &ChunkRef) -> R<ChunkMcnkItem> {
+        root.expect_type("MCNK")?;
+        root.expect_size_at_least(ChunkMcnkHeader::SIZE)?;
+        let mut header = ChunkMcnkHeader::from_bytes(root.data)?;
+        let root_chunks = split_sub_chunks(root, ChunkMcnkHeader::SIZE)?;
+        let tex_chunks = split_sub_chunks(tex, 0)?;
+        let obj_chunks = split_sub_chunks(obj, 0)?;
+
+        if header.flags & MCNK_FLAG_HIGH_RES_HOLES != 0 {
+            let mut high_res_holes = header.ofs_mcly.to_le_bytes().to_vec();
+            high_res_holes.extend_from_slice(&header.ofs_mcrf.to_le_bytes());
+            header.holes = low_res_holes(&high_res_holes);
+            header.flags &= !MCNK_FLAG_HIGH_RES_HOLES;
+        }
+
+        let mcvt = root_chunks.get_chunk_of_type("MCVT")
+            .with_context(|| format!("{}: missing MCVT", root.describe()))?;
+        let mcnr = root_chunks.get_chunk_of_type("MCNR")
+            .with_context(|| format!("{}: missing MCNR", root.describe()))?;
+        let mut mcnr = ChunkMcnr::from_chunk(mcnr)?;
+        mcnr.padding.resize(13, 0);
+        let mclq = root_chunks.get_chunk_of_type_optionally("MCLQ").map(|c| ChunkMclq {
+            declared_size: c.size,
+            data: c.data.to_vec(),
+        });
+        let mcsh = tex_chunks.get_chunk_of_type_optionally("MCSH").map(|c| ChunkMcsh { shadow_map: c.data.to_vec() });
+        if mcsh.is_some() {
+            header.flags |= MCNK_FLAG_HAS_MCSH;
+        } else {
+            header.flags &= !MCNK_FLAG_HAS_MCSH;
+        }
+        let refs = |chunk_type: &str| {
+            obj_chunks.get_chunk_of_type_optionally(chunk_type)
+                .map(|c| u32::read_all(c.data))
+                .transpose()
+                .map(Option::unwrap_or_default)
+        };
+
+        Ok(ChunkMcnkItem {
+            mcvt: ChunkMcvt::from_chunk(mcvt)?,
+            mcnr,
+            mcly: match tex_chunks.get_chunk_of_type_optionally("MCLY") {
+                Some(mcly) => ChunkMcly::from_chunk(mcly)?,
+                None => ChunkMcly(vec![]),
+            },
+            mcrf: ChunkMcrf {
+                doodad_refs: refs("MCRD")?,
+                map_obj_refs: refs("MCRW")?,
+            },
+            mcsh,
+            mcal: tex_chunks.get_chunk_of_type_optionally("MCAL").map(|c| ChunkMcal { data: c.data.to_vec() }),
+            mclq,
+            mcse: root_chunks.get_chunk_of_type_optionally("MCSE").map(ChunkMcse::from_chunk).transpose()?,
+            mccv: root_chunks.get_chunk_of_type_optionally("MCCV").map(ChunkMccv::from_chunk).transpose()?,
+            header,
+        })
+    }
+}
+
+/// A 4x4 hole mask out of a 8x8 one (a byte per row of quads), a hole covers the 2x2 quads of any
+/// hole inside them.
+fn low_res_holes(high_res_holes: &[u8]) -> u16 {
+    let mut holes = 0;
+    for (quad_y, row) in high_res_holes.iter().enumerate() {
+        for quad_x in 0..8 {
+            if row & (1 << quad_x) != 0 {
+                holes |= 1 << ((quad_y / 2) * 4 + quad_x / 2);
+            }
+        }
+    }
+    holes
+}
+
+impl AdtFile {
+    /// Loads a Cataclysm style split tile, `<Map>_<x>_<y>.adt` along with its `_tex0` and `_obj0`
+    /// files, as a single 3.3.5 ADT. Chunks the 3.3.5 client has no use for (MAMP, MCMT, MCLV,
+    /// MCBB, ...) are dropped.
+    pub fn from_split_path<P: AsRef<Path>>(root_path: P) -> R<AdtFile> {
+        let root_path = root_path.as_ref();
+        let (tex_path, obj_path) = split_adt_paths(root_path)?;
+        let root = map_file(root_path)?;
+        let tex = map_file(&tex_path)?;
+        let obj = map_file(&obj_path)?;
+        let root = ChunkRef::from_bytes(&root)
+            .with_context(|| format!("Failed to parse ADT file {:?}", root_path.display()))?;
+        let tex = ChunkRef::from_bytes(&tex)
+            .with_context(|| format!("Failed to parse ADT file {:?}", tex_path.display()))?;
+        let obj = ChunkRef::from_bytes(&obj)
+            .with_context(|| format!("Failed to parse ADT file {:?}", obj_path.display()))?;
+        AdtFile::from_split_chunks(&root, &tex, &obj)
+            .with_context(|| format!("Failed to merge split ADT {:?}", root_path.display()))
+    }
+
+    pub fn from_split_chunks(root: &[ChunkRef], tex: &[ChunkRef], obj: &[ChunkRef]) -> R<AdtFile> {
+        let root_mcnks = root.get_all_chunks_of_type("MCNK");
+        let tex_mcnks = tex.get_all_chunks_of_type("MCNK");
+        let obj_mcnks = obj.get_all_chunks_of_type("MCNK");
+        if root_mcnks.len() != 256 || tex_mcnks.len() != 256 || obj_mcnks.len() != 256 {
+            return err(format!(
+                "Expected 256 MCNKs in every part, got {} in the root, {} in _tex0 and {} in _obj0",
+                root_mcnks.len(), tex_mcnks.len(), obj_mcnks.len()
+            ));
+        }
+        if tex.get_chunk_of_type_optionally("MTEX").is_none() {
+            return err("_tex0 has no MTEX, textures referenced by file data id (MDID) are not supported".to_string());
+        }
+        let items = root_mcnks.iter()
+            .zip(&tex_mcnks)
+            .zip(&obj_mcnks)
+            .map(|((root, tex), obj)| ChunkMcnkItem::from_split_chunks(root, tex, obj))
+            .collect::<R<Vec<ChunkMcnkItem>>>()?;
+
+        let mut mhdr = root.get_mhdr()?;
+        mhdr.unused = [0; 4];
+        let unparsed_chunks = root.iter()
+            .chain(tex)
+            .filter(|c| CARRIED_OVER_CHUNKS.iter().any(|it| c.is_type(it)))
+            .map(ChunkRef::to_owned_chunk)
+            .collect();
+        Ok(AdtFile {
+            mver: ChunkMver { map_version: 18 },
+            mhdr,
+            // the offsets are filled in by AdtFile::to_chunks.
+            mcin: ChunkMcin::read_all(&[0; 256 * ChunkMcin::SIZE])?,
+            mtex: tex.get_mtex()?,
+            mmdx: obj.get_mmdx()?,
+            mmid: obj.get_mmid()?,
+            mwmo: obj.get_mwmo()?,
+            mwid: obj.get_mwid()?,
+            mddf: obj.get_mddf()?,
+            modf: obj.get_modf()?.unwrap_or(ChunkModf(vec![])),
+            mh2o: root.get_chunk_of_type_optionally("MH2O").map(ChunkMh2o::from_chunk).transpose()?,
+            mcnk: ChunkMcnk { items },
+            unparsed_chunks,
+        })
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn split_adt_merges_parts() {
+    use crate::byte_utils::BinWrite;
+
+    fn sub_chunk(chunk_type: &str, data: Vec<u8>) -> Vec<u8> {
+        Chunk::new(chunk_type, data).to_bytes()
+    }
+
+    let mut root = vec![ChunkMver { map_version: 18 }.to_chunk()];
+    root.push(ChunkMhdr::from_bytes(&[0; ChunkMhdr::SIZE]).unwrap().to_chunk());
+    let mut tex = vec![ChunkMtex(vec!["a.blp".to_string()]).to_chunk()];
+    let mut obj = vec![
+        ChunkMmdx(vec!["a.m2".to_string()]).to_chunk(),
+        ChunkMmid(vec![0]).to_chunk(),
+        ChunkMwmo(vec![]).to_chunk(),
+        ChunkMwid(vec![]).to_chunk(),
+    ];
+    obj.push(Chunk::new("MDDF", vec![0; 36]));
+    for i in 0..256u32 {
+        let mut header = ChunkMcnkHeader::from_bytes(&[0; ChunkMcnkHeader::SIZE]).unwrap();
+        header.index_x = i % 16;
+        header.index_y = i / 16;
+        if i == 1 {
+            header.flags = MCNK_FLAG_HIGH_RES_HOLES;
+            // quad (2, 0) and quad (7, 7).
+            header.ofs_mcly = 0b100;
+            header.ofs_mcrf = 0x80 << 24;
+        }
+        let mut data = header.to_bytes();
+        data.append(&mut sub_chunk("MCVT", vec![0.0f32; MCNK_VERTEX_COUNT].to_bytes()));
+        // no padding after MCNR, as in later clients.
+        data.append(&mut sub_chunk("MCNR", vec![1; MCNK_VERTEX_COUNT * 3]));
+        root.push(Chunk::new("MCNK", data));
+
+        let mut data = sub_chunk("MCLY", ChunkMclyItem::read_all(&[0; ChunkMclyItem::SIZE]).unwrap().to_bytes());
+        data.append(&mut sub_chunk("MCSH", vec![0; 512]));
+        data.append(&mut sub_chunk("MCMT", vec![0; 4]));
+        tex.push(Chunk::new("MCNK", data));
+        let refs = if i == 0 { vec![0u32] } else { vec![] };
+        obj.push(Chunk::new("MCNK", sub_chunk("MCRD", refs.to_bytes())));
+    }
+    root.push(Chunk::new("MFBO", vec![0; 36]));
+    tex.push(Chunk::new("MAMP", vec![0; 4]));
+
+    let (root, tex, obj) = (chunks_to_bytes(&root), chunks_to_bytes(&tex), chunks_to_bytes(&obj));
+    let adt = AdtFile::from_split_chunks(
+        &ChunkRef::from_bytes(&root).unwrap(),
+        &ChunkRef::from_bytes(&tex).unwrap(),
+        &ChunkRef::from_bytes(&obj).unwrap(),
+    ).unwrap();
+    assert_eq!(adt.mtex.0, vec!["a.blp"]);
+    assert_eq!(adt.mddf.0.len(), 1);
+    assert_eq!(adt.mcnk.items[0].mcrf.doodad_refs, vec![0]);
+    assert_eq!(adt.mcnk.items[0].mcly.0.len(), 1);
+    assert_eq!(adt.mcnk.items[0].mcnr.padding.len(), 13);
+    assert_eq!(adt.mcnk.items[0].header.flags, MCNK_FLAG_HAS_MCSH);
+    assert_eq!(adt.mcnk.items[1].header.holes, 0b10 | 1 << 15);
+    let chunk_types: Vec<String> = adt.unparsed_chunks.iter().map(Chunk::get_id_as_string).collect();
+    assert_eq!(chunk_types, vec!["MFBO"]);
+
+    // the result reads back as a regular ADT.
+    let bytes = chunks_to_bytes(&adt.to_chunks());
+    let written = AdtFile::new(&ChunkRef::from_bytes(&bytes).unwrap()).unwrap();
+    assert_eq!(written.mcnk.items[0].mcrf.doodad_refs, vec![0]);
+    assert!(written.mcnk.items[0].mcsh.is_some());
+}
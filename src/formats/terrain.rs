@@ -0,0 +1,95 @@
+use crate::formats::adt::{AdtFile, TILE_SIZE};
+use crate::formats::chunk::ChunkMcnkItem;
+use crate::formats::mesh::{world_to_mesh_position, Mesh};
+
+/// Quads per chunk side, each one split in 4 triangles around its inner vertex.
+const QUADS_PER_CHUNK: usize = 8;
+/// Distance between two outer vertices in world units.
+const UNIT_SIZE: f32 = TILE_SIZE / 16.0 / QUADS_PER_CHUNK as f32;
+
+impl ChunkMcnkItem {
+    /// Whether quad `(x, y)` of the 8x8 quads is a hole. Each bit of the 4x4 hole mask covers 2x2 quads.
+    pub fn is_hole(&self, quad_x: usize, quad_y: usize) -> bool {
+        self.header.holes & (1 << ((quad_y / 2) * 4 + quad_x / 2)) != 0
+    }
+
+    /// The terrain of this chunk in world coordinates. MCVT rows alternate between 9 outer and
+    /// 8 inner vertices, rows go south and columns go east from the north west corner in the
+    /// MCNK header. UVs span 0 to 1 over the chunk, holed quads get no triangles.
+    pub fn terrain_mesh(&self) -> Mesh {
+        let mut mesh = Mesh::default();
+        let [corner_x, corner_y, base_height] = self.header.position;
+        for (i, height) in self.mcvt.heights.iter().enumerate() {
+            let (row, column) = (i / 17, i % 17);
+            // inner vertices sit half a unit down and right of their outer neighbour.
+            let (row, column) = if column < 9 {
+                (row as f32, column as f32)
+            } else {
+                (row as f32 + 0.5, (column - 9) as f32 + 0.5)
+            };
+            mesh.positions.push(world_to_mesh_position([
+                corner_x - row * UNIT_SIZE,
+                corner_y - column * UNIT_SIZE,
+                base_height + height,
+            ]));
+            let normal = self.mcnr.normals.get(i).copied().unwrap_or([0, 0, 127]);
+            mesh.normals.push(world_to_mesh_position([
+                normal[0] as f32 / 127.0,
+                normal[1] as f32 / 127.0,
+                normal[2] as f32 / 127.0,
+            ]));
+            mesh.uvs.push([column / QUADS_PER_CHUNK as f32, row / QUADS_PER_CHUNK as f32]);
+        }
+
+        for quad_y in 0..QUADS_PER_CHUNK {
+            for quad_x in 0..QUADS_PER_CHUNK {
+                if self.is_hole(quad_x, quad_y) {
+                    continue;
+                }
+                let top_left = (quad_y * 17 + quad_x) as u32;
+                let (top_right, bottom_left, bottom_right) = (top_left + 1, top_left + 17, top_left + 18);
+                let center = top_left + 9;
+                mesh.indices.extend_from_slice(&[
+                    center, top_right, top_left,
+                    center, bottom_right, top_right,
+                    center, bottom_left, bottom_right,
+                    center, top_left, bottom_left,
+                ]);
+            }
+        }
+        mesh
+    }
+}
+
+impl AdtFile {
+    /// The terrain of every MCNK, see [ChunkMcnkItem::terrain_mesh].
+    pub fn terrain_mesh(&self) -> Mesh {
+        let mut mesh = Mesh::default();
+        for chunk in &self.mcnk.items {
+            mesh.append(&chunk.terrain_mesh());
+        }
+        mesh
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn terrain_mesh_layout() {
+    use crate::formats::chunk::MCNK_VERTEX_COUNT;
+
+    let mut adt = crate::formats::adt::test_adt();
+    adt.mcnk.items[0].header.position = [100.0, 200.0, 10.0];
+    adt.mcnk.items[0].header.holes = 0b10;
+    let mesh = adt.terrain_mesh();
+    assert_eq!(mesh.positions.len(), 256 * MCNK_VERTEX_COUNT);
+    // a hole bit drops 2x2 quads of 4 triangles.
+    assert_eq!(mesh.triangle_count(), 256 * 64 * 4 - 16);
+
+    assert_eq!(mesh.positions[0], [-200.0, 10.0, -100.0]);
+    assert_eq!(mesh.positions[8], [-200.0 + 8.0 * UNIT_SIZE, 10.0, -100.0]);
+    assert_eq!(mesh.positions[9], [-200.0 + 0.5 * UNIT_SIZE, 10.0, -100.0 + 0.5 * UNIT_SIZE]);
+    assert_eq!(mesh.normals[0], [0.0, 1.0, 0.0]);
+    assert_eq!(mesh.uvs[144], [1.0, 1.0]);
+    let first_chunk = &mesh.indices[..60 * 4 * 3];
+    assert!(!first_chunk.iter().any(|it| [3, 11, 12].contains(it)));
+}
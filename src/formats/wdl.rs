@@ -1,7 +1,8 @@
 use serde::{Deserialize, Serialize};
-use crate::common::R;
+use crate::common::{R, map_file};
 use std::path::{Path};
-use crate::formats::chunk::{Chunk, ChunkVecUtils, ChunkMwmo};
+use anyhow::Context;
+use crate::formats::chunk::{ChunkRef, ChunkVecUtils, ChunkMwmo};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct WdlFile {
@@ -10,8 +11,12 @@ pub struct WdlFile {
 
 impl WdlFile {
     pub fn from_path<P: AsRef<Path>>(path: P) -> R<WdlFile> {
-        let chunks = Chunk::from_path(path)?;
-        let mwmo = chunks.get_mwmo();
+        let path = path.as_ref();
+        let mapped_file = map_file(path)?;
+        let chunks = ChunkRef::from_bytes(&mapped_file)
+            .with_context(|| format!("Failed to parse WDL file {:?}", path.display()))?;
+        let mwmo = chunks.get_mwmo()
+            .with_context(|| format!("Failed to parse WDL file {:?}", path.display()))?;
         Ok(WdlFile {
             mwmo,
         })
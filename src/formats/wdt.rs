@@ -1,7 +1,10 @@
-use crate::formats::chunk::{Chunk, ChunkVecUtils, ChunkMver, ChunkMphd, ChunkMain, ChunkMwmo, ChunkModf};
-use crate::common::R;
+use crate::formats::chunk::{
+    write_chunks, Chunk, ChunkMain, ChunkMainItem, ChunkModf, ChunkMphd, ChunkMver, ChunkMwmo, ChunkRef, ChunkVecUtils,
+};
+use crate::common::{R, map_file};
 use serde::{Serialize, Deserialize};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use anyhow::Context;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct WdtFile {
@@ -14,16 +17,53 @@ pub struct WdtFile {
 
 impl WdtFile {
     pub fn from_path<P: AsRef<Path>>(path: P) -> R<WdtFile> {
-        let chunks = Chunk::from_path(path)?;
-        WdtFile::new(chunks)
+        let path = path.as_ref();
+        let mapped_file = map_file(path)?;
+        ChunkRef::from_bytes(&mapped_file)
+            .and_then(|chunks| WdtFile::new(&chunks))
+            .with_context(|| format!("Failed to parse WDT file {:?}", path.display()))
     }
 
-    fn new(chunks: Vec<Chunk>) -> R<WdtFile> {
+    /// A WDT without any tile or WMO.
+    pub fn empty(mphd_flags: u32) -> WdtFile {
+        WdtFile {
+            mver: ChunkMver { map_version: 18 },
+            mphd: ChunkMphd { flags: mphd_flags, something: 0, unused: [0; 6] },
+            main: ChunkMain((0..64 * 64).map(|_| ChunkMainItem { flags: 0, area: 0 }).collect()),
+            mwmo: ChunkMwmo(vec![]),
+            modf: None,
+        }
+    }
+
+    pub fn to_chunks(&self) -> Vec<Chunk> {
+        let mut chunks = vec![self.mver.to_chunk(), self.mphd.to_chunk(), self.main.to_chunk(), self.mwmo.to_chunk()];
+        chunks.extend(self.modf.as_ref().map(ChunkModf::to_chunk));
+        chunks
+    }
+
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> R<()> {
+        write_chunks(path, &self.to_chunks())
+    }
+
+    /// Paths of the ADT files of every tile flagged in MAIN, they sit next to the WDT.
+    /// Returns `(x, y, path)` tuples, the paths are not checked for existence.
+    pub fn adt_paths(&self, wdt_path: &Path) -> R<Vec<(u32, u32, PathBuf)>> {
+        let map_name = wdt_path.file_stem()
+            .and_then(|it| it.to_str())
+            .with_context(|| format!("Invalid WDT file name {:?}", wdt_path.display()))?;
+        let folder = wdt_path.parent().unwrap_or_else(|| Path::new(""));
+        Ok(self.main.adt_tiles()
+            .into_iter()
+            .map(|(x, y)| (x, y, folder.join(format!("{}_{}_{}.adt", map_name, x, y))))
+            .collect())
+    }
+
+    pub(crate) fn new(chunks: &[ChunkRef]) -> R<WdtFile> {
         let mver = chunks.get_mver_chunk()?;
-        let mphd = chunks.get_mphd_chunk();
-        let main = chunks.get_main();
-        let mwmo = chunks.get_mwmo();
-        let modf = chunks.get_modf();
+        let mphd = chunks.get_mphd_chunk()?;
+        let main = chunks.get_main()?;
+        let mwmo = chunks.get_mwmo()?;
+        let modf = chunks.get_modf()?;
         Ok(WdtFile {
             mver,
             mphd,
@@ -33,3 +73,18 @@ impl WdtFile {
         })
     }
 }
+
+#[cfg(test)]
+#[test]
+fn wdt_write_round_trip() {
+    use crate::formats::chunk::{chunks_to_bytes, MAIN_FLAG_HAS_ADT, MPHD_FLAG_ADT_HAS_BIG_ALPHA};
+
+    let mut wdt = WdtFile::empty(MPHD_FLAG_ADT_HAS_BIG_ALPHA);
+    wdt.main.tile_mut(32, 48).flags = MAIN_FLAG_HAS_ADT;
+    let bytes = chunks_to_bytes(&wdt.to_chunks());
+    let read = WdtFile::new(&ChunkRef::from_bytes(&bytes).unwrap()).unwrap();
+    assert_eq!(read.main.adt_tiles(), vec![(32, 48)]);
+    assert!(read.mphd.has_big_alpha());
+    assert!(read.modf.is_none());
+    assert_eq!(chunks_to_bytes(&read.to_chunks()), bytes);
+}
@@ -1,12 +1,15 @@
-use crate::common::{R, err};
-use crate::formats::chunk::{Chunk, ChunkMver, ChunkVecUtils, ChunkMotx, ChunkMogn, ChunkModn, ChunkMohd, ChunkMolr};
+use crate::common::{R, err, map_file};
+use crate::formats::chunk::{Chunk, ChunkRef, ChunkMver, ChunkVecUtils, ChunkMotx, ChunkMogn, ChunkModn, ChunkMohd, ChunkMolr};
 use serde::{Serialize, Deserialize};
 use std::collections::HashSet;
 use std::path::{Path, PathBuf};
-use crate::byte_utils::VecUtils;
+use crate::byte_utils::{BinRead, BinWrite};
+use crate::bin_layout;
+use crate::formats::mesh::{world_to_mesh_position, Mesh};
+use anyhow::Context;
 
 const ROOT_FILE_CHUNKS: &[&str] = &["MOMT", "MOGI", "MOSB", "MOVV", "MODN"];
-const GROUP_FILE_CHUNKS: &[&str] = &["MOGP", "MOPY", "MOVI", "MONR", "MOTV"];
+const GROUP_FILE_CHUNKS: &[&str] = &["MVER", "MOGP"];
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct WmoFile {
@@ -45,7 +48,7 @@ pub struct WmoRootFile {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct WmoGroupFile {
     pub mver: ChunkMver,
-    pub mogp: (),
+    pub mogp: ChunkMogp,
     pub mopy: (),
     pub movi: (),
     pub movt: (),
@@ -67,18 +70,32 @@ pub struct WmoGroupFile {
 }
 
 impl WmoFile {
+    /// The geometry of every group, see [WmoGroupFile::mesh].
+    pub fn mesh(&self) -> R<Mesh> {
+        let mut mesh = Mesh::default();
+        for (group, path) in self.groups.iter().zip(&self.loaded_group_files) {
+            mesh.append(&group.mesh().with_context(|| format!("Failed to read WMO group {:?}", path.display()))?);
+        }
+        Ok(mesh)
+    }
+
     pub fn from_path<P: AsRef<Path>>(path: P) -> R<WmoFile> {
         let path = path.as_ref().to_path_buf();
-        let chunks = Chunk::from_path(&path)?;
-        let variant = WmoFileVariant::new(chunks)?;
+        let mapped_file = map_file(&path)?;
+        let variant = ChunkRef::from_bytes(&mapped_file)
+            .and_then(|chunks| WmoFileVariant::new(&chunks))
+            .with_context(|| format!("Failed to parse WMO file {:?}", path.display()))?;
         match variant {
             WmoFileVariant::ROOT(root_file) => {
                 // ok we have a root file,
                 // load the dependent groups
-                let parent_dir = path.parent().unwrap();
-                let original_file_name = path.file_name().unwrap().to_str().unwrap();
+                let parent_dir = path.parent()
+                    .with_context(|| format!("WMO path {:?} has no parent directory", path.display()))?;
+                let original_file_name = path.file_name()
+                    .and_then(|name| name.to_str())
+                    .with_context(|| format!("WMO path {:?} has no valid file name", path.display()))?;
 
-                let (groups, loaded_group_files) = WmoFile::get_groups(parent_dir, original_file_name, &root_file);
+                let (groups, loaded_group_files) = WmoFile::get_groups(parent_dir, original_file_name, &root_file)?;
 
                 Ok(WmoFile {
                     root: root_file,
@@ -96,7 +113,7 @@ impl WmoFile {
         parent_path: &Path,
         original_file_name: &str,
         root_file: &WmoRootFile,
-    ) -> (Vec<WmoGroupFile>, Vec<PathBuf>) {
+    ) -> R<(Vec<WmoGroupFile>, Vec<PathBuf>)> {
         let group_names = WmoRootFile::get_group_names(root_file.mohd.n_groups, original_file_name);
         let mut loaded_group_paths = Vec::new();
 
@@ -106,28 +123,25 @@ impl WmoFile {
                 b.push(parent_path);
                 b.push(group_name);
 
-                let wmo_group_file = Self::load_group_wmo(b.to_str().unwrap())
-                    .expect(format!("Failed to load group WMO {:?}", b).as_str());
+                let wmo_group_file = Self::load_group_wmo(&b)
+                    .with_context(|| format!("Failed to load group WMO {:?}", b))?;
 
-                // let wmo_group_file = Self::load_group_wmo(b.to_str().unwrap());
-                // if wmo_group_file.is_ok() {
                 loaded_group_paths.push(b);
-                // }
-                wmo_group_file
+                Ok(wmo_group_file)
             })
-            // .filter_map(|g| g.ok())
-            .collect();
-        (groups, loaded_group_paths)
+            .collect::<R<Vec<WmoGroupFile>>>()?;
+        Ok((groups, loaded_group_paths))
     }
 
-    fn load_group_wmo(path: &str) -> R<WmoGroupFile> {
-        let chunks = Chunk::from_path(path)?;
-        WmoGroupFile::new(chunks)
+    fn load_group_wmo(path: &Path) -> R<WmoGroupFile> {
+        let mapped_file = map_file(path)?;
+        let chunks = ChunkRef::from_bytes(&mapped_file)?;
+        WmoGroupFile::new(&chunks)
     }
 }
 
 impl WmoFileVariant {
-    fn new(chunks: Vec<Chunk>) -> R<WmoFileVariant> {
+    pub(crate) fn new(chunks: &[ChunkRef]) -> R<WmoFileVariant> {
         fn matches_file_type(marker_chunks: &[&str], lookup: &HashSet<String>) -> bool {
             marker_chunks
                 .iter()
@@ -145,16 +159,18 @@ impl WmoFileVariant {
         } else if matches_file_type(GROUP_FILE_CHUNKS, &chunk_names_lookup) {
             Ok(WmoFileVariant::GROUP(WmoGroupFile::new(chunks)?))
         } else {
-            panic!("WmoFile#new: Cannot create a root or group WMO from given chunks!")
+            err("WmoFile#new: Cannot create a root or group WMO from given chunks!".to_string())
         }
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ChunkMogiItem {
-    pub flags: u32,
-    pub bounding_box: [f32; 6],
-    pub name_offset: i32,
+bin_layout! {
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct ChunkMogiItem {
+        pub flags: u32,
+        pub bounding_box: [f32; 6],
+        pub name_offset: i32,
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -177,13 +193,22 @@ impl WmoRootFile {
             .collect()
     }
 
-    fn new(chunks: Vec<Chunk>) -> R<WmoRootFile> {
+    /// Parses a root file on its own, without loading its groups like [WmoFile::from_path] does.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> R<WmoRootFile> {
+        let path = path.as_ref();
+        let mapped_file = map_file(path)?;
+        ChunkRef::from_bytes(&mapped_file)
+            .and_then(|chunks| WmoRootFile::new(&chunks))
+            .with_context(|| format!("Failed to parse WMO root file {:?}", path.display()))
+    }
+
+    fn new(chunks: &[ChunkRef]) -> R<WmoRootFile> {
         let mver = chunks.get_mver_chunk()?;
-        let motx = chunks.get_motx();
-        let mohd = chunks.get_mohd();
-        let mogn = chunks.get_mogn();
-        let modn = chunks.get_modn();
-        let mogi = chunks.get_mogi();
+        let motx = chunks.get_motx()?;
+        let mohd = chunks.get_mohd()?;
+        let mogn = chunks.get_mogn()?;
+        let modn = chunks.get_modn()?;
+        let mogi = chunks.get_mogi()?;
 
         Ok(WmoRootFile {
             mver,
@@ -208,12 +233,15 @@ impl WmoRootFile {
 }
 
 impl WmoGroupFile {
-    fn new(chunks: Vec<Chunk>) -> R<WmoGroupFile> {
+    fn new(chunks: &[ChunkRef]) -> R<WmoGroupFile> {
         let mver = chunks.get_mver_chunk()?;
-        let molr = chunks.get_molr();
+        let mogp_chunk = chunks.get_chunk_of_type("MOGP")?;
+        let group_chunks = mogp_chunk.nested(ChunkMogpHeader::SIZE)?;
+        let molr = group_chunks.get_molr()?;
+        let mogp = ChunkMogp::from_chunk(mogp_chunk, &group_chunks)?;
         Ok(WmoGroupFile {
             mver,
-            mogp: (),
+            mogp,
             mopy: (),
             movi: (),
             movt: (),
@@ -234,39 +262,111 @@ impl WmoGroupFile {
             morb: None,
         })
     }
+
+    pub fn to_chunks(&self) -> Vec<Chunk> {
+        vec![self.mver.to_chunk(), self.mogp.to_chunk()]
+    }
+
+    /// The rendered triangles of the group from MOVT/MONR/MOTV/MOVI, in the space of the root WMO.
+    /// Collision only triangles (MOPY material 0xFF) are left out. Vertices are converted like
+    /// world coordinates, see [world_to_mesh_position].
+    pub fn mesh(&self) -> R<Mesh> {
+        let get_chunk = |chunk_type: &str| {
+            self.mogp.chunks.iter().find(|it| it.get_id_as_string() == chunk_type).map(Chunk::as_chunk_ref)
+        };
+        let movt = get_chunk("MOVT").context("WMO group has no MOVT chunk")?;
+        let movi = get_chunk("MOVI").context("WMO group has no MOVI chunk")?;
+        movt.expect_size_multiple_of(<[f32; 3]>::SIZE)?;
+        movi.expect_size_multiple_of(<[u16; 3]>::SIZE)?;
+
+        let mut mesh = Mesh {
+            positions: <[f32; 3]>::read_all(movt.data)?.into_iter().map(world_to_mesh_position).collect(),
+            ..Default::default()
+        };
+        if let Some(monr) = get_chunk("MONR") {
+            monr.expect_size_multiple_of(<[f32; 3]>::SIZE)?;
+            mesh.normals = <[f32; 3]>::read_all(monr.data)?.into_iter().map(world_to_mesh_position).collect();
+        }
+        if let Some(motv) = get_chunk("MOTV") {
+            motv.expect_size_multiple_of(<[f32; 2]>::SIZE)?;
+            mesh.uvs = <[f32; 2]>::read_all(motv.data)?;
+        }
+        // one (flags, material id) pair per triangle.
+        let materials = get_chunk("MOPY").map_or(&[][..], |it| it.data);
+        for (i, triangle) in <[u16; 3]>::read_all(movi.data)?.into_iter().enumerate() {
+            if materials.get(i * 2 + 1) == Some(&0xFF) {
+                continue;
+            }
+            if triangle.iter().any(|it| *it as usize >= mesh.positions.len()) {
+                return err(format!("WMO group triangle {} uses a missing vertex", i));
+            }
+            mesh.indices.extend(triangle.iter().map(|it| *it as u32));
+        }
+        Ok(mesh)
+    }
 }
 
 trait WmoChunkExt {
-    fn get_mogi(&self) -> ChunkMogi;
+    fn get_mogi(&self) -> R<ChunkMogi>;
 }
 
-impl WmoChunkExt for Vec<Chunk> {
-    fn get_mogi(&self) -> ChunkMogi { ChunkMogi::from_chunk(self.get_chunk_of_type("MOGI")) }
+impl WmoChunkExt for [ChunkRef<'_>] {
+    fn get_mogi(&self) -> R<ChunkMogi> { ChunkMogi::from_chunk(self.get_chunk_of_type("MOGI")?) }
 }
 
-impl ChunkMogi {
-    fn from_chunk(chunk: &Chunk) -> ChunkMogi {
-        let mapped = chunk.data.chunks(32)
-            .map(|segment| {
-                let segment = segment.to_vec();
-                let flags = segment.get_u32(0).unwrap();
-                let b1 = segment.get_f32(4).unwrap();
-                let b2 = segment.get_f32(8).unwrap();
-                let b3 = segment.get_f32(12).unwrap();
-                let b4 = segment.get_f32(16).unwrap();
-                let b5 = segment.get_f32(20).unwrap();
-                let b6 = segment.get_f32(24).unwrap();
-                let bounding_box = [b1, b2, b3, b4, b5, b6];
-                let name_offset = segment.get_i32(28).unwrap();
-                ChunkMogiItem {
-                    flags,
-                    bounding_box,
-                    name_offset,
-                }
-            })
-            .collect();
+bin_layout! {
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct ChunkMogpHeader {
+        pub group_name: u32,
+        pub descriptive_group_name: u32,
+        pub flags: u32,
+        pub bounding_box: [f32; 6],
+        pub portal_start: u16,
+        pub portal_count: u16,
+        pub trans_batch_count: u16,
+        pub int_batch_count: u16,
+        pub ext_batch_count: u16,
+        pub padding: u16,
+        pub fog_ids: [u8; 4],
+        pub group_liquid: u32,
+        pub group_id: u32,
+        pub flags_2: u32,
+        pub unused: u32,
+    }
+}
 
-        ChunkMogi(mapped)
+/// MOGP is a container: a fixed header followed by the group's sub-chunks (MOPY, MOVI, MOVT, ...).
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChunkMogp {
+    pub header: ChunkMogpHeader,
+    #[serde(skip)]
+    pub chunks: Vec<Chunk>,
+}
+
+impl ChunkMogp {
+    fn from_chunk(chunk: &ChunkRef, group_chunks: &[ChunkRef]) -> R<ChunkMogp> {
+        chunk.expect_type("MOGP")?;
+        chunk.expect_size_at_least(ChunkMogpHeader::SIZE)?;
+        Ok(ChunkMogp {
+            header: ChunkMogpHeader::from_bytes(chunk.data)?,
+            chunks: group_chunks.iter().map(ChunkRef::to_owned_chunk).collect(),
+        })
+    }
+
+    pub fn to_chunk(&self) -> Chunk {
+        let mut data = self.header.to_bytes();
+        for chunk in &self.chunks {
+            data.append(&mut chunk.to_bytes());
+        }
+        Chunk::new("MOGP", data)
+    }
+}
+
+impl ChunkMogi {
+    fn from_chunk(chunk: &ChunkRef) -> R<ChunkMogi> {
+        chunk.expect_type("MOGI")?;
+        chunk.expect_size_multiple_of(ChunkMogiItem::SIZE)?;
+        Ok(ChunkMogi(ChunkMogiItem::read_all(chunk.data)?))
     }
 }
 
@@ -297,3 +397,48 @@ fn wmo_root_get_group_names() {
         "test00_004.wmo",
     ]);
 }
+
+#[cfg(test)]
+#[test]
+fn wmo_group_round_trip() {
+    use crate::formats::chunk::chunks_to_bytes;
+
+    let mut mogp_data = vec![0u8; ChunkMogpHeader::SIZE];
+    mogp_data[0x38] = 42;
+    mogp_data.append(&mut Chunk::new("MOPY", vec![1, 2]).to_bytes());
+    mogp_data.append(&mut Chunk::new("MOLR", vec![3, 0, 4, 0]).to_bytes());
+    let file = chunks_to_bytes(&[
+        Chunk::new("MVER", 17u32.to_le_bytes().to_vec()),
+        Chunk::new("MOGP", mogp_data),
+    ]);
+
+    let chunks = ChunkRef::from_bytes(&file).unwrap();
+    let group = WmoGroupFile::new(&chunks).unwrap();
+    assert_eq!(group.mogp.header.group_id, 42);
+    assert_eq!(group.mogp.chunks.len(), 2);
+    assert_eq!(group.molr.as_ref().unwrap().0, vec![3, 4]);
+    assert_eq!(chunks_to_bytes(&group.to_chunks()), file);
+}
+
+#[cfg(test)]
+#[test]
+fn wmo_group_mesh() {
+    let floats = |values: &[f32]| values.iter().flat_map(|it| it.to_le_bytes()).collect::<Vec<u8>>();
+    let mut mogp_data = vec![0u8; ChunkMogpHeader::SIZE];
+    mogp_data.append(&mut Chunk::new("MOPY", vec![0, 1, 0, 0xFF]).to_bytes());
+    mogp_data.append(&mut Chunk::new("MOVI", [0u16, 1, 2, 2, 1, 0].iter().flat_map(|it| it.to_le_bytes()).collect()).to_bytes());
+    mogp_data.append(&mut Chunk::new("MOVT", floats(&[1.0, 2.0, 3.0, 0.0, 0.0, 0.0, 4.0, 5.0, 6.0])).to_bytes());
+    mogp_data.append(&mut Chunk::new("MOTV", floats(&[0.0, 0.0, 1.0, 0.0, 1.0, 1.0])).to_bytes());
+    let file = crate::formats::chunk::chunks_to_bytes(&[
+        Chunk::new("MVER", 17u32.to_le_bytes().to_vec()),
+        Chunk::new("MOGP", mogp_data),
+    ]);
+
+    let group = WmoGroupFile::new(&ChunkRef::from_bytes(&file).unwrap()).unwrap();
+    let mesh = group.mesh().unwrap();
+    assert_eq!(mesh.positions[0], [-2.0, 3.0, -1.0]);
+    assert_eq!(mesh.uvs.len(), 3);
+    assert!(mesh.normals.is_empty());
+    // the second triangle is collision only.
+    assert_eq!(mesh.indices, vec![0, 1, 2]);
+}
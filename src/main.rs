@@ -6,12 +6,18 @@ pub mod common;
 pub mod formats;
 pub mod mpq;
 
+use crate::command_handler::adt::handle_adt_command;
+use crate::command_handler::chunks::handle_chunks_command;
 use crate::command_handler::dbc_join::handle_dbc_join;
+use crate::command_handler::map::handle_map_command;
 use crate::command_handler::mpq::handle_mpq_command;
 use crate::command_handler::resolve_map_assets::handle_resolve_map_assets;
+use crate::command_handler::scene::handle_scene_command;
 use crate::command_handler::view::handle_view_command;
 
 use crate::common::R;
+use crate::formats::adt::{ChunkRange, TileCoords};
+use crate::formats::alpha_map::AlphaMapFormat;
 
 use clap::Clap;
 use serde::ser::SerializeStruct;
@@ -34,9 +40,13 @@ fn main() {
 fn handle_cmd(root_cmd: RootCmd) -> R<()> {
     let mut result: Box<dyn erased_serde::Serialize> = match &root_cmd.cmd {
         Cmd::View(v) => handle_view_command(v)?,
+        Cmd::Chunks(cmd) => handle_chunks_command(cmd)?,
         Cmd::ResolveMapAssets(cmd) => handle_resolve_map_assets(cmd)?,
         Cmd::DbcJoin(cmd) => handle_dbc_join(cmd)?,
         Cmd::Mpq { cmd } => handle_mpq_command(cmd)?,
+        Cmd::Adt { cmd } => handle_adt_command(cmd)?,
+        Cmd::Map { cmd } => handle_map_command(cmd)?,
+        Cmd::Scene { cmd } => handle_scene_command(cmd)?,
     };
 
     if root_cmd.no_result {
@@ -75,12 +85,25 @@ pub struct RootCmd {
 #[derive(Clap)]
 pub enum Cmd {
     View(ViewCmd),
+    Chunks(ChunksCmd),
     ResolveMapAssets(ResolveMapAssetsCmd),
     DbcJoin(DbcJoinCmd),
     Mpq {
         #[clap(subcommand)]
         cmd: MpqToolCmd,
     },
+    Adt {
+        #[clap(subcommand)]
+        cmd: AdtToolCmd,
+    },
+    Map {
+        #[clap(subcommand)]
+        cmd: MapToolCmd,
+    },
+    Scene {
+        #[clap(subcommand)]
+        cmd: SceneToolCmd,
+    },
 }
 
 #[derive(Clap)]
@@ -149,6 +172,404 @@ pub struct MpqToolCmdExtractTree {
     pub dest: String,
 }
 
+#[derive(Clap)]
+#[clap(about = "A set of ADT related tools")]
+pub enum AdtToolCmd {
+    ExportHeightmap(AdtToolCmdExportHeightmap),
+    ImportHeightmap(AdtToolCmdImportHeightmap),
+    ExportAlpha(AdtToolCmdExportAlpha),
+    ImportAlpha(AdtToolCmdImportAlpha),
+    Placements(AdtToolCmdPlacements),
+    ApplyPlacements(AdtToolCmdApplyPlacements),
+    ExportMesh(AdtToolCmdExportMesh),
+    SetWater(AdtToolCmdSetWater),
+    SetArea(AdtToolCmdSetArea),
+    Relocate(AdtToolCmdRelocate),
+    Merge(AdtToolCmdMerge),
+    Validate(AdtToolCmdValidate),
+    ConvertSplit(AdtToolCmdConvertSplit),
+}
+
+#[derive(Clap)]
+#[clap(
+    about = "Export terrain heights as a 16 bit .png/.pgm or a f32 .raw grid, min/max heights are written to <output>.json"
+)]
+pub struct AdtToolCmdExportHeightmap {
+    #[clap(
+        short = 'f',
+        long = "file",
+        about = "An ADT file, or a WDT file to export every tile of the map"
+    )]
+    file: String,
+
+    #[clap(short = 'o', long = "output")]
+    output: String,
+}
+
+#[derive(Clap)]
+#[clap(
+    about = "Rewrite MCVT heights and MCNR normals of ADTs from a .png/.pgm/.raw heightmap, tiles sharing the grid get seamless edges"
+)]
+pub struct AdtToolCmdImportHeightmap {
+    #[clap(short = 'i', long = "input")]
+    input: String,
+
+    #[clap(
+        short = 'f',
+        long = "file",
+        about = "ADT files to rewrite, placed in the grid by their <Map>_<x>_<y>.adt name"
+    )]
+    files: Vec<String>,
+
+    #[clap(
+        short = 'b',
+        long = "base-height",
+        about = "Height of a 0 sample, defaults to min_height of <input>.json"
+    )]
+    base_height: Option<f32>,
+
+    #[clap(
+        short = 's',
+        long = "height-scale",
+        about = "Height difference between a 0 and a full scale sample, defaults to max_height - min_height of <input>.json"
+    )]
+    height_scale: Option<f32>,
+
+    #[clap(
+        short = 'o',
+        long = "output-dir",
+        about = "Write the ADTs to this folder instead of overwriting them"
+    )]
+    output_dir: Option<String>,
+}
+
+#[derive(Clap)]
+#[clap(
+    about = "Export one 1024x1024 grayscale .png per texture per tile, holding the weight of that texture"
+)]
+pub struct AdtToolCmdExportAlpha {
+    #[clap(
+        short = 'f',
+        long = "file",
+        about = "An ADT file, or a WDT file to export every tile of the map"
+    )]
+    file: String,
+
+    #[clap(short = 'o', long = "output-dir")]
+    output_dir: String,
+}
+
+#[derive(Clap)]
+#[clap(
+    about = "Paint the texture layers of an ADT from 1024x1024 grayscale masks, keeping the 4 most used textures per MCNK"
+)]
+pub struct AdtToolCmdImportAlpha {
+    #[clap(short = 'f', long = "file")]
+    file: String,
+
+    #[clap(
+        short = 'm',
+        long = "mask",
+        about = "<texture>=<png>, e.g. Tileset\\Elwynn\\ElwynnGrassBase.blp=grass.png"
+    )]
+    masks: Vec<String>,
+
+    #[clap(
+        short = 'a',
+        long = "alpha-format",
+        about = "one of: 4bit, 8bit, compressed. Defaults to the format the tile uses, 8bit and compressed need big alpha in the WDT"
+    )]
+    alpha_format: Option<AlphaMapFormat>,
+
+    #[clap(
+        short = 'w',
+        long = "wdt",
+        about = "The WDT of the map, the alpha format must match its big alpha flag"
+    )]
+    wdt: Option<String>,
+
+    #[clap(
+        short = 'o',
+        long = "output",
+        about = "Write the ADT to this file instead of overwriting it"
+    )]
+    output: Option<String>,
+}
+
+#[derive(Clap)]
+#[clap(
+    about = "List MDDF/MODF placements with their model path, server coordinates, rotation in degrees and scale"
+)]
+pub struct AdtToolCmdPlacements {
+    #[clap(short = 'f', long = "file")]
+    file: String,
+
+    #[clap(
+        short = 'o',
+        long = "output",
+        about = "Also write the placements to this .json or .csv file"
+    )]
+    output: Option<String>,
+}
+
+#[derive(Clap)]
+#[clap(
+    about = "Replace the MDDF/MODF placements of an ADT with an edited `adt placements` .json or .csv list"
+)]
+pub struct AdtToolCmdApplyPlacements {
+    #[clap(short = 'f', long = "file")]
+    file: String,
+
+    #[clap(short = 'i', long = "input")]
+    input: String,
+
+    #[clap(
+        short = 'w',
+        long = "workspace",
+        about = "Look up moved and new WMOs here to compute their extents"
+    )]
+    workspace: Option<String>,
+
+    #[clap(
+        short = 'u',
+        long = "first-unique-id",
+        about = "Lowest unique id to give new entries, defaults to one above the highest one in use"
+    )]
+    first_unique_id: Option<u32>,
+
+    #[clap(
+        short = 'o',
+        long = "output",
+        about = "Write the ADT to this file instead of overwriting it"
+    )]
+    output: Option<String>,
+}
+
+#[derive(Clap)]
+#[clap(about = "Export the terrain of an ADT, or of every tile of a WDT, as a .obj or .glb mesh")]
+pub struct AdtToolCmdExportMesh {
+    #[clap(short = 'f', long = "file", about = "ADT or WDT file")]
+    file: String,
+
+    #[clap(short = 'o', long = "output", about = ".obj or .glb file")]
+    output: String,
+
+    #[clap(long = "min-tile", about = "Only export tiles from this <x>_<y> on, when given a WDT")]
+    min_tile: Option<TileCoords>,
+
+    #[clap(long = "max-tile", about = "Only export tiles up to this <x>_<y>, when given a WDT")]
+    max_tile: Option<TileCoords>,
+
+    #[clap(long = "water", about = "Also export the MH2O water surface of every tile")]
+    water: bool,
+}
+
+#[derive(Clap)]
+#[clap(about = "Set or clear the MH2O water of a rectangle of MCNKs, or of the tiles painted in a mask")]
+pub struct AdtToolCmdSetWater {
+    #[clap(short = 'f', long = "file")]
+    file: String,
+
+    #[clap(short = 'c', long = "chunks", about = "MCNKs to change, <x>_<y> or <x1>_<y1>:<x2>_<y2>")]
+    chunks: Option<ChunkRange>,
+
+    #[clap(
+        short = 'm',
+        long = "mask",
+        about = "128x128 grayscale png, one pixel per liquid tile, white tiles are changed"
+    )]
+    mask: Option<String>,
+
+    #[clap(
+        short = 't',
+        long = "liquid-type",
+        default_value = "1",
+        about = "LiquidType.dbc id, 1 water, 2 ocean, 3 magma, 4 slime"
+    )]
+    liquid_type: u16,
+
+    #[clap(long = "height", about = "Height of the liquid surface")]
+    height: Option<f32>,
+
+    #[clap(long = "clear", about = "Remove the water instead")]
+    clear: bool,
+
+    #[clap(
+        short = 'o',
+        long = "output",
+        about = "Write the ADT to this file instead of overwriting it"
+    )]
+    output: Option<String>,
+}
+
+#[derive(Clap)]
+#[clap(about = "Set the area id of MCNKs, for whole tiles, a rectangle of MCNKs or from a color mask")]
+pub struct AdtToolCmdSetArea {
+    #[clap(short = 'f', long = "file", about = "ADT or WDT file")]
+    file: String,
+
+    #[clap(short = 'd', long = "area-table", about = "AreaTable.dbc the area ids are checked against")]
+    area_table: String,
+
+    #[clap(short = 'a', long = "area-id")]
+    area_id: Option<u32>,
+
+    #[clap(
+        short = 'c',
+        long = "chunks",
+        about = "Only set --area-id on these MCNKs, <x>_<y> or <x1>_<y1>:<x2>_<y2>"
+    )]
+    chunks: Option<ChunkRange>,
+
+    #[clap(
+        short = 'm',
+        long = "mask",
+        about = "png with one pixel per MCNK, 16x16 for an ADT or 1024x1024 for a WDT"
+    )]
+    mask: Option<String>,
+
+    #[clap(long = "color", about = "Area id of a mask color, <rrggbb>=<area id>")]
+    colors: Vec<String>,
+
+    #[clap(long = "min-tile", about = "Only change tiles from this <x>_<y> on, when given a WDT")]
+    min_tile: Option<TileCoords>,
+
+    #[clap(long = "max-tile", about = "Only change tiles up to this <x>_<y>, when given a WDT")]
+    max_tile: Option<TileCoords>,
+
+    #[clap(
+        short = 'o',
+        long = "output",
+        about = "Write the ADT to this file instead of overwriting it"
+    )]
+    output: Option<String>,
+}
+
+#[derive(Clap)]
+#[clap(about = "Copy a tile to another grid slot of its map, shifting every position inside and flagging it in the WDT")]
+pub struct AdtToolCmdRelocate {
+    #[clap(short = 'f', long = "file", about = "The WDT of the map, the ADTs sit next to it")]
+    file: String,
+
+    #[clap(long = "from", about = "<x>_<y> of the tile to copy")]
+    from: TileCoords,
+
+    #[clap(long = "to", about = "<x>_<y> of the new tile, must not be flagged in the WDT yet")]
+    to: TileCoords,
+
+    #[clap(long = "move", about = "Remove the source ADT and clear its flag in the WDT")]
+    move_tile: bool,
+}
+
+#[derive(Clap)]
+#[clap(about = "Write the terrain of one copy of a tile along with the doodads and WMOs of another copy")]
+pub struct AdtToolCmdMerge {
+    #[clap(short = 't', long = "terrain", about = "ADT the heights, textures and liquids are taken from")]
+    terrain: String,
+
+    #[clap(short = 'b', long = "objects", about = "ADT the MDDF/MODF placements and their models are taken from")]
+    objects: String,
+
+    #[clap(
+        short = 'u',
+        long = "first-unique-id",
+        about = "Lowest unique id to give renumbered entries, defaults to one above the highest one in the tile"
+    )]
+    first_unique_id: Option<u32>,
+
+    #[clap(short = 'o', long = "out")]
+    output: String,
+}
+
+#[derive(Clap)]
+#[clap(about = "Check the chunk offsets, MCNK layout and object references of ADTs, reporting findings as JSON")]
+pub struct AdtToolCmdValidate {
+    #[clap(short = 'f', long = "file", about = "An ADT file, or a WDT file to check every tile of the map")]
+    file: String,
+}
+
+#[derive(Clap)]
+#[clap(about = "Convert a Cataclysm style split tile (<Map>_<x>_<y>.adt, _tex0.adt, _obj0.adt) to a single 3.3.5 ADT")]
+pub struct AdtToolCmdConvertSplit {
+    #[clap(
+        short = 'f',
+        long = "file",
+        about = "The root ADT of the tile, or a WDT file to convert every tile of the map"
+    )]
+    file: String,
+
+    #[clap(short = 'o', long = "output", about = "ADT to write, or the folder to write the tiles to when given a WDT")]
+    output: String,
+}
+
+#[derive(Clap)]
+#[clap(about = "A set of map wide tools, working on every ADT of a map")]
+pub enum MapToolCmd {
+    CheckUniqueIds(MapToolCmdCheckUniqueIds),
+    CreateWdt(MapToolCmdCreateWdt),
+}
+
+#[derive(Clap)]
+#[clap(about = "Report MDDF/MODF unique ids shared by different objects across the ADTs of a map")]
+pub struct MapToolCmdCheckUniqueIds {
+    #[clap(short = 'w', long = "workspace")]
+    workspace: String,
+
+    #[clap(short = 'm', long = "map-id")]
+    map_id: u32,
+
+    #[clap(
+        short = 'r',
+        long = "renumber",
+        about = "Rewrite the ADTs with a fresh, stable unique id sequence"
+    )]
+    renumber: bool,
+}
+
+#[derive(Clap)]
+#[clap(about = "Write the WDT of a map, flagging exactly the tiles of the <Map>_<x>_<y>.adt files in its World/Maps folder")]
+pub struct MapToolCmdCreateWdt {
+    #[clap(short = 'w', long = "workspace")]
+    workspace: String,
+
+    #[clap(short = 'm', long = "map-id")]
+    map_id: u32,
+
+    #[clap(long = "big-alpha", about = "Set the MPHD flag for 8 bit alpha maps")]
+    big_alpha: bool,
+
+    #[clap(long = "vertex-shading", about = "Set the MPHD flag for MCCV vertex colors")]
+    vertex_shading: bool,
+
+    #[clap(long = "mphd-flags", about = "Raw MPHD flags, combined with the ones above")]
+    mphd_flags: Option<u32>,
+
+    #[clap(long = "force", about = "Write a new WDT even if one exists, instead of keeping its MPHD flags and WMO")]
+    force: bool,
+
+    #[clap(short = 'o', long = "output", about = "Write the WDT here instead of World/Maps/<Map>/<Map>.wdt")]
+    output: Option<String>,
+}
+
+#[derive(Clap)]
+#[clap(about = "Export ADT tiles together with the models they place")]
+pub enum SceneToolCmd {
+    Export(SceneToolCmdExport),
+}
+
+#[derive(Clap)]
+#[clap(about = "Export the terrain and every M2 and WMO placed by an ADT to a single .glb file")]
+pub struct SceneToolCmdExport {
+    #[clap(short = 'f', long = "file")]
+    file: String,
+
+    #[clap(short = 'w', long = "workspace", about = "Folder the M2 and WMO files are looked up in")]
+    workspace: String,
+
+    #[clap(short = 'o', long = "output", about = ".glb file")]
+    output: String,
+}
+
 #[derive(Clap)]
 #[clap(about = "View given file as JSON")]
 pub struct ViewCmd {
@@ -156,6 +577,20 @@ pub struct ViewCmd {
     file: String,
 }
 
+#[derive(Clap)]
+#[clap(about = "List the raw chunks of any chunked file (ADT, WDT, WDL, WMO)")]
+pub struct ChunksCmd {
+    #[clap(short = 'f', long = "file")]
+    file: String,
+
+    #[clap(
+        short = 'x',
+        long = "hex-preview",
+        about = "Include the first N bytes of each chunk as hex"
+    )]
+    hex_preview: Option<usize>,
+}
+
 #[derive(Clap)]
 #[clap(about = "Resolve all map dependencies")]
 pub struct ResolveMapAssetsCmd {